@@ -0,0 +1,41 @@
+// Setup shared by the benchmarks
+
+use nes::Nes;
+
+const WARMUP_FRAMES: usize = 10;
+
+// LDA #$1E; STA $2001, so the PPU fetches as it would in a game
+pub const RENDERING_ON: [u8; 5] = [0xA9, 0x1E, 0x8D, 0x01, 0x20];
+
+// A tight arithmetic loop at $8000, after some setup code
+fn build_rom(setup: &[u8]) -> Vec<u8> {
+    let mut rom = vec![
+        0x4e, 0x45, 0x53, 0x1a, // NES<EOF>
+        0x02, // Two pages of PRG-ROM
+        0x00, // Zero pages CHR-ROM means use CHR-RAM
+        0x01, // Vertical mirroring
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    let mut prg = setup.to_vec();
+    let start = 0x8000 + prg.len() as u16;
+    // INX; INY; DEX; ADC #$01; STA $00; JMP start
+    prg.extend_from_slice(&[0xE8, 0xC8, 0xCA, 0x69, 0x01, 0x85, 0x00, 0x4C]);
+    prg.extend_from_slice(&[start as u8, (start >> 8) as u8]);
+    prg.resize(2 * 0x4000, 0);
+    // NMI, reset and IRQ vectors all point at the program
+    prg[0x7FFA..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    rom.extend_from_slice(&prg);
+    rom
+}
+
+// A console that has run the setup code and a few frames of the loop
+pub fn build_nes(setup: &[u8]) -> Nes {
+    let mut nes = Nes::new();
+    nes.load_rom(&build_rom(setup));
+    for _ in 0..WARMUP_FRAMES {
+        nes.run_frame();
+        nes.cpu.bus.apu.buffer.clear();
+    }
+    nes
+}
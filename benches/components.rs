@@ -0,0 +1,83 @@
+// Benchmarks for the parts of the console that run every cycle, on their
+// own. Run them with:
+//
+//     cargo bench --bench components
+
+#[macro_use]
+extern crate criterion;
+extern crate nes;
+
+mod common;
+
+use common::{build_nes, RENDERING_ON};
+use criterion::{Criterion, Throughput};
+
+const TICKS: u64 = 10_000;
+
+// Every channel but the DMC playing at full volume
+#[rustfmt::skip]
+const AUDIO_ON: [u8; 45] = [
+    0xA9, 0x0F, 0x8D, 0x15, 0x40, // LDA #$0F; STA $4015
+    0xA9, 0xBF, 0x8D, 0x00, 0x40, // Pulse 1: constant volume 15
+    0xA9, 0x80, 0x8D, 0x02, 0x40,
+    0xA9, 0x08, 0x8D, 0x03, 0x40,
+    0xA9, 0xFF, 0x8D, 0x08, 0x40, // Triangle: halted linear counter
+    0xA9, 0x40, 0x8D, 0x0A, 0x40,
+    0xA9, 0x08, 0x8D, 0x0B, 0x40,
+    0xA9, 0x3F, 0x8D, 0x0C, 0x40, // Noise: constant volume 15
+    0xA9, 0x08, 0x8D, 0x0F, 0x40,
+];
+
+fn bench_components(c: &mut Criterion) {
+    let mut group = c.benchmark_group("components");
+    group.throughput(Throughput::Elements(TICKS));
+
+    // One CPU cycle: the APU, the mapper and three PPU dots
+    let mut nes = build_nes(&RENDERING_ON);
+    group.bench_function("bus_tick", |b| {
+        b.iter(|| {
+            for _ in 0..TICKS {
+                nes.cpu.bus.tick();
+            }
+            nes.cpu.bus.apu.buffer.clear();
+        })
+    });
+
+    // Just the renderer, one dot at a time
+    let mut nes = build_nes(&RENDERING_ON);
+    group.bench_function("ppu_tick", |b| {
+        b.iter(|| {
+            for _ in 0..TICKS {
+                nes.cpu.bus.ppu.tick();
+            }
+        })
+    });
+
+    // Just the APU, including taking samples every so often
+    let mut nes = build_nes(&[]);
+    let mut cycles = 0;
+    group.bench_function("apu_tick", |b| {
+        b.iter(|| {
+            for _ in 0..TICKS {
+                cycles += 1;
+                nes.cpu.bus.apu.tick(cycles);
+            }
+            nes.cpu.bus.apu.buffer.clear();
+        })
+    });
+
+    // Just mixing and filtering, which tick only does once a sample
+    let mut nes = build_nes(&AUDIO_ON);
+    group.bench_function("apu_sample", |b| {
+        b.iter(|| {
+            for _ in 0..TICKS {
+                criterion::black_box(nes.cpu.bus.apu.sample());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_components);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+// Benchmarks for the hot emulation paths, each running a whole console on a
+// tight loop ROM. Run them with:
+//
+//     cargo bench --bench emulation
+
+#[macro_use]
+extern crate criterion;
+extern crate nes;
+
+mod common;
+
+use common::{build_nes, RENDERING_ON};
+use criterion::{Criterion, Throughput};
+
+const INSTRUCTIONS: u64 = 10_000;
+
+fn bench_cpu_instructions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    let mut nes = build_nes(&[]);
+    group.bench_function("instructions", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                nes.cpu.execute_next_instruction();
+            }
+            nes.cpu.bus.apu.buffer.clear();
+        })
+    });
+    group.finish();
+}
+
+fn bench_frame_render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ppu");
+    group.throughput(Throughput::Elements(1));
+    let mut nes = build_nes(&RENDERING_ON);
+    group.bench_function("frame_render", |b| {
+        b.iter(|| {
+            nes.run_frame();
+            nes.cpu.bus.apu.buffer.clear();
+        })
+    });
+    group.finish();
+}
+
+fn bench_apu_samples(c: &mut Criterion) {
+    let mut nes = build_nes(&[
+        0xA9, 0x0F, 0x8D, 0x15, 0x40, // Enable pulse 1, pulse 2, triangle and noise
+        0xA9, 0xBF, 0x8D, 0x00, 0x40, // Pulse 1 at constant full volume
+        0xA9, 0xFD, 0x8D, 0x02, 0x40, // Pulse 1 timer low
+        0xA9, 0x00, 0x8D, 0x03, 0x40, // Pulse 1 timer high and length
+        0xA9, 0xFF, 0x8D, 0x08, 0x40, // Triangle linear counter
+        0xA9, 0x40, 0x8D, 0x0A, 0x40, // Triangle timer low
+        0xA9, 0x00, 0x8D, 0x0B, 0x40, // Triangle timer high and length
+        0xA9, 0x3F, 0x8D, 0x0C, 0x40, // Noise at constant full volume
+        0xA9, 0x04, 0x8D, 0x0E, 0x40, // Noise period
+        0xA9, 0x00, 0x8D, 0x0F, 0x40, // Noise length
+    ]);
+
+    // A frame's worth of samples, which is what each iteration produces
+    nes.run_frame();
+    let samples = nes.cpu.bus.apu.buffer.len() as u64;
+    nes.cpu.bus.apu.buffer.clear();
+
+    let mut group = c.benchmark_group("apu");
+    group.throughput(Throughput::Elements(samples));
+    group.bench_function("samples", |b| {
+        b.iter(|| {
+            nes.run_frame();
+            nes.cpu.bus.apu.buffer.clear();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_cpu_instructions,
+    bench_frame_render,
+    bench_apu_samples
+);
+criterion_main!(benches);
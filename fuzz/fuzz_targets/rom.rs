@@ -0,0 +1,24 @@
+// Loads arbitrary bytes as a ROM and runs a few frames of it. The first 8
+// bytes are the seed for Nes::from_fuzz_seed and the rest are the iNES
+// file, so a crashing input found by the fuzzer reproduces on its own.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nes::Nes;
+
+const FRAMES: usize = 3;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+    let mut seed = [0; 8];
+    seed.copy_from_slice(&data[..8]);
+
+    // Bad ROMs should be rejected with an error rather than a panic
+    if let Ok(mut nes) = Nes::from_fuzz_seed(u64::from_le_bytes(seed), &data[8..]) {
+        for _ in 0..FRAMES {
+            nes.run_frame();
+        }
+    }
+});
@@ -7,6 +7,7 @@ pub const PERIODS: [u8; 16] = [
     214, 190, 170, 160, 143, 127, 113, 107, 95, 80, 71, 64, 53, 42, 36, 27
 ];
 
+#[derive(Clone)]
 pub struct DmcChannel {
     cartridge: Option<Rc<RefCell<Cartridge>>>,
     pub irq_enabled: bool,
@@ -22,7 +23,8 @@ pub struct DmcChannel {
     period: u8,
     counter: u8,
     looping: bool,
-    cpu_stall_cycles: u8,
+    dma_halt: bool,
+    dma_stall: bool,
 }
 
 impl DmcChannel {
@@ -39,23 +41,42 @@ impl DmcChannel {
             current_length: 0,
             shift_register: 0,
             bit_count: 0,
-            period: 0,
+            period: PERIODS[0],
             counter: 0,
             looping: false,
-            cpu_stall_cycles: 0,
+            dma_halt: false,
+            dma_stall: true,
         }
     }
 
-    pub fn reset_cpu_stall_cycles(&mut self) -> u8 {
-        let c = self.cpu_stall_cycles;
-        self.cpu_stall_cycles = 0;
-        c
+    // The DMA that fetches a sample byte halts the CPU on its next read cycle.
+    // Returns true once for each fetch so the bus can repeat that read.
+    pub fn reset_dma_halt(&mut self) -> bool {
+        let h = self.dma_halt;
+        self.dma_halt = false;
+        h
+    }
+
+    // A debugging aid. Without the stall, samples still play but the CPU
+    // never waits for their fetches, which shows whether a glitch comes from
+    // the DMA timing.
+    pub fn set_dma_stall_enabled(&mut self, enabled: bool) {
+        self.dma_stall = enabled;
+    }
+
+    pub fn dma_pending(&self) -> bool {
+        self.dma_halt
     }
 
     pub fn sample(&self) -> u8 {
         self.output
     }
 
+    // The DAC holds this level until a playing sample moves it
+    pub fn set_output_level(&mut self, value: u8) {
+        self.output = value & 0b0111_1111;
+    }
+
     pub fn write_register(&mut self, address: u16, value: u8) {
         match address {
             0x4010 => {
@@ -64,9 +85,7 @@ impl DmcChannel {
                 self.looping = value & 0b0100_0000 != 0;
                 self.period = PERIODS[value as usize & 0x0F];
             }
-            0x4011 => {
-                self.output = value & 0b0111_1111;
-            }
+            0x4011 => self.set_output_level(value),
             0x4012 => {
                 self.sample_address = 0xC000 + (value as u16 * 64);
             }
@@ -108,10 +127,10 @@ impl DmcChannel {
 
     fn tick_read(&mut self) {
         if self.current_length > 0 && self.bit_count == 0 {
-            self.cpu_stall_cycles += 4;
+            self.dma_halt = self.dma_stall;
             let a = self.current_address;
             self.shift_register = match self.cartridge {
-                Some(ref c) => c.borrow_mut().read_prg_byte(a),
+                Some(ref c) => c.borrow().read_prg_byte(a).unwrap_or(0),
                 None => 0,
             };
             self.bit_count = 8;
@@ -149,7 +168,35 @@ impl DmcChannel {
         }
     }
 
+    // Whether there are bytes left to fetch, as $4015 reports it. The last
+    // byte is still shifted out after this goes false.
     pub fn playing(&self) -> bool {
         self.current_length > 0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_output_level() {
+        let mut dmc = DmcChannel::new();
+        assert_eq!(dmc.sample(), 0);
+
+        dmc.write_register(0x4011, 0xC5);
+        assert_eq!(dmc.sample(), 0x45);
+
+        // Without a sample playing nothing changes the level
+        dmc.write_register(0x4010, 0x0F);
+        dmc.set_enabled(true);
+        for _ in 0..1000 {
+            dmc.tick_sequencer();
+        }
+        assert!(!dmc.playing());
+        assert_eq!(dmc.sample(), 0x45);
+
+        dmc.set_output_level(0x12);
+        assert_eq!(dmc.sample(), 0x12);
+    }
+}
@@ -1,5 +1,9 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 bitfield!{
     #[derive(Copy, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
     pub struct EnvelopeControl(u8);
     impl Debug;
     pub constant_level,   _: 3, 0;
@@ -8,6 +12,7 @@ bitfield!{
     pub looping,          _:    5;
 }
 
+#[derive(Clone)]
 pub struct Envelope {
     control: EnvelopeControl,
     counter: u8,
@@ -1,5 +1,6 @@
 use std::f64::consts::PI;
 
+#[derive(Clone)]
 pub struct FirstOrderFilter {
     b0: f64,
     b1: f64,
@@ -35,6 +36,11 @@ impl FirstOrderFilter {
         }
     }
 
+    pub fn reset(&mut self) {
+        self.prev_x = 0.0;
+        self.prev_y = 0.0;
+    }
+
     pub fn tick(&mut self, x: f64) -> f64 {
         let y = self.b0 * x + self.b1 * self.prev_x - self.a1 * self.prev_y;
         self.prev_y = y;
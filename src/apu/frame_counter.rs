@@ -19,6 +19,7 @@ pub struct FrameCounter {
     pub public_irq_flag: bool,
     pub private_irq_flag: bool,
     mode: Mode,
+    reset_delay: u8,
 }
 
 impl FrameCounter {
@@ -30,10 +31,13 @@ impl FrameCounter {
             public_irq_flag: false,
             private_irq_flag: false,
             mode: Mode::Zero,
+            reset_delay: 0,
         }
     }
 
-    pub fn write_register(&mut self, value: u8, cycles: u64) -> FrameResult {
+    // The counter is reset 3 cycles after a write on an even cycle, or 4 after
+    // one on an odd cycle. Until then the old sequence keeps running.
+    pub fn write_register(&mut self, value: u8, cycles: u64) {
         self.irq_enabled = value & 0x40 == 0;
         if !self.irq_enabled {
             self.public_irq_flag = false;
@@ -46,15 +50,34 @@ impl FrameCounter {
             Mode::One
         };
 
-        self.counter = if cycles & 1 == 0 { 0 } else { -1 };
+        self.reset_delay = if cycles & 1 == 0 { 3 } else { 4 };
+    }
 
-        match self.mode {
-            Mode::Zero => FrameResult::None,
-            Mode::One => FrameResult::Half,
+    // The value last written to $4017, as far as it affects the counter
+    pub fn last_value(&self) -> u8 {
+        let mut v = 0;
+        if self.mode == Mode::One {
+            v |= 0x80;
+        }
+        if !self.irq_enabled {
+            v |= 0x40;
         }
+        v
     }
 
     pub fn tick(&mut self) -> FrameResult {
+        if self.reset_delay > 0 {
+            self.reset_delay -= 1;
+            if self.reset_delay == 0 {
+                self.counter = 1;
+                // Resetting into mode one clocks everything straight away
+                return match self.mode {
+                    Mode::Zero => FrameResult::None,
+                    Mode::One => FrameResult::Half,
+                };
+            }
+        }
+
         let result = match self.mode {
             Mode::Zero => self.tick_mode_zero(),
             Mode::One => self.tick_mode_one(),
@@ -65,25 +88,25 @@ impl FrameCounter {
 
     fn tick_mode_zero(&mut self) -> FrameResult {
         match self.counter {
-            7_459 => FrameResult::Quarter,
-            14_915 => FrameResult::Half,
-            22_373 => FrameResult::Quarter,
-            29_830 => {
+            7_457 => FrameResult::Quarter,
+            14_913 => FrameResult::Half,
+            22_371 => FrameResult::Quarter,
+            29_828 => {
                 self.trigger_irq();
                 FrameResult::None
             }
-            29_831 => {
+            29_829 => {
                 self.trigger_irq();
                 self.publish_irq();
                 FrameResult::Half
             }
-            29_832 => {
+            29_830 => {
                 self.trigger_irq();
                 self.publish_irq();
-                // The counter *actually* rolls over to zero on cycle 29_830.
-                // The actions at 29_831 and 29_832 happen after the rollover.
-                // We emulate that by resetting our counter at 29_832 and skipping
-                // it ahead as if it had been reset at 29_830.
+                // The counter *actually* rolls over to zero on cycle 29_828.
+                // The actions at 29_829 and 29_830 happen after the rollover.
+                // We emulate that by resetting our counter at 29_830 and skipping
+                // it ahead as if it had been reset at 29_828.
                 self.counter = 2;
                 FrameResult::None
             }
@@ -93,14 +116,14 @@ impl FrameCounter {
 
     fn tick_mode_one(&mut self) -> FrameResult {
         match self.counter {
-            7_459 => FrameResult::Quarter,
-            14_915 => FrameResult::Half,
-            22_373 => FrameResult::Quarter,
-            37_283 => {
-                // The counter *actually* rolls over to zero on cycle 37_282.
+            7_457 => FrameResult::Quarter,
+            14_913 => FrameResult::Half,
+            22_371 => FrameResult::Quarter,
+            37_281 => {
+                // The counter *actually* rolls over to zero on cycle 37_280.
                 // The Half-frame signal is sent 1 tick after. We emulate this
                 // behavior by adding an extra tick to the clock, then skipping
-                // the clock ahead as if it had been reset at 37_282.
+                // the clock ahead as if it had been reset at 37_280.
                 self.counter = 1;
                 FrameResult::Half
             }
@@ -117,3 +140,38 @@ impl FrameCounter {
         self.public_irq_flag = self.private_irq_flag;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tick_n(frame_counter: &mut FrameCounter, n: usize) -> Vec<FrameResult> {
+        (0..n).map(|_| frame_counter.tick()).collect()
+    }
+
+    #[test]
+    fn test_reset_delay() {
+        for &(cycles, delay) in [(10, 3), (11, 4)].iter() {
+            let mut frame_counter = FrameCounter::new();
+            frame_counter.counter = 100;
+            frame_counter.write_register(0x80, cycles);
+
+            // The old sequence carries on until the reset
+            tick_n(&mut frame_counter, delay - 1);
+            assert_eq!(frame_counter.counter, 100 + delay as i64 - 1);
+
+            // Resetting into mode one clocks a half frame
+            assert_eq!(frame_counter.tick(), FrameResult::Half);
+            assert_eq!(frame_counter.counter, 1);
+        }
+    }
+
+    #[test]
+    fn test_first_quarter_frame() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.write_register(0x00, 0);
+        let results = tick_n(&mut frame_counter, 3 + 7_457);
+        let first = results.iter().position(|&r| r != FrameResult::None);
+        assert_eq!(first, Some(3 + 7_457 - 1));
+    }
+}
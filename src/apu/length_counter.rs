@@ -4,6 +4,7 @@ pub const LENGTHS: [u8; 32] = [
     0x0e, 0x0c, 0x10, 0x18, 0x12, 0x30, 0x14, 0x60, 0x16, 0xc0, 0x18, 0x48, 0x1a, 0x10, 0x1c, 0x20, 0x1e,
 ];
 
+#[derive(Clone)]
 pub struct LengthCounter {
     counter: u8,
     pub enabled: bool,
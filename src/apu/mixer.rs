@@ -0,0 +1,130 @@
+// Combines the channel outputs into a single value from 0.0 to 1.0
+// Formulas are from http://wiki.nesdev.com/w/index.php/APU_Mixer
+//
+// The formulas are evaluated once per possible input at construction,
+// so mixing a sample is just a pair of table lookups.
+//
+// The linear mode uses the linear approximation from the same page instead,
+// a flat weighted sum of the channels.
+
+use std::rc::Rc;
+
+const PULSE_TABLE_SIZE: usize = 31; // p0 + p1 ranges from 0 to 30
+const TND_TABLE_SIZE: usize = 16 * 16 * 128; // triangle x noise x dmc
+
+const PULSE_WEIGHT: f64 = 0.00752;
+const TRIANGLE_WEIGHT: f64 = 0.00851;
+const NOISE_WEIGHT: f64 = 0.00494;
+const DMC_WEIGHT: f64 = 0.00335;
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum MixingMode {
+    #[default]
+    Nonlinear,
+    Linear,
+}
+
+#[derive(Clone)]
+pub struct Mixer {
+    pulse_table: [f64; PULSE_TABLE_SIZE],
+    // Shared by clones, as it never changes and is large
+    tnd_table: Rc<Vec<f64>>,
+    mode: MixingMode,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; PULSE_TABLE_SIZE];
+        for (p, v) in pulse_table.iter_mut().enumerate() {
+            *v = pulse_out(p as f64);
+        }
+
+        let mut tnd_table = vec![0.0; TND_TABLE_SIZE];
+        for (i, v) in tnd_table.iter_mut().enumerate() {
+            let (t, n, d) = (i >> 11, (i >> 7) & 0x0F, i & 0x7F);
+            *v = tnd_out(t as f64, n as f64, d as f64);
+        }
+
+        Mixer {
+            pulse_table,
+            tnd_table: Rc::new(tnd_table),
+            mode: MixingMode::default(),
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: MixingMode) {
+        self.mode = mode;
+    }
+
+    pub fn sample(&self, p0: u8, p1: u8, t: u8, n: u8, d: u8) -> f64 {
+        if self.mode == MixingMode::Linear {
+            return linear_out(p0, p1, t, n, d);
+        }
+
+        let tnd_index = (t as usize) << 11 | (n as usize) << 7 | d as usize;
+        self.pulse_table[(p0 + p1) as usize] + self.tnd_table[tnd_index]
+    }
+}
+
+fn pulse_out(p: f64) -> f64 {
+    95.88 / ((8128.0 / p) + 100.0)
+}
+
+fn tnd_out(t: f64, n: f64, d: f64) -> f64 {
+    159.79 / ((1.0 / (t / 8227.0 + n / 12241.0 + d / 22638.0)) + 100.0)
+}
+
+fn linear_out(p0: u8, p1: u8, t: u8, n: u8, d: u8) -> f64 {
+    PULSE_WEIGHT * (p0 + p1) as f64
+        + TRIANGLE_WEIGHT * t as f64
+        + NOISE_WEIGHT * n as f64
+        + DMC_WEIGHT * d as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+
+    // Expected values are the nesdev formulas worked out separately
+    #[test]
+    fn test_pulse_table() {
+        let mixer = Mixer::new();
+        assert_eq!(mixer.sample(0, 0, 0, 0, 0), 0.0);
+        assert_close(mixer.sample(1, 0, 0, 0, 0), 0.011653);
+        assert_close(mixer.sample(0, 1, 0, 0, 0), 0.011653);
+        assert_close(mixer.sample(8, 7, 0, 0, 0), 0.149377);
+        assert_close(mixer.sample(15, 15, 0, 0, 0), 0.258483);
+    }
+
+    #[test]
+    fn test_tnd_table() {
+        let mixer = Mixer::new();
+        assert_close(mixer.sample(0, 0, 15, 0, 0), 0.246412);
+        assert_close(mixer.sample(0, 0, 0, 15, 0), 0.174431);
+        assert_close(mixer.sample(0, 0, 0, 0, 127), 0.574264);
+        assert_close(mixer.sample(0, 0, 8, 4, 64), 0.466746);
+        assert_close(mixer.sample(0, 0, 15, 15, 127), 0.741516);
+    }
+
+    #[test]
+    fn test_linear_mode() {
+        let mut mixer = Mixer::new();
+        let nonlinear = mixer.sample(15, 15, 15, 15, 127);
+        mixer.set_mode(MixingMode::Linear);
+        let linear = mixer.sample(15, 15, 15, 15, 127);
+        assert!((nonlinear - linear).abs() > 0.01);
+
+        let expected = 30.0 * 0.00752 + 15.0 * 0.00851 + 15.0 * 0.00494 + 127.0 * 0.00335;
+        assert!((linear - expected).abs() < 1e-9);
+        assert!(linear <= 1.0);
+
+        // Doubling a channel's output doubles its contribution
+        let t = mixer.sample(0, 0, 4, 0, 0);
+        assert!((mixer.sample(0, 0, 8, 0, 0) - 2.0 * t).abs() < 1e-9);
+        assert_eq!(mixer.sample(0, 0, 0, 0, 0), 0.0);
+    }
+}
@@ -6,6 +6,7 @@ mod triangle_channel;
 mod noise_channel;
 mod dmc_channel;
 mod filter;
+mod mixer;
 mod sequencer;
 mod sweep;
 
@@ -14,12 +15,44 @@ use self::envelope::Envelope;
 use self::filter::FirstOrderFilter;
 use self::frame_counter::{FrameCounter, FrameResult};
 use self::length_counter::LengthCounter;
+use self::mixer::Mixer;
+pub use self::mixer::MixingMode;
 use self::noise_channel::NoiseChannel;
 use self::pulse_channel::PulseChannel;
 use self::sequencer::Sequencer;
 use self::sweep::{Sweep, SweepNegationMode};
 use self::triangle_channel::TriangleChannel;
 
+use cartridge::Cartridge;
+use consts::{AUDIO_SAMPLE_RATE_DEFAULT, NTSC_CPU_HZ};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AudioFormat {
+    Mono,
+    Stereo,
+}
+
+impl AudioFormat {
+    pub fn channels(self) -> usize {
+        match self {
+            AudioFormat::Mono => 1,
+            AudioFormat::Stereo => 2,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Apu {
     pub buffer: Vec<i16>,
     frame_counter: FrameCounter,
@@ -29,6 +62,15 @@ pub struct Apu {
     noise: NoiseChannel,
     pub dmc: DmcChannel,
     filters: [FirstOrderFilter; 3],
+    cycles_per_sample: f64,
+    sample_cycles: f64,
+    target_samples_per_frame: Option<usize>,
+    audio_format: AudioFormat,
+    cartridge: Option<Rc<RefCell<Cartridge>>>,
+    expansion_audio: bool,
+    mixer: Mixer,
+    channels_enabled: [bool; 5],
+    register_log: Option<Vec<(u64, u16, u8)>>,
 }
 
 impl Apu {
@@ -41,21 +83,99 @@ impl Apu {
             triangle: TriangleChannel::new(),
             noise: NoiseChannel::new(),
             dmc: DmcChannel::new(),
-            filters: [
-                FirstOrderFilter::high_pass(44100.0, 90.0),
-                FirstOrderFilter::high_pass(44100.0, 440.0),
-                FirstOrderFilter::low_pass(44100.0, 14_000.0),
-            ],
+            filters: build_filters(AUDIO_SAMPLE_RATE_DEFAULT),
+            cycles_per_sample: NTSC_CPU_HZ / AUDIO_SAMPLE_RATE_DEFAULT,
+            sample_cycles: 0.0,
+            target_samples_per_frame: None,
+            audio_format: AudioFormat::Stereo,
+            cartridge: None,
+            expansion_audio: true,
+            mixer: Mixer::new(),
+            channels_enabled: [true; 5],
+            register_log: None,
         }
     }
 
+    pub fn power_on(&mut self) {
+        self.write_register(0x4015, 0, 0);
+        self.noise.reset();
+        self.restart_frame_counter(0);
+    }
+
+    // The reset button silences every channel and restarts the frame
+    // counter, but the mode and IRQ inhibit written to $4017 are kept.
+    // The filters are cleared too, so nothing from before the reset leaks
+    // into the output.
     pub fn reset(&mut self) {
-        self.write_register(0x4017, 0, 0);
+        self.write_register(0x4015, 0, 0);
+        self.noise.reset();
+        for f in self.filters.iter_mut() {
+            f.reset();
+        }
+        let value = self.frame_counter.last_value();
+        self.restart_frame_counter(value);
+    }
+
+    fn restart_frame_counter(&mut self, value: u8) {
+        self.write_register(0x4017, value, 0);
         for i in 0..11 {
             self.tick(i);
         }
     }
 
+    pub fn frame_counter_cycles(&self) -> i64 {
+        self.frame_counter.counter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.filters = build_filters(sample_rate);
+        self.cycles_per_sample = NTSC_CPU_HZ / sample_rate;
+        self.sample_cycles = 0.0;
+    }
+
+    // The cartridge supplies DMC samples and any expansion audio
+    pub fn set_cartridge(&mut self, cartridge: Rc<RefCell<Cartridge>>) {
+        self.dmc.set_cartridge(cartridge.clone());
+        self.cartridge = Some(cartridge);
+    }
+
+    // Whether audio generated by the cartridge is mixed in
+    pub fn set_mixing_mode(&mut self, mode: MixingMode) {
+        self.mixer.set_mode(mode);
+    }
+
+    pub fn set_expansion_audio(&mut self, enabled: bool) {
+        self.expansion_audio = enabled;
+    }
+
+    // Stereo output has both channels interleaved in the buffer, though they
+    // are always identical.
+    pub fn set_audio_format(&mut self, format: AudioFormat) {
+        self.audio_format = format;
+    }
+
+    // Frontends that need the same number of samples every frame can set a
+    // target here and call finalize_frame at the end of each frame. In stereo
+    // a sample is a left and right pair.
+    pub fn set_target_samples_per_frame(&mut self, samples: usize) {
+        self.target_samples_per_frame = Some(samples);
+    }
+
+    // Pads or trims the buffer to the target number of samples. Padding
+    // repeats the last sample, since jumping to silence would click.
+    pub fn finalize_frame(&mut self) {
+        if let Some(samples) = self.target_samples_per_frame {
+            let last = self.buffer.last().cloned().unwrap_or(0);
+            self.buffer.resize(samples * self.audio_format.channels(), last);
+        }
+    }
+
+    // Muting a channel only removes it from the mix. It keeps running, so
+    // unmuting it later picks up exactly where the hardware would be.
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        self.channels_enabled[channel as usize] = enabled;
+    }
+
     pub fn read_register(&mut self) -> u8 {
         let mut result = 0;
         if self.dmc.irq_flag {
@@ -85,7 +205,30 @@ impl Apu {
         result
     }
 
+    // Logs every register write with the CPU cycle it happened on, so the
+    // audio can be reproduced exactly by replaying the writes
+    pub fn start_register_log(&mut self) {
+        self.register_log = Some(Vec::new());
+    }
+
+    pub fn stop_register_log(&mut self) -> Vec<(u64, u16, u8)> {
+        self.register_log.take().unwrap_or_default()
+    }
+
+    // Moves the log out and back without stopping it, for snapshots
+    pub fn take_register_log(&mut self) -> Option<Vec<(u64, u16, u8)>> {
+        self.register_log.take()
+    }
+
+    pub fn restore_register_log(&mut self, log: Option<Vec<(u64, u16, u8)>>) {
+        self.register_log = log;
+    }
+
     pub fn write_register(&mut self, address: u16, value: u8, cycles: u64) {
+        if let Some(ref mut log) = self.register_log {
+            log.push((cycles, address, value));
+        }
+
         match address {
             0x4000...0x4003 => self.pulse_0.write_register(address, value),
             0x4004...0x4007 => self.pulse_1.write_register(address, value),
@@ -99,10 +242,7 @@ impl Apu {
                 self.noise.set_enabled(value & 0b0000_1000 != 0);
                 self.dmc.set_enabled(value & 0b0001_0000 != 0);
             }
-            0x4017 => {
-                let r = self.frame_counter.write_register(value, cycles);
-                self.handle_frame_result(r);
-            }
+            0x4017 => self.frame_counter.write_register(value, cycles),
             _ => panic!("Bad APU address: {:04X}", address),
         }
     }
@@ -127,13 +267,16 @@ impl Apu {
         self.triangle.update_pending_length_counter();
         self.noise.update_pending_length_counter();
 
-        // We need 730 stereo audio samples per frame for 60 fps.
-        // Each frame lasts a minimum of 29,779 CPU cycles. This
-        // works out to around 40 CPU cycles per sample.
-        if cpu_cycles % 40 == 0 {
+        // Output samples fall between CPU cycles, so keep track of the
+        // fractional cycles left over to get exactly sample_rate samples
+        // for every NTSC_CPU_HZ cycles.
+        self.sample_cycles += 1.0;
+        if self.sample_cycles >= self.cycles_per_sample {
+            self.sample_cycles -= self.cycles_per_sample;
             let s = self.sample();
-            self.buffer.push(s);
-            self.buffer.push(s);
+            for _ in 0..self.audio_format.channels() {
+                self.buffer.push(s);
+            }
         }
     }
 
@@ -162,20 +305,33 @@ impl Apu {
         self.frame_counter.public_irq_flag || self.dmc.irq_flag
     }
 
-    fn sample(&mut self) -> i16 {
-        let p0 = self.pulse_0.sample() as f64;
-        let p1 = self.pulse_1.sample() as f64;
-        let t = self.triangle.sample() as f64;
-        let n = self.noise.sample() as f64;
-        let d = self.dmc.sample() as f64;
+    fn channel_output(&self, channel: Channel, output: u8) -> u8 {
+        if self.channels_enabled[channel as usize] {
+            output
+        } else {
+            0
+        }
+    }
 
-        // Combine channels into a single value from 0.0 to 1.0
-        // Formula is from http://wiki.nesdev.com/w/index.php/APU_Mixer
-        let pulse_out = 95.88 / ((8218.0 / (p0 + p1)) + 100.0);
-        let tnd_out = 159.79 / ((1.0 / (t / 8227.0 + n / 12241.0 + d / 22638.0)) + 100.0);
+    // Combine channels into a single value from 0.0 to 1.0
+    fn mix(&self) -> f64 {
+        let p0 = self.channel_output(Channel::Pulse1, self.pulse_0.sample());
+        let p1 = self.channel_output(Channel::Pulse2, self.pulse_1.sample());
+        let t = self.channel_output(Channel::Triangle, self.triangle.sample());
+        let n = self.channel_output(Channel::Noise, self.noise.sample());
+        let d = self.channel_output(Channel::Dmc, self.dmc.sample());
+        let expansion = match self.cartridge {
+            Some(ref c) if self.expansion_audio => c.borrow().mapper_audio_sample() as f64,
+            _ => 0.0,
+        };
+        self.mixer.sample(p0, p1, t, n, d) + expansion
+    }
 
+    // One output sample, mixed and filtered. tick takes these at the
+    // output sample rate.
+    pub fn sample(&mut self) -> i16 {
         // Scale to 0..65536
-        let mut output = (pulse_out + tnd_out) * 65535.0;
+        let mut output = self.mix() * 65535.0;
 
         // Apply high pass and low pass filters
         for i in 0..3 {
@@ -186,3 +342,283 @@ impl Apu {
         output as i16
     }
 }
+
+fn build_filters(sample_rate: f64) -> [FirstOrderFilter; 3] {
+    [
+        FirstOrderFilter::high_pass(sample_rate, 90.0),
+        FirstOrderFilter::high_pass(sample_rate, 440.0),
+        FirstOrderFilter::low_pass(sample_rate, 14_000.0),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use consts::NTSC_FPS;
+
+    fn count_frame_samples(apu: &mut Apu) -> usize {
+        let frame_cycles = (NTSC_CPU_HZ / NTSC_FPS) as u64;
+        for i in 0..frame_cycles {
+            apu.tick(i);
+        }
+        let samples = apu.buffer.len() / 2;
+        apu.buffer.clear();
+        samples
+    }
+
+    #[test]
+    fn test_samples_per_frame() {
+        // 44100 / 60.0988 = 733.8 samples per frame
+        let mut apu = Apu::new();
+        let counts: Vec<usize> = (0..5).map(|_| count_frame_samples(&mut apu)).collect();
+        assert!(counts.iter().all(|&c| c == 733 || c == 734), "{:?}", counts);
+        assert!((counts.iter().sum::<usize>() as f64 - 5.0 * 733.8).abs() <= 1.0);
+
+        // 48000 / 60.0988 = 798.7 samples per frame
+        apu.set_sample_rate(48000.0);
+        let counts: Vec<usize> = (0..5).map(|_| count_frame_samples(&mut apu)).collect();
+        assert!(counts.iter().all(|&c| c == 798 || c == 799), "{:?}", counts);
+    }
+
+    fn build_apu_with_triangle() -> Apu {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0100, 0);
+        apu.write_register(0x4008, 0b1111_1111, 0); // Halt and max linear counter
+        apu.write_register(0x400A, 0x10, 0);
+        apu.write_register(0x400B, 0b1111_1000, 0);
+        apu.triangle.update_pending_length_counter();
+        apu.triangle.tick_quarter_frame();
+        apu
+    }
+
+    // Runs the APU from just after a $4017 write on cycle 0
+    fn build_apu_after_4017_write(value: u8, cycles: u64) -> Apu {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, value, 0);
+        for i in 1..cycles + 1 {
+            apu.tick(i);
+        }
+        apu
+    }
+
+    // The counter resets 3 cycles after the write, then sets the flag on
+    // the 3 cycles from 29828. $4015 sees it a cycle before the IRQ line.
+    const FIRST_IRQ_CYCLE: u64 = 29_831;
+
+    #[test]
+    fn test_frame_irq_flag_timing() {
+        let mut apu = build_apu_after_4017_write(0x00, FIRST_IRQ_CYCLE - 1);
+        assert!(!apu.frame_counter.private_irq_flag);
+        assert_eq!(apu.clone().read_register() & 0x40, 0);
+
+        apu.tick(FIRST_IRQ_CYCLE);
+        assert_eq!(apu.clone().read_register() & 0x40, 0x40);
+        assert!(!apu.irq_flag());
+        apu.tick(FIRST_IRQ_CYCLE + 1);
+        assert!(apu.irq_flag());
+
+        // Reading clears the flag, but it's set again on the last of the 3
+        // cycles, so only a read after that clears it for good
+        assert_eq!(apu.read_register() & 0x40, 0x40);
+        assert!(!apu.irq_flag());
+        apu.tick(FIRST_IRQ_CYCLE + 2);
+        assert!(apu.irq_flag());
+        assert_eq!(apu.read_register() & 0x40, 0x40);
+        apu.tick(FIRST_IRQ_CYCLE + 3);
+        assert_eq!(apu.read_register() & 0x40, 0);
+        assert!(!apu.irq_flag());
+    }
+
+    #[test]
+    fn test_frame_irq_inhibit() {
+        // Setting the inhibit flag clears the IRQ flag straight away,
+        // without waiting for the counter to reset
+        let mut apu = build_apu_after_4017_write(0x00, FIRST_IRQ_CYCLE + 1);
+        assert!(apu.irq_flag());
+        apu.write_register(0x4017, 0x40, FIRST_IRQ_CYCLE + 1);
+        assert!(!apu.irq_flag());
+        assert_eq!(apu.clone().read_register() & 0x40, 0);
+
+        // And it stays clear through the next frame
+        let mut apu = build_apu_after_4017_write(0x40, FIRST_IRQ_CYCLE + 10);
+        assert!(!apu.irq_flag());
+        assert_eq!(apu.read_register() & 0x40, 0);
+    }
+
+    // Writes $4017 with mode 1, which clocks the half frame units 3 or 4
+    // cycles later, and runs the APU up to the cycle before that clock
+    fn start_half_frame_clock(apu: &mut Apu, cycles: &mut u64) {
+        apu.write_register(0x4017, 0x80, *cycles);
+        let delay = if *cycles & 1 == 0 { 3 } else { 4 };
+        for _ in 0..delay - 1 {
+            *cycles += 1;
+            apu.tick(*cycles);
+        }
+    }
+
+    #[test]
+    fn test_length_counter_halt_on_clock() {
+        // Lengths of 2, with the pulse and noise counters halted
+        let mut apu = Apu::new();
+        let mut cycles = 0;
+        apu.write_register(0x4015, 0b0000_1011, cycles);
+        for &address in [0x4000, 0x4004, 0x400C].iter() {
+            apu.write_register(address, 0b0010_0000, cycles);
+            apu.write_register(address + 3, 0b0001_1000, cycles);
+        }
+        cycles += 1;
+        apu.tick(cycles);
+
+        // Clearing the halt flag on the cycle of a half frame clock only
+        // takes effect after the clock, so the counters aren't clocked
+        start_half_frame_clock(&mut apu, &mut cycles);
+        for &address in [0x4000, 0x4004, 0x400C].iter() {
+            apu.write_register(address, 0, cycles);
+        }
+        cycles += 1;
+        apu.tick(cycles);
+
+        // So they take two more clocks to run out, not one
+        start_half_frame_clock(&mut apu, &mut cycles);
+        cycles += 1;
+        apu.tick(cycles);
+        assert_eq!(apu.read_register() & 0x0F, 0b0000_1011);
+
+        start_half_frame_clock(&mut apu, &mut cycles);
+        cycles += 1;
+        apu.tick(cycles);
+        assert_eq!(apu.read_register() & 0x0F, 0);
+    }
+
+    #[test]
+    fn test_dmc_playing_bit() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0x0F, 0); // Fastest rate, 27 cycles a bit
+        apu.write_register(0x4011, 0x40, 0);
+        apu.write_register(0x4013, 0x00, 0); // One byte
+        apu.write_register(0x4015, 0b0001_0000, 0);
+        assert_eq!(apu.read_register() & 0x10, 0x10);
+
+        // The bit is for bytes left to fetch, so it clears as soon as the
+        // only byte is read, while that byte's bits are still being output.
+        // Without a cartridge the byte is 0, so each bit lowers the level.
+        apu.dmc.tick_sequencer();
+        assert!(apu.dmc.reset_dma_halt());
+        assert_eq!(apu.read_register() & 0x10, 0);
+        let mut levels = vec![apu.dmc.sample()];
+        for _ in 0..27 * 8 {
+            apu.dmc.tick_sequencer();
+            if levels.last() != Some(&apu.dmc.sample()) {
+                levels.push(apu.dmc.sample());
+            }
+            assert_eq!(apu.read_register() & 0x10, 0);
+        }
+        assert_eq!(levels, [0x3E, 0x3C, 0x3A, 0x38, 0x36, 0x34, 0x32, 0x30]);
+    }
+
+    #[test]
+    fn test_register_log() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0001, 10);
+        apu.start_register_log();
+        apu.write_register(0x4015, 0b0000_0101, 20);
+        apu.write_register(0x4000, 0xBF, 21);
+        apu.write_register(0x4003, 0x08, 25);
+        apu.write_register(0x400B, 0xF8, 30);
+        apu.write_register(0x4017, 0x40, 31);
+
+        assert_eq!(
+            apu.stop_register_log(),
+            [
+                (20, 0x4015, 0b0000_0101),
+                (21, 0x4000, 0xBF),
+                (25, 0x4003, 0x08),
+                (30, 0x400B, 0xF8),
+                (31, 0x4017, 0x40),
+            ]
+        );
+
+        // Nothing is logged once it's stopped
+        apu.write_register(0x4002, 0x80, 40);
+        assert!(apu.stop_register_log().is_empty());
+    }
+
+    #[test]
+    fn test_mute_channel() {
+        let mut apu = build_apu_with_triangle();
+        assert_ne!(apu.triangle.sample(), 0);
+        assert!(apu.mix() > 0.0);
+
+        apu.set_channel_enabled(Channel::Triangle, false);
+        assert_eq!(apu.mix(), 0.0);
+
+        // The muted triangle keeps stepping through its waveform
+        let before = apu.triangle.sample();
+        for i in 0..0x11 * 4 {
+            apu.tick(i);
+        }
+        assert_ne!(apu.triangle.sample(), before);
+        assert_eq!(apu.mix(), 0.0);
+
+        apu.set_channel_enabled(Channel::Triangle, true);
+        assert!(apu.mix() > 0.0);
+    }
+
+    #[test]
+    fn test_triangle_holds_output_when_disabled() {
+        let mut apu = build_apu_with_triangle();
+        let mut i = 0;
+        while apu.triangle.sample() != 10 {
+            apu.tick(i);
+            i += 1;
+        }
+
+        apu.write_register(0x4015, 0, i);
+        for j in i..i + 0x11 * 8 {
+            apu.tick(j);
+        }
+        assert_eq!(apu.triangle.sample(), 10);
+    }
+
+    #[test]
+    fn test_finalize_frame() {
+        let mut apu = build_apu_with_triangle();
+        apu.set_target_samples_per_frame(800);
+        for i in 0..20_000 {
+            apu.tick(i);
+        }
+        let produced = apu.buffer.len();
+        assert!(produced < 1600);
+        let last = apu.buffer[produced - 1];
+        assert_ne!(last, 0);
+
+        apu.finalize_frame();
+        assert_eq!(apu.buffer.len(), 1600);
+        assert!(apu.buffer[produced..].iter().all(|&s| s == last));
+
+        // Long frames are trimmed
+        apu.set_target_samples_per_frame(100);
+        apu.finalize_frame();
+        assert_eq!(apu.buffer.len(), 200);
+    }
+
+    #[test]
+    fn test_mono() {
+        let mut stereo = build_apu_with_triangle();
+        let mut mono = build_apu_with_triangle();
+        mono.set_audio_format(AudioFormat::Mono);
+        for i in 0..20_000 {
+            stereo.tick(i);
+            mono.tick(i);
+        }
+        assert_eq!(mono.buffer.len() * 2, stereo.buffer.len());
+        for (i, &s) in mono.buffer.iter().enumerate() {
+            assert_eq!(stereo.buffer[i * 2], s);
+            assert_eq!(stereo.buffer[i * 2 + 1], s);
+        }
+
+        mono.set_target_samples_per_frame(800);
+        mono.finalize_frame();
+        assert_eq!(mono.buffer.len(), 800);
+    }
+}
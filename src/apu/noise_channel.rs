@@ -6,6 +6,7 @@ const PERIODS: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
 
+#[derive(Clone)]
 pub struct NoiseChannel {
     envelope: Envelope,
     length_counter: LengthCounter,
@@ -27,6 +28,11 @@ impl NoiseChannel {
         }
     }
 
+    // The shift register powers up holding 1, and goes back to it on reset
+    pub fn reset(&mut self) {
+        self.shift = 1;
+    }
+
     pub fn write_register(&mut self, address: u16, value: u8) {
         match address {
             0x400C => {
@@ -60,6 +66,8 @@ impl NoiseChannel {
             self.counter -= 1;
         } else {
             self.counter = self.period;
+            // Feedback is bit 0 XOR bit 6 in short mode, and bit 0 XOR bit 1
+            // otherwise
             let bit1 = (self.shift >> (if self.mode { 6 } else { 1 })) & 1;
             let bit2 = self.shift & 1;
             self.shift = (self.shift >> 1) | (bit1 ^ bit2) << 14
@@ -86,3 +94,53 @@ impl NoiseChannel {
         self.length_counter.update_pending();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Steps the shift register until it's back to 1, returning the first
+    // few values it held along the way
+    fn run_lfsr(noise: &mut NoiseChannel) -> (usize, Vec<u16>) {
+        let mut values = vec![];
+        let mut steps = 0;
+        loop {
+            noise.tick_sequencer();
+            steps += 1;
+            if values.len() < 4 {
+                values.push(noise.shift);
+            }
+            if noise.shift == 1 {
+                return (steps, values);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lfsr_sequence() {
+        let mut noise = NoiseChannel::new();
+        assert_eq!(
+            run_lfsr(&mut noise),
+            (32767, vec![0x4000, 0x2000, 0x1000, 0x0800])
+        );
+
+        // Short mode taps bit 6, for a 93 step loop from 1
+        noise.write_register(0x400E, 0b1000_0000);
+        noise.period = 0;
+        assert_eq!(
+            run_lfsr(&mut noise),
+            (93, vec![0x4000, 0x2000, 0x1000, 0x0800])
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut noise = NoiseChannel::new();
+        for _ in 0..10 {
+            noise.tick_sequencer();
+        }
+        assert_ne!(noise.shift, 1);
+        noise.reset();
+        assert_eq!(noise.shift, 1);
+    }
+}
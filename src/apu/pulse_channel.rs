@@ -10,6 +10,7 @@ pub const PULSE_WAVEFORMS: [[u8; 8]; 4] = [
     [1, 0, 0, 1, 1, 1, 1, 1],
 ];
 
+#[derive(Clone)]
 pub struct PulseChannel {
     sweep: Sweep,
     envelope: Envelope,
@@ -85,3 +86,33 @@ impl PulseChannel {
         self.length_counter.update_pending();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_pulse(negation_mode: SweepNegationMode) -> PulseChannel {
+        let mut pulse = PulseChannel::new(negation_mode);
+        pulse.set_enabled(true);
+        pulse.write_register(0x4001, 0b1000_1001); // Enabled, negate, shift of 1
+        pulse.write_register(0x4002, 0x00);
+        pulse.write_register(0x4003, 0b1111_1001); // Period of $100
+        pulse.update_pending_length_counter();
+        pulse
+    }
+
+    #[test]
+    fn test_sweep_negate() {
+        // Pulse 1 adds the ones' complement of the change, so it ends up one
+        // lower than pulse 2, which adds the two's complement
+        let mut pulse_0 = build_pulse(SweepNegationMode::OnesCompliment);
+        let mut pulse_1 = build_pulse(SweepNegationMode::TwosCompliment);
+        assert_eq!(pulse_0.sweep.target_period(&pulse_0.sequencer), 0x7F);
+        assert_eq!(pulse_1.sweep.target_period(&pulse_1.sequencer), 0x80);
+
+        pulse_0.tick_half_frame();
+        pulse_1.tick_half_frame();
+        assert_eq!(pulse_0.sequencer.period, 0x7F);
+        assert_eq!(pulse_1.sequencer.period, 0x80);
+    }
+}
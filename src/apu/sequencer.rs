@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct Sequencer {
     pub counter: u16,
     pub period: u16,
@@ -7,6 +7,7 @@ pub const TRIANGLE_WAVEFORM: [u8; 32] = [
     0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
 ];
 
+#[derive(Clone)]
 pub struct TriangleChannel {
     length_counter: LengthCounter,
     sequencer: Sequencer,
@@ -49,19 +50,24 @@ impl TriangleChannel {
         }
     }
 
+    // When the channel is silenced the sequencer stops but the output holds
+    // its current step, rather than dropping to 0 with an audible click.
     pub fn sample(&self) -> u8 {
-        if self.active() && self.sequencer.period > 2 {
-            TRIANGLE_WAVEFORM[self.sequencer.current_step]
-        } else {
-            0
-        }
+        TRIANGLE_WAVEFORM[self.sequencer.current_step]
     }
 
+    // Periods of 2 and below are ultrasonic. Rather than stepping the waveform at a
+    // rate that can't be heard, the sequencer stops there like it does when
+    // silenced, so it picks up from the same phase afterwards.
     pub fn tick_sequencer(&mut self) {
-        let sequencer_active = self.active();
+        let sequencer_active = self.active() && !self.ultrasonic();
         self.sequencer.tick(sequencer_active);
     }
 
+    fn ultrasonic(&self) -> bool {
+        self.sequencer.period <= 2
+    }
+
     pub fn tick_quarter_frame(&mut self) {
         // Tick the linear counter
         if self.linear_counter_start {
@@ -95,3 +101,45 @@ impl TriangleChannel {
         self.length_counter.update_pending();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_triangle(period: u8) -> TriangleChannel {
+        let mut triangle = TriangleChannel::new();
+        triangle.set_enabled(true);
+        triangle.write_register(0x4008, 0b1111_1111); // Halt and max linear counter
+        triangle.write_register(0x400A, period);
+        triangle.write_register(0x400B, 0b1111_1000);
+        triangle.update_pending_length_counter();
+        triangle.tick_quarter_frame();
+        triangle
+    }
+
+    #[test]
+    fn test_ultrasonic_holds_phase() {
+        let mut triangle = build_triangle(0x10);
+        for _ in 0..0x11 * 4 {
+            triangle.tick_sequencer();
+        }
+        let step = triangle.sequencer.current_step;
+        assert_eq!(step, 4);
+
+        for &period in [0, 1, 2].iter() {
+            triangle.write_register(0x400A, period);
+            for _ in 0..100 {
+                triangle.tick_sequencer();
+            }
+            assert_eq!(triangle.sequencer.current_step, step);
+            assert_eq!(triangle.sample(), TRIANGLE_WAVEFORM[step]);
+        }
+
+        // It carries on from the same step at an audible period
+        triangle.write_register(0x400A, 3);
+        for _ in 0..4 {
+            triangle.tick_sequencer();
+        }
+        assert_eq!(triangle.sequencer.current_step, step + 1);
+    }
+}
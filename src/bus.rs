@@ -1,11 +1,15 @@
 use apu::Apu;
-use cartridge::Cartridge;
-use controller::Controller;
+use cartridge::{Cartridge, RomError};
+use controller::{Controller, FourScore, InputDevice};
+use model::ConsoleModel;
 use ppu::Ppu;
 use ppu::result::PpuResult;
+use ram_init::RamInit;
 use std::cell::RefCell;
+use std::mem;
 use std::rc::Rc;
 
+#[derive(Clone)]
 pub struct Interrupt {
     schedule: Option<u8>,
 }
@@ -40,59 +44,136 @@ impl Interrupt {
     }
 }
 
+// The frame buffer, audio samples and logs. They're for the frontend rather
+// than part of the console's state, so snapshots leave them out.
+pub struct Output {
+    pixels: Vec<u32>,
+    background_layer: Vec<u32>,
+    sprite_layer: Vec<u32>,
+    audio: Vec<i16>,
+    register_log: Option<Vec<(u64, u16, u8)>>,
+}
+
+#[derive(Clone)]
 pub struct Bus {
     pub ram: [u8; 2048],
     pub apu: Apu,
     pub ppu: Ppu,
     pub cartridge: Option<Rc<RefCell<Cartridge>>>,
     pub controller_0: Controller,
-    pub controller_1: Controller,
+    pub controller_1: InputDevice,
+    pub controller_2: Controller,
+    pub controller_3: Controller,
+    four_score: Option<FourScore>,
+    model: ConsoleModel,
+    microphone: bool,
+    dip_switches: u8,
+    // The master CPU cycle count since power on, which the rest of the
+    // console is clocked from
     pub cycles: u64,
     pub nmi: Interrupt,
     pub draw: bool,
     cpu_stall_cycles: usize,
+    dmc_write_cycles: u64,
+    last_bus_value: u8,
+    ram_init: RamInit,
 }
 
 impl Bus {
     pub fn new() -> Self {
-        Bus {
+        let mut bus = Bus {
             ppu: Ppu::new(),
             apu: Apu::new(),
             cartridge: None,
             controller_0: Controller::new(),
-            controller_1: Controller::new(),
+            controller_1: InputDevice::Controller(Controller::new()),
+            controller_2: Controller::new(),
+            controller_3: Controller::new(),
+            four_score: None,
+            model: ConsoleModel::default(),
+            microphone: false,
+            dip_switches: 0,
             ram: [0; 2048],
             cycles: 0,
             nmi: Interrupt::new(),
             draw: false, // add: mapper/cartridge
             cpu_stall_cycles: 0,
-        }
+            dmc_write_cycles: 0,
+            last_bus_value: 0,
+            ram_init: RamInit::default(),
+        };
+        bus.set_model(ConsoleModel::default());
+        bus
+    }
+
+    pub fn set_model(&mut self, model: ConsoleModel) {
+        self.model = model;
+        self.apu.set_expansion_audio(model.has_expansion_audio());
+    }
+
+    pub fn model(&self) -> ConsoleModel {
+        self.model
+    }
+
+    // Ignored by models without a microphone
+    pub fn set_microphone(&mut self, active: bool) {
+        self.microphone = active;
+    }
+
+    // Ignored by models without DIP switches. Switch 1 is the lowest bit.
+    pub fn set_dip_switches(&mut self, switches: u8) {
+        self.dip_switches = switches;
     }
 
+    // DMC stalls are handled as they happen in read_byte, so only OAM DMA
+    // is left to stall the CPU between instructions.
     pub fn reset_cpu_stall_cycles(&mut self) -> usize {
-        let c = self.cpu_stall_cycles + self.apu.dmc.reset_cpu_stall_cycles() as usize;
+        let c = self.cpu_stall_cycles;
         self.cpu_stall_cycles = 0;
         c
     }
 
-    // unclocked_read_byte and unclocked_write_byte are unclocked memory access
+    // unclocked_read_byte and unclocked_write_byte are unclocked memory access.
+    // Nothing drives the data bus for open regions, so reads of them return
+    // the last value that was on it.
     pub fn unclocked_read_byte(&mut self, address: u16) -> u8 {
-        match address {
+        let value = match address {
             0...0x1FFF => self.ram[address as usize % 0x0800],
-            0x2000...0x3FFF => self.ppu.read_register(address),
+            0x2000...0x3FFF => {
+                let value = self.ppu.read_register(address);
+                if self.ppu.take_nmi_cancelled() {
+                    self.nmi.acknowledge();
+                }
+                value
+            }
             0x4015 => self.apu.read_register(),
-            0x4016 => self.controller_0.read_register(),
-            0x4017 => self.controller_1.read_register(),
-            0x4018...0xFFFF => if let Some(ref c) = self.cartridge {
-                c.borrow().read_prg_byte(address)
-            } else {
-                (address >> 8) as u8
-            },
-            address => (address >> 8) as u8,
+            0x4016 | 0x4017 => self.read_input(address),
+            0x4020...0xFFFF => self.cartridge
+                .as_ref()
+                .and_then(|c| c.borrow().read_prg_byte(address))
+                .unwrap_or(self.last_bus_value),
+            _ => self.last_bus_value,
+        };
+        self.last_bus_value = value;
+        value
+    }
+
+    // What the CPU would read, for debuggers, but without the side effects
+    // of reading a register. The console's own registers all have some, so
+    // they show the open bus value instead.
+    pub fn peek_byte(&self, address: u16) -> u8 {
+        match address {
+            0..=0x1FFF => self.ram[address as usize % 0x0800],
+            0x4020..=0xFFFF => self.cartridge
+                .as_ref()
+                .and_then(|c| c.borrow().peek_prg_byte(address))
+                .unwrap_or(self.last_bus_value),
+            _ => self.last_bus_value,
         }
     }
 
     fn unclocked_write_byte(&mut self, address: u16, value: u8) {
+        self.last_bus_value = value;
         match address {
             0...0x1FFF => self.ram[address as usize % 0x0800] = value,
             0x2000...0x3FFF => self.ppu.write_register(address, value),
@@ -102,15 +183,79 @@ impl Bus {
             0x4016 => {
                 self.controller_0.write_register(value);
                 self.controller_1.write_register(value);
+                if let Some(ref mut f) = self.four_score {
+                    f.write_register(value);
+                }
             }
-
-            0x4018...0xFFFF => if let Some(ref c) = self.cartridge {
+            0x4020...0xFFFF => if let Some(ref c) = self.cartridge {
                 c.borrow_mut().write_prg_byte(address, value);
             },
             _ => (),
         }
     }
 
+    // With a Four Score, controllers 1 and 3 are read from 0x4016 and
+    // controllers 2 and 4 from 0x4017
+    pub fn set_four_score(&mut self, enabled: bool) {
+        self.four_score = if enabled {
+            Some(FourScore::new())
+        } else {
+            None
+        };
+    }
+
+    fn read_input(&mut self, address: u16) -> u8 {
+        let v = if self.four_score.is_some() {
+            self.read_four_score(address)
+        } else if address == 0x4016 {
+            self.controller_0.read_register()
+        } else {
+            self.controller_1.read_register()
+        };
+        let open_bus = self.last_bus_value & self.input_open_bus_mask(address);
+        v | self.console_input_bits(address) | open_bus
+    }
+
+    // The top three bits of 0x4016 and 0x4017 aren't driven, except by the
+    // VS System's DIP switches, so they keep what was last on the bus. That's
+    // usually 0x40, the high byte of the address.
+    fn input_open_bus_mask(&self, address: u16) -> u8 {
+        if address == 0x4017 && self.model.has_dip_switches() {
+            0
+        } else {
+            0b1110_0000
+        }
+    }
+
+    // The microphone is on bit 2 of 0x4016. DIP switches 1 and 2 are on
+    // bits 3 and 4 of 0x4016, and 3 to 8 are on bits 2 to 7 of 0x4017.
+    fn console_input_bits(&self, address: u16) -> u8 {
+        let mut v = 0;
+        if address == 0x4016 && self.model.has_microphone() && self.microphone {
+            v |= 0b0000_0100;
+        }
+        if self.model.has_dip_switches() {
+            v |= if address == 0x4016 {
+                (self.dip_switches & 0b11) << 3
+            } else {
+                self.dip_switches & 0b1111_1100
+            };
+        }
+        v
+    }
+
+    fn read_four_score(&mut self, address: u16) -> u8 {
+        let (port, first, second) = if address == 0x4016 {
+            (0, self.controller_0.button_states(), self.controller_2.button_states())
+        } else {
+            (1, self.controller_1.button_states(), self.controller_3.button_states())
+        };
+        match self.four_score {
+            Some(ref mut f) => f.read_register(port, first, second),
+            None => 0,
+        }
+    }
+
     fn oam_dma(&mut self, bank: u16) {
         self.cpu_stall_cycles += 513 + (self.cycles as usize % 2);
         for i in 0..256 {
@@ -120,15 +265,55 @@ impl Bus {
     }
 
     pub fn read_byte<T: Into<u16>>(&mut self, address: T) -> u8 {
+        let address = address.into();
         self.tick();
-        self.unclocked_read_byte(address.into())
+
+        // When the DMC halts the CPU to fetch a sample, the halted CPU repeats
+        // its read. This is visible for registers with read side effects, like
+        // $2007 and $4016/$4017. The stall happens here, in the middle of the
+        // instruction, so the PPU and APU see the fetch at the right time.
+        if self.apu.dmc.reset_dma_halt() {
+            self.unclocked_read_byte(address);
+            let absorbed = self.dmc_write_cycles.min(2);
+            self.dmc_write_cycles = 0;
+            self.tick_dmc_stall(absorbed);
+        }
+
+        self.unclocked_read_byte(address)
     }
 
     pub fn write_byte<T: Into<u16>>(&mut self, address: T, value: u8) {
         self.tick();
+        if self.apu.dmc.dma_pending() {
+            self.dmc_write_cycles += 1;
+        }
         self.unclocked_write_byte(address.into(), value)
     }
 
+    // A cycle of the CPU being stalled by OAM DMA. A DMC fetch during it
+    // only needs to wait for alignment, as the CPU is already halted.
+    pub fn tick_oam_dma_cycle(&mut self) {
+        self.tick();
+        if self.apu.dmc.reset_dma_halt() {
+            self.dmc_write_cycles = 0;
+            self.tick_dmc_stall(2);
+        }
+    }
+
+    // DMC DMA halts the CPU, takes a dummy cycle, then fetches on an odd
+    // cycle, taking one more cycle to align if needed. The CPU can only be
+    // halted on a read, so writes made while the DMA waits stand in for the
+    // halt and dummy cycles. That gives 4 cycles when the fetch falls on a
+    // read, 3 on a write and 2 on the second of two writes.
+    fn tick_dmc_stall(&mut self, absorbed: u64) {
+        let waiting = 2 - absorbed;
+        let fetch_cycle = self.cycles + waiting + 1;
+        let alignment = if fetch_cycle & 1 == 0 { 1 } else { 0 };
+        for _ in 0..waiting + alignment + 1 {
+            self.tick();
+        }
+    }
+
     pub fn read_noncontinuous_word<T: Into<u16>, U: Into<u16>>(&mut self, a: T, b: U) -> u16 {
         (self.read_byte(a) as u16) | (self.read_byte(b) as u16) << 8
     }
@@ -144,6 +329,10 @@ impl Bus {
         let c = self.cycles;
         self.apu.tick(c);
 
+        if let Some(ref c) = self.cartridge {
+            c.borrow_mut().tick();
+        }
+
         self.nmi.tick();
 
         // Roughly 3x per frame. I made this number up.
@@ -180,25 +369,403 @@ impl Bus {
             },
             PpuResult::Draw => {
                 self.draw = true;
+                self.controller_0.tick_frame();
+                self.controller_1.tick_frame();
+                self.controller_2.tick_frame();
+                self.controller_3.tick_frame();
             }
             PpuResult::None => {}
         }
     }
 
-    pub fn load_rom_from_memory(&mut self, data: &[u8]) {
-        let c = Rc::new(RefCell::new(Cartridge::new(data)));
+    pub fn load_rom_from_memory(&mut self, data: &[u8]) -> Result<(), RomError> {
+        self.insert_cartridge(Cartridge::try_new(data)?);
+        Ok(())
+    }
+
+    pub fn load_fds_from_memory(&mut self, bios: &[u8], disk: &[u8]) -> Result<(), RomError> {
+        self.insert_cartridge(Cartridge::try_new_fds(bios, disk)?);
+        Ok(())
+    }
+
+    // Moves out what's been produced for the frontend, leaving empty buffers
+    // that can't be drawn into until restore_output puts some back
+    pub fn take_output(&mut self) -> Output {
+        let renderer = &mut self.ppu.renderer;
+        Output {
+            pixels: mem::take(&mut renderer.pixels),
+            background_layer: mem::take(&mut renderer.background_layer),
+            sprite_layer: mem::take(&mut renderer.sprite_layer),
+            audio: mem::take(&mut self.apu.buffer),
+            register_log: self.apu.take_register_log(),
+        }
+    }
+
+    pub fn restore_output(&mut self, output: Output) {
+        self.ppu.renderer.pixels = output.pixels;
+        self.ppu.renderer.background_layer = output.background_layer;
+        self.ppu.renderer.sprite_layer = output.sprite_layer;
+        self.apu.buffer = output.audio;
+        self.apu.restore_register_log(output.register_log);
+    }
+
+    // A cloned bus shares its cartridge with the original. This gives it a
+    // copy of its own, so the clone can be used as a snapshot.
+    pub fn detach_cartridge(&mut self) {
+        if let Some(c) = self.cartridge.take() {
+            let copy = c.borrow().clone();
+            self.insert_cartridge(copy);
+        }
+    }
+
+    fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        let c = Rc::new(RefCell::new(cartridge));
         self.ppu.registers.vram.set_cartridge(c.clone());
-        self.apu.dmc.set_cartridge(c.clone());
+        self.apu.set_cartridge(c.clone());
         self.cartridge = Some(c);
     }
 
+    // Takes effect at the next power on
+    pub fn set_ram_init(&mut self, ram_init: RamInit) {
+        self.ram_init = ram_init;
+    }
+
+    pub fn power_on(&mut self) {
+        self.ram_init.fill(&mut self.ram);
+        if let Some(ref cartridge) = self.cartridge {
+            cartridge.borrow_mut().power_on(self.ram_init);
+        }
+        self.apu.power_on();
+        self.ppu.power_on();
+    }
+
+    // A soft reset leaves RAM as it was. Cartridges aren't wired to the
+    // reset button, so mappers and their PRG-RAM carry on as they were too.
     pub fn reset(&mut self) {
         self.apu.reset();
-        // TODO - PPU and cartridge reset?
+        self.ppu.reset();
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use controller::Button;
+    use paddle::Paddle;
+    use zapper::Zapper;
+
+    fn build_bus() -> Bus {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x02, // Two pages of PRG-ROM
+            0x00, // Zero pages CHR-ROM means use CHR-RAM
+            0x01, // Vertical mirroring
+            0x00,
+            0x01, // One page of PRG-RAM
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+
+        // add the PRG-ROM
+        data.extend_from_slice(&[0u8; 2 * 0x4000]);
+
+        let mut bus = Bus::new();
+        bus.load_rom_from_memory(&data).unwrap();
+        bus
+    }
+
+    fn prepare_ppu_data_read(bus: &mut Bus) {
+        bus.ppu.registers.vram.write_byte(0x2000, 1);
+        bus.ppu.registers.vram.write_byte(0x2001, 2);
+        bus.ppu.registers.vram.write_byte(0x2002, 3);
+        bus.unclocked_write_byte(0x2006, 0x20);
+        bus.unclocked_write_byte(0x2006, 0x00);
+        bus.unclocked_read_byte(0x2007); // Prime the read buffer
+    }
+
+    #[test]
+    fn test_ppu_data_read() {
+        let mut bus = build_bus();
+        prepare_ppu_data_read(&mut bus);
+        assert_eq!(bus.read_byte(0x2007u16), 1);
+        assert_eq!(bus.ppu.registers.v_address.address(), 0x2002);
+    }
+
+    #[test]
+    fn test_dmc_dma_repeats_ppu_data_read() {
+        let mut bus = build_bus();
+        prepare_ppu_data_read(&mut bus);
+
+        // Start a one byte sample. The DMC fetches on odd cycles, so the
+        // fetch lands on the clock of the next read.
+        bus.cycles = 0;
+        bus.apu.write_register(0x4010, 0x0F, 0);
+        bus.apu.write_register(0x4012, 0x00, 0);
+        bus.apu.write_register(0x4013, 0x00, 0);
+        bus.apu.write_register(0x4015, 0x10, 0);
+
+        // The repeated read consumes the buffered byte and increments v again.
+        assert_eq!(bus.read_byte(0x2007u16), 2);
+        assert_eq!(bus.ppu.registers.v_address.address(), 0x2003);
+
+        // The halt only repeats one read per fetch
+        assert_eq!(bus.read_byte(0x2007u16), 3);
+        assert_eq!(bus.ppu.registers.v_address.address(), 0x2004);
+    }
+
+    fn nmi_after_status_read(start_dot: usize) -> bool {
+        let mut bus = build_bus();
+        bus.ppu.write_register(0x2000, 0x80);
+        bus.ppu.renderer.scanline = 241;
+        bus.ppu.renderer.dot = start_dot;
+        bus.read_byte(0x2002u16);
+        bus.tick();
+        bus.nmi.ready()
+    }
+
+    #[test]
+    fn test_status_read_cancels_nmi() {
+        // The read comes after three dots, so starting from dot 0 it's one
+        // dot after vblank is set, and from dot 1 it's two
+        assert!(!nmi_after_status_read(0));
+        assert!(nmi_after_status_read(1));
+    }
+
+    #[test]
+    fn test_controller_strobe_is_shared() {
+        let mut bus = build_bus();
+        bus.controller_0.set_button_state(Button::A, true);
+        bus.controller_0.set_button_state(Button::Start, true);
+        bus.controller_1.set_button_state(Button::B, true);
+        bus.controller_1.set_button_state(Button::Right, true);
+
+        // Leave both controllers part way through a read
+        bus.unclocked_write_byte(0x4016, 1);
+        bus.unclocked_write_byte(0x4016, 0);
+        bus.unclocked_read_byte(0x4016);
+        bus.unclocked_read_byte(0x4017);
+        bus.unclocked_read_byte(0x4017);
+
+        // A single strobe rewinds both of them
+        bus.unclocked_write_byte(0x4016, 1);
+        bus.unclocked_write_byte(0x4016, 0);
+
+        // Order: A, B, Select, Start, Up, Down, Left, Right
+        let expected_0 = [1, 0, 0, 1, 0, 0, 0, 0];
+        let expected_1 = [0, 1, 0, 0, 0, 0, 0, 1];
+        for i in 0..8 {
+            assert_eq!(bus.unclocked_read_byte(0x4016) & 1, expected_0[i]);
+            assert_eq!(bus.unclocked_read_byte(0x4017) & 1, expected_1[i]);
+        }
+    }
+
+    #[test]
+    fn test_zapper() {
+        let mut bus = build_bus();
+        bus.controller_1 = InputDevice::Zapper(Zapper::new());
+        assert_eq!(bus.unclocked_read_byte(0x4017) & 0b0001_1000, 0b0000_1000);
+
+        if let InputDevice::Zapper(ref mut z) = bus.controller_1 {
+            z.set_trigger(true);
+            z.set_light(true);
+        }
+        assert_eq!(bus.unclocked_read_byte(0x4017) & 0b0001_1000, 0b0001_0000);
+
+        // Strobing and reading doesn't shift anything out
+        bus.unclocked_write_byte(0x4016, 1);
+        bus.unclocked_write_byte(0x4016, 0);
+        assert_eq!(bus.unclocked_read_byte(0x4017) & 0b0001_1000, 0b0001_0000);
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut bus = build_bus();
+        bus.set_four_score(true);
+        bus.controller_0.set_button_state(Button::A, true);
+        bus.controller_1.set_button_state(Button::B, true);
+        bus.controller_2.set_button_state(Button::Select, true);
+        bus.controller_3.set_button_state(Button::Right, true);
+
+        bus.unclocked_write_byte(0x4016, 1);
+        bus.unclocked_write_byte(0x4016, 0);
+
+        // Controllers 1 and 3 then the signature, and 2 and 4 then theirs
+        let expected_0 = [
+            1, 0, 0, 0, 0, 0, 0, 0, //
+            0, 0, 1, 0, 0, 0, 0, 0, //
+            0, 0, 0, 0, 1, 0, 0, 0,
+        ];
+        let expected_1 = [
+            0, 1, 0, 0, 0, 0, 0, 0, //
+            0, 0, 0, 0, 0, 0, 0, 1, //
+            0, 0, 0, 0, 0, 1, 0, 0,
+        ];
+        for i in 0..24 {
+            assert_eq!(bus.unclocked_read_byte(0x4016) & 1, expected_0[i]);
+            assert_eq!(bus.unclocked_read_byte(0x4017) & 1, expected_1[i]);
+        }
+        assert_eq!(bus.unclocked_read_byte(0x4016) & 1, 1);
+        assert_eq!(bus.unclocked_read_byte(0x4017) & 1, 1);
+
+        // Without it, controller 1 reads 1s after its 8 buttons
+        bus.set_four_score(false);
+        bus.unclocked_write_byte(0x4016, 1);
+        bus.unclocked_write_byte(0x4016, 0);
+        for _ in 0..8 {
+            bus.unclocked_read_byte(0x4016);
+        }
+        assert_eq!(bus.unclocked_read_byte(0x4016) & 1, 1);
+    }
+
+    #[test]
+    fn test_paddle() {
+        let mut bus = build_bus();
+        let mut paddle = Paddle::new();
+        paddle.set_position(0x80);
+        bus.controller_1 = InputDevice::Paddle(paddle);
+
+        bus.unclocked_write_byte(0x4016, 1);
+        bus.unclocked_write_byte(0x4016, 0);
+        assert_eq!(bus.unclocked_read_byte(0x4017) & 0b0001_0000, 0);
+        assert_eq!(bus.unclocked_read_byte(0x4017) & 0b0001_0000, 0b0001_0000);
+    }
+
+    #[test]
+    fn test_model() {
+        let mut bus = build_bus();
+        assert_eq!(bus.model(), ConsoleModel::Nes);
+        bus.set_microphone(true);
+        bus.set_dip_switches(0xFF);
+        assert_eq!(bus.unclocked_read_byte(0x4016) & 0b0001_1100, 0);
+        assert_eq!(bus.unclocked_read_byte(0x4017) & 0b1111_1100, 0);
+
+        bus.set_model(ConsoleModel::Famicom);
+        assert_eq!(bus.unclocked_read_byte(0x4016) & 0b0001_1100, 0b0000_0100);
+
+        bus.set_model(ConsoleModel::VsSystem);
+        bus.set_dip_switches(0b1010_0110);
+        assert_eq!(bus.unclocked_read_byte(0x4016) & 0b0001_1100, 0b0001_0000);
+        // The switches drive the bits open bus would otherwise fill
+        bus.write_byte(0x0000u16, 0xFF);
+        assert_eq!(bus.unclocked_read_byte(0x4017) & 0b1111_1100, 0b1010_0100);
+    }
+
+    // Starts a one byte sample which is fetched on the next cycle
+    fn start_dmc_sample(bus: &mut Bus) {
+        bus.cycles = 0;
+        bus.apu.write_register(0x4010, 0x0F, 0);
+        bus.apu.write_register(0x4012, 0x00, 0);
+        bus.apu.write_register(0x4013, 0x00, 0);
+        bus.apu.write_register(0x4015, 0x10, 0);
+    }
+
+    #[test]
+    fn test_dmc_stall_on_read() {
+        let mut bus = build_bus();
+        start_dmc_sample(&mut bus);
+        bus.read_byte(0u16);
+        assert_eq!(bus.cycles, 1 + 4);
+    }
+
+    #[test]
+    fn test_dmc_stall_on_write() {
+        let mut bus = build_bus();
+        start_dmc_sample(&mut bus);
+        bus.write_byte(0u16, 0);
+        bus.read_byte(0u16);
+        assert_eq!(bus.cycles, 2 + 3);
+    }
+
+    #[test]
+    fn test_dmc_stall_on_second_write() {
+        let mut bus = build_bus();
+        start_dmc_sample(&mut bus);
+        bus.write_byte(0u16, 0);
+        bus.write_byte(0u16, 0);
+        bus.read_byte(0u16);
+        assert_eq!(bus.cycles, 3 + 2);
+
+        // Only reads are delayed by the fetch
+        bus.read_byte(0u16);
+        assert_eq!(bus.cycles, 6);
+    }
+
+    #[test]
+    fn test_dmc_stall_during_oam_dma() {
+        let mut bus = build_bus();
+        start_dmc_sample(&mut bus);
+        bus.tick_oam_dma_cycle();
+        assert_eq!(bus.cycles, 1 + 2);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = build_bus();
+        bus.write_byte(0x0000u16, 0x5A);
+        assert_eq!(bus.read_byte(0x4018u16), 0x5A);
+        assert_eq!(bus.read_byte(0x4000u16), 0x5A);
+
+        // Reads also leave their value on the bus
+        bus.write_byte(0x0001u16, 0xA5);
+        bus.read_byte(0x0000u16);
+        assert_eq!(bus.read_byte(0x401Fu16), 0x5A);
+
+        // As are parts of the cartridge's space the board doesn't decode
+        assert_eq!(bus.read_byte(0x5000u16), 0x5A);
+        bus.write_byte(0x0000u16, 0xC3);
+        assert_eq!(bus.read_byte(0x4020u16), 0xC3);
+
+        // Only the bottom bits of the input ports are driven
+        bus.write_byte(0x0000u16, 0xA5);
+        assert_eq!(bus.read_byte(0x4016u16), 0xA0);
+        bus.write_byte(0x0000u16, 0x40);
+        assert_eq!(bus.read_byte(0x4017u16), 0x40);
+    }
+
+    #[test]
+    fn test_apu_test_registers() {
+        // $4018-$401F are disabled on a retail console, so neither reads nor
+        // writes reach the cartridge, which would panic on them
+        let mut bus = build_bus();
+        bus.write_byte(0x401Au16, 0x77);
+        bus.write_byte(0x0000u16, 0x5A);
+        assert_eq!(bus.read_byte(0x401Au16), 0x5A);
+        assert_eq!(bus.read_byte(0x4018u16), 0x5A);
+    }
+
+    #[test]
+    fn test_peek_byte() {
+        let mut bus = build_bus();
+        bus.write_byte(0x0012u16, 0x34);
+        bus.write_byte(0x6000u16, 0x56);
+        assert_eq!(bus.peek_byte(0x0812), 0x34);
+        assert_eq!(bus.peek_byte(0x6000), 0x56);
+
+        // Reading $2002 would clear the vblank flag
+        bus.ppu.registers.status.set_vblank(true);
+        bus.write_byte(0x0000u16, 0x12);
+        assert_eq!(bus.peek_byte(0x2002), 0x12);
+        assert_eq!(bus.read_byte(0x2002u16) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn test_ram_init() {
+        let mut bus = build_bus();
+        bus.set_ram_init(RamInit::Pattern);
+        bus.ram[0] = 0x12;
+        bus.ram[4] = 0x34;
+        bus.reset();
+        assert_eq!(bus.ram[..5], [0x12, 0, 0, 0, 0x34]);
+
+        bus.power_on();
+        assert_eq!(bus.ram[..5], [0, 0, 0, 0, 0xFF]);
+    }
 }
@@ -1,24 +1,73 @@
 use super::cartridge_header::CartridgeHeader;
 use super::pager::Pager;
+use super::RomError;
+use std::rc::Rc;
 
+// ROM never changes, so clones share it
+#[derive(Clone)]
 pub struct CartridgeData {
     pub header: CartridgeHeader,
-    pub prg_rom: Pager,
+    pub prg_rom: Rc<Pager>,
     pub prg_ram: Pager,
-    pub chr_rom: Pager,
+    pub chr_rom: Rc<Pager>,
     pub chr_ram: Pager,
 }
 
 impl CartridgeData {
-    pub fn new(data: &[u8]) -> Self {
-        let header = CartridgeHeader::new(data);
+    pub fn try_new(data: &[u8]) -> Result<Self, RomError> {
+        let header = CartridgeHeader::try_new(data)?;
 
-        CartridgeData {
+        // The PlayChoice-10's INST-ROM and PROM drive its menu hardware,
+        // which isn't emulated, so they're skipped. Anything else past the
+        // end of CHR-ROM is ignored.
+        if data.len() < header.chr_rom_range().end.max(header.inst_rom_range().end) {
+            return Err(RomError::SizeMismatch);
+        }
+
+        Ok(CartridgeData {
             header: header,
-            prg_rom: Pager::new(data[header.prg_rom_range()].to_vec()),
-            chr_rom: Pager::new(data[header.chr_rom_range()].to_vec()),
+            prg_rom: Rc::new(Pager::new(data[header.prg_rom_range()].to_vec())),
+            chr_rom: Rc::new(Pager::new(data[header.chr_rom_range()].to_vec())),
             prg_ram: Pager::new(vec![0u8; header.prg_ram_bytes()]),
             chr_ram: Pager::new(vec![0u8; header.chr_ram_bytes()]),
+        })
+    }
+
+    // Battery-backed PRG-RAM keeps its contents, so it isn't included
+    pub fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        if self.header.battery {
+            None
+        } else {
+            Some(&mut self.prg_ram.data)
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_playchoice10() {
+        let mut data = vec![
+            0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        data.extend_from_slice(&[0x11; 2 * 0x4000]);
+        data.extend_from_slice(&[0x22; 0x2000]);
+        data.extend_from_slice(&[0x33; 0x2000]); // INST-ROM
+        data.extend_from_slice(&[0x44; 32]); // PROM
+
+        let cartridge = CartridgeData::try_new(&data).unwrap();
+        assert_eq!(cartridge.prg_rom.data.len(), 2 * 0x4000);
+        assert!(cartridge.prg_rom.data.iter().all(|&b| b == 0x11));
+        assert_eq!(cartridge.chr_rom.data.len(), 0x2000);
+        assert!(cartridge.chr_rom.data.iter().all(|&b| b == 0x22));
+
+        // Clones share ROM but not RAM
+        let clone = cartridge.clone();
+        assert!(Rc::ptr_eq(&clone.prg_rom, &cartridge.prg_rom));
+        assert!(Rc::ptr_eq(&clone.chr_rom, &cartridge.chr_rom));
+        assert!(clone.prg_ram.data.as_ptr() != cartridge.prg_ram.data.as_ptr());
+    }
+}
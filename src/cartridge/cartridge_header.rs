@@ -1,10 +1,11 @@
-use super::Mirroring;
+use super::{Mirroring, RomError};
 use std::ops::Range;
 
 const PRG_ROM_PAGE_SIZE: usize = 0x4000;
 const PRG_RAM_PAGE_SIZE: usize = 0x2000;
 const CHR_ROM_PAGE_SIZE: usize = 0x2000;
 const CHR_RAM_PAGE_SIZE: usize = 0x2000;
+const INST_ROM_SIZE: usize = 0x2000;
 
 #[derive(Copy, Clone)]
 pub struct CartridgeHeader {
@@ -14,13 +15,18 @@ pub struct CartridgeHeader {
     pub prg_ram_pages: usize,
     pub chr_rom_pages: usize,
     pub preamble: bool,
+    pub playchoice10: bool,
+    // PRG-RAM keeps its contents with the power off
+    pub battery: bool,
 }
 
 impl CartridgeHeader {
     pub fn new(data: &[u8]) -> Self {
         CartridgeHeader {
             preamble: data[0..4] == [0x4e, 0x45, 0x53, 0x1a],
-            mirroring: if data[6] & 1 == 0 {
+            mirroring: if data[6] & 0b1000 != 0 {
+                Mirroring::FourScreen
+            } else if data[6] & 1 == 0 {
                 Mirroring::Horizontal
             } else {
                 Mirroring::Vertical
@@ -29,6 +35,20 @@ impl CartridgeHeader {
             chr_rom_pages: data[5] as usize,
             prg_ram_pages: if data[8] == 0 { 1 } else { data[8] } as usize,
             mapper_number: (data[6] >> 4) | (data[7] & 0xf0),
+            playchoice10: data[7] & 0b10 != 0,
+            battery: data[6] & 0b10 != 0,
+        }
+    }
+
+    pub fn try_new(data: &[u8]) -> Result<Self, RomError> {
+        if data.len() < 16 {
+            return Err(RomError::TooShort);
+        }
+        let header = CartridgeHeader::new(data);
+        if header.preamble {
+            Ok(header)
+        } else {
+            Err(RomError::BadMagic)
         }
     }
 
@@ -41,6 +61,17 @@ impl CartridgeHeader {
         prg_range.end..prg_range.end + self.chr_rom_bytes()
     }
 
+    // PlayChoice-10 ROMs follow CHR-ROM with the INST-ROM, holding the
+    // game's menu instructions, then a PROM
+    pub fn inst_rom_range(&self) -> Range<usize> {
+        let chr_range = self.chr_rom_range();
+        if self.playchoice10 {
+            chr_range.end..chr_range.end + INST_ROM_SIZE
+        } else {
+            chr_range.end..chr_range.end
+        }
+    }
+
     pub fn prg_rom_bytes(&self) -> usize {
         self.prg_rom_pages * PRG_ROM_PAGE_SIZE
     }
@@ -92,5 +123,27 @@ mod test {
         assert_eq!(0x13 * PRG_RAM_PAGE_SIZE, header.prg_ram_bytes());
 
         assert_eq!(0x01, header.mapper_number);
+        assert!(!header.playchoice10);
+        assert!(header.inst_rom_range().is_empty());
+    }
+
+    #[test]
+    fn test_playchoice10() {
+        let mut data = HEADER;
+        data[7] |= 0b10;
+        let header = CartridgeHeader::new(&data);
+        assert!(header.playchoice10);
+        assert_eq!(0x01, header.mapper_number);
+
+        let chr_end = header.chr_rom_range().end;
+        assert_eq!(chr_end..chr_end + INST_ROM_SIZE, header.inst_rom_range());
+    }
+
+    #[test]
+    fn test_four_screen() {
+        let mut data = HEADER;
+        data[6] |= 0b1000;
+        let header = CartridgeHeader::new(&data);
+        assert_eq!(Mirroring::FourScreen, header.mirroring);
     }
 }
@@ -0,0 +1,626 @@
+// Fds implements the Famicom Disk System RAM adapter and disk drive
+// https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System
+// https://wiki.nesdev.com/w/index.php/FDS_disk_format
+//
+// Disk images hold the blocks of each side without the gaps and CRCs that
+// are on a real disk, so they're added back when loading. Expansion audio
+// isn't implemented yet.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use super::mapper::{AddressKind, IrqState};
+use super::Mapper;
+use super::Mirroring;
+use super::RomError;
+
+const BIOS_SIZE: usize = 0x2000;
+const PRG_RAM_SIZE: usize = 0x8000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+const HEADER_SIZE: usize = 16;
+const DISK_SIDE_SIZE: usize = 65500;
+
+// Gaps are measured in bits on the disk
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+
+// The CRC isn't checked against anything, only read past
+const FAKE_CRC: [u8; 2] = [0x4D, 0x62];
+
+// The drive transfers a byte roughly every 150 CPU cycles, and takes a
+// while to get back to the start of the disk once it reaches the end.
+const CYCLES_PER_BYTE: usize = 150;
+const REWIND_CYCLES: usize = 50000;
+
+// How long a disk is ejected for when changing sides, so that the BIOS
+// notices the change. About a second.
+const EJECT_CYCLES: usize = 1_789_773;
+
+#[derive(Clone)]
+pub struct Fds {
+    // Shared by clones, as it never changes
+    bios: Rc<Vec<u8>>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+    sides: Vec<Vec<u8>>,
+    side: usize,
+    eject_cycles: usize,
+
+    // IRQ timer
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: Cell<bool>,
+
+    // Drive
+    disk_registers_enabled: bool,
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    mirroring: Mirroring,
+    crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: Cell<bool>,
+    transfer_complete: Cell<bool>,
+    read_data: u8,
+    write_data: u8,
+    position: usize,
+    delay: usize,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+}
+
+impl Fds {
+    pub fn try_new(bios: &[u8], disk: &[u8]) -> Result<Self, RomError> {
+        if bios.len() != BIOS_SIZE {
+            return Err(RomError::BadFdsBios);
+        }
+
+        Ok(Fds {
+            bios: Rc::new(bios.to_vec()),
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr_ram: vec![0; CHR_RAM_SIZE],
+            sides: parse_disk(disk)?,
+            side: 0,
+            eject_cycles: 0,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: Cell::new(false),
+            disk_registers_enabled: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            mirroring: Mirroring::Horizontal,
+            crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: Cell::new(false),
+            transfer_complete: Cell::new(false),
+            read_data: 0,
+            write_data: 0,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+        })
+    }
+
+    fn disk_inserted(&self) -> bool {
+        self.eject_cycles == 0
+    }
+
+    fn read_status(&self) -> u8 {
+        let status = self.status();
+        self.timer_irq.set(false);
+        self.disk_irq.set(false);
+        self.transfer_complete.set(false);
+        status
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if self.timer_irq.get() {
+            status |= 0b0000_0001;
+        }
+        if self.transfer_complete.get() {
+            status |= 0b0000_0010;
+        }
+        if self.end_of_head {
+            status |= 0b0100_0000;
+        }
+        status
+    }
+
+    fn read_data(&self) -> u8 {
+        self.transfer_complete.set(false);
+        self.disk_irq.set(false);
+        self.read_data
+    }
+
+    fn read_drive_status(&self) -> u8 {
+        let mut status = 0b0100_0000;
+        if !self.disk_inserted() {
+            status |= 0b0000_0101;
+        }
+        if !self.disk_inserted() || !self.scanning {
+            status |= 0b0000_0010;
+        }
+        status
+    }
+
+    fn write_irq_control(&mut self, value: u8) {
+        self.irq_repeat = value & 0b01 != 0;
+        self.irq_enabled = value & 0b10 != 0 && self.disk_registers_enabled;
+        if self.irq_enabled {
+            self.irq_counter = self.irq_reload;
+        } else {
+            self.timer_irq.set(false);
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.motor_on = value & 0b0000_0001 != 0;
+        self.reset_transfer = value & 0b0000_0010 != 0;
+        self.read_mode = value & 0b0000_0100 != 0;
+        self.mirroring = if value & 0b0000_1000 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+        self.crc_control = value & 0b0001_0000 != 0;
+        self.disk_ready = value & 0b0100_0000 != 0;
+        self.disk_irq_enabled = value & 0b1000_0000 != 0;
+        self.disk_irq.set(false);
+    }
+
+    fn tick_irq(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq.set(true);
+            self.irq_counter = self.irq_reload;
+            self.irq_enabled = self.irq_repeat;
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn tick_drive(&mut self) {
+        if self.eject_cycles > 0 {
+            self.eject_cycles -= 1;
+        }
+
+        if !self.disk_inserted() || !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        if self.read_mode {
+            self.read_byte();
+        } else {
+            self.write_byte();
+        }
+
+        self.position += 1;
+        if self.position >= self.sides[self.side].len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = CYCLES_PER_BYTE;
+        }
+    }
+
+    // Bytes are only passed on once the start mark at the end of a gap has
+    // been seen, and the start mark itself doesn't raise an IRQ.
+    fn read_byte(&mut self) {
+        let data = self.sides[self.side][self.position];
+        let mut irq = self.disk_irq_enabled;
+        if !self.disk_ready {
+            self.gap_ended = false;
+        } else if data != 0 && !self.gap_ended {
+            self.gap_ended = true;
+            irq = false;
+        }
+
+        if self.gap_ended {
+            self.transfer_complete.set(true);
+            self.read_data = data;
+            if irq {
+                self.disk_irq.set(true);
+            }
+        }
+    }
+
+    fn write_byte(&mut self) {
+        if !self.crc_control {
+            self.transfer_complete.set(true);
+            if self.disk_irq_enabled {
+                self.disk_irq.set(true);
+            }
+            self.sides[self.side][self.position] = if self.disk_ready {
+                self.write_data
+            } else {
+                0
+            };
+        }
+        self.gap_ended = false;
+    }
+}
+
+impl Mapper for Fds {
+    // The disks, and which side is in the drive, are left alone
+    fn reset(&mut self) {
+        self.irq_reload = 0;
+        self.irq_counter = 0;
+        self.irq_repeat = false;
+        self.irq_enabled = false;
+        self.timer_irq.set(false);
+        self.disk_registers_enabled = false;
+        self.motor_on = false;
+        self.reset_transfer = false;
+        self.read_mode = true;
+        self.mirroring = Mirroring::Horizontal;
+        self.crc_control = false;
+        self.disk_ready = false;
+        self.disk_irq_enabled = false;
+        self.disk_irq.set(false);
+        self.transfer_complete.set(false);
+        self.read_data = 0;
+        self.write_data = 0;
+        self.position = 0;
+        self.delay = 0;
+        self.end_of_head = true;
+        self.scanning = false;
+        self.gap_ended = false;
+    }
+
+    // The RAM adapter has no battery
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
+        let value = match address {
+            0x4030 if self.disk_registers_enabled => self.read_status(),
+            0x4031 if self.disk_registers_enabled => self.read_data(),
+            0x4032 if self.disk_registers_enabled => self.read_drive_status(),
+            // Battery is good
+            0x4033 if self.disk_registers_enabled => 0x80,
+            0x6000...0xDFFF => self.prg_ram[address as usize - 0x6000],
+            0xE000...0xFFFF => self.bios[address as usize - 0xE000],
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // Without acknowledging IRQs
+    fn peek_prg_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4030 if self.disk_registers_enabled => Some(self.status()),
+            0x4031 if self.disk_registers_enabled => Some(self.read_data),
+            _ => self.read_prg_byte(address),
+        }
+    }
+
+    fn write_prg_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (value as u16) << 8,
+            0x4022 => self.write_irq_control(value),
+            0x4023 => {
+                self.disk_registers_enabled = value & 1 != 0;
+                if !self.disk_registers_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq.set(false);
+                    self.disk_irq.set(false);
+                }
+            }
+            0x4024 if self.disk_registers_enabled => {
+                self.write_data = value;
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+            }
+            0x4025 if self.disk_registers_enabled => self.write_control(value),
+            0x6000...0xDFFF => self.prg_ram[address as usize - 0x6000] = value,
+            _ => (),
+        }
+    }
+
+    fn read_chr_byte(&self, address: u16) -> u8 {
+        self.chr_ram[address as usize]
+    }
+
+    fn write_chr_byte(&mut self, address: u16, value: u8) {
+        self.chr_ram[address as usize] = value;
+    }
+
+    fn address_kind(&self, address: u16) -> AddressKind {
+        match address {
+            0x0000...0x1FFF => AddressKind::ChrRam,
+            0x6000...0xDFFF => AddressKind::PrgRam,
+            0xE000...0xFFFF => AddressKind::PrgRom,
+            _ => AddressKind::Unmapped,
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_flag(&self) -> bool {
+        self.timer_irq.get() || self.disk_irq.get()
+    }
+
+    // Just the timer. The disk IRQ comes from transfers, not a counter.
+    fn irq_state(&self) -> Option<IrqState> {
+        Some(IrqState {
+            counter: self.irq_counter,
+            reload: self.irq_reload,
+            enabled: self.irq_enabled,
+            pending: self.timer_irq.get(),
+        })
+    }
+
+    fn tick(&mut self) {
+        self.tick_irq();
+        self.tick_drive();
+    }
+
+    // Sides the image doesn't have are ignored, leaving the disk as it is
+    fn set_disk_side(&mut self, side: u8) {
+        let side = side as usize;
+        if side >= self.sides.len() {
+            return;
+        }
+        self.side = side;
+        self.eject_cycles = EJECT_CYCLES;
+    }
+}
+
+// Images either have a 16 byte fwNES header or start with the first side
+fn parse_disk(data: &[u8]) -> Result<Vec<Vec<u8>>, RomError> {
+    let data = if data.starts_with(b"FDS\x1a") {
+        &data[HEADER_SIZE.min(data.len())..]
+    } else {
+        data
+    };
+    if data.is_empty() || data.len() % DISK_SIDE_SIZE != 0 {
+        return Err(RomError::BadDiskImage);
+    }
+
+    Ok(data.chunks(DISK_SIDE_SIZE).map(add_gaps).collect())
+}
+
+// Each block gets a start mark before it, and its CRC and a gap after
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut disk = vec![0; LEADING_GAP];
+    let mut i = 0;
+    while i < side.len() {
+        let length = match side[i] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            // The file size is at bytes 13 and 14 of the preceding file header
+            4 if i >= 16 => 1 + (side[i - 3] as usize | (side[i - 2] as usize) << 8),
+            _ => break,
+        };
+        let end = (i + length).min(side.len());
+
+        disk.push(0x80);
+        disk.extend_from_slice(&side[i..end]);
+        disk.extend_from_slice(&FAKE_CRC);
+        disk.extend_from_slice(&[0; BLOCK_GAP]);
+        i = end;
+    }
+    disk
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_disk(header: bool) -> Vec<u8> {
+        let mut disk = if header {
+            let mut h = b"FDS\x1a".to_vec();
+            h.push(1);
+            h.extend_from_slice(&[0; 11]);
+            h
+        } else {
+            vec![]
+        };
+
+        let mut side = vec![0; DISK_SIDE_SIZE];
+        side[0] = 1;
+        side[1..15].copy_from_slice(b"*NINTENDO-HVC*");
+        side[56] = 2;
+        side[57] = 1;
+        // A file header for a 3 byte file, then the file
+        side[58] = 3;
+        side[58 + 13] = 3;
+        side[74..78].copy_from_slice(&[4, 0xAA, 0xBB, 0xCC]);
+        disk.extend_from_slice(&side);
+        disk
+    }
+
+    fn build_fds() -> Fds {
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[BIOS_SIZE - 1] = 0xE0;
+        Fds::try_new(&bios, &build_disk(true)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_disk() {
+        let sides = parse_disk(&build_disk(true)).unwrap();
+        assert_eq!(sides, parse_disk(&build_disk(false)).unwrap());
+        assert_eq!(sides.len(), 1);
+
+        let side = &sides[0];
+        let blocks = [56, 2, 16, 4];
+        let length: usize = blocks.iter().map(|b| b + 3 + BLOCK_GAP).sum();
+        assert_eq!(side.len(), LEADING_GAP + length);
+        assert_eq!(side[LEADING_GAP - 1..LEADING_GAP + 2], [0, 0x80, 1]);
+
+        let file = side.len() - BLOCK_GAP - 2 - 4;
+        assert_eq!(side[file - 1..file + 4], [0x80, 4, 0xAA, 0xBB, 0xCC]);
+    }
+
+    #[test]
+    fn test_bad_images() {
+        let bios = vec![0; BIOS_SIZE];
+        let disk = build_disk(true);
+        assert_eq!(
+            Fds::try_new(&bios[1..], &disk).err(),
+            Some(RomError::BadFdsBios)
+        );
+        assert_eq!(
+            Fds::try_new(&bios, &disk[..disk.len() - 1]).err(),
+            Some(RomError::BadDiskImage)
+        );
+        assert_eq!(Fds::try_new(&bios, &disk[..4]).err(), Some(RomError::BadDiskImage));
+        assert_eq!(Fds::try_new(&bios, &[]).err(), Some(RomError::BadDiskImage));
+    }
+
+    #[test]
+    fn test_missing_disk_side() {
+        let mut fds = build_fds();
+        fds.set_disk_side(1);
+        assert_eq!(fds.side, 0);
+        assert!(fds.disk_inserted());
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut fds = build_fds();
+        fds.write_prg_byte(0x6000, 1);
+        fds.write_prg_byte(0xDFFF, 2);
+        fds.write_prg_byte(0xFFFF, 3);
+        assert_eq!(fds.read_prg_byte(0x6000), Some(1));
+        assert_eq!(fds.read_prg_byte(0xDFFF), Some(2));
+        assert_eq!(fds.read_prg_byte(0xFFFF), Some(0xE0));
+
+        fds.write_chr_byte(0x1FFF, 4);
+        assert_eq!(fds.read_chr_byte(0x1FFF), 4);
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut fds = build_fds();
+        fds.write_prg_byte(0x4023, 1);
+        fds.write_prg_byte(0x4020, 2);
+        fds.write_prg_byte(0x4021, 0);
+        fds.write_prg_byte(0x4022, 0b11);
+        for _ in 0..2 {
+            fds.tick();
+        }
+        assert!(!fds.irq_flag());
+        fds.tick();
+        assert!(fds.irq_flag());
+
+        // Peeking at the status doesn't acknowledge it
+        assert_eq!(fds.peek_prg_byte(0x4030).unwrap() & 1, 1);
+        assert!(fds.irq_flag());
+
+        // Reading the status acknowledges it, and repeat mode reloads it
+        assert_eq!(fds.read_prg_byte(0x4030).unwrap() & 1, 1);
+        assert!(!fds.irq_flag());
+        for _ in 0..3 {
+            fds.tick();
+        }
+        assert!(fds.irq_flag());
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut fds = build_fds();
+        fds.write_prg_byte(0x4023, 1);
+        fds.write_prg_byte(0x4025, 0b0010_0000);
+        assert_eq!(fds.mirroring(), Mirroring::Vertical);
+        fds.write_prg_byte(0x4025, 0b0010_1000);
+        assert_eq!(fds.mirroring(), Mirroring::Horizontal);
+    }
+
+    // Ticks until the drive has a byte, returning it
+    fn next_byte(fds: &mut Fds) -> u8 {
+        for _ in 0..REWIND_CYCLES + (LEADING_GAP + 1) * (CYCLES_PER_BYTE + 1) {
+            fds.tick();
+            if fds.read_prg_byte(0x4030).unwrap() & 0b10 != 0 {
+                return fds.read_prg_byte(0x4031).unwrap();
+            }
+        }
+        panic!("No byte was transferred")
+    }
+
+    #[test]
+    fn test_disk_read() {
+        let mut fds = build_fds();
+        fds.write_prg_byte(0x4023, 1);
+        assert_eq!(fds.read_prg_byte(0x4032).unwrap() & 0b111, 0b010);
+
+        // Motor on, read mode, then ready once the gap has started
+        fds.write_prg_byte(0x4025, 0b0010_0101);
+        fds.write_prg_byte(0x4025, 0b0110_0101);
+
+        // The start mark is transferred too, then the block follows
+        assert_eq!(next_byte(&mut fds), 0x80);
+        assert_eq!(next_byte(&mut fds), 1);
+        assert_eq!(fds.read_prg_byte(0x4032).unwrap() & 0b111, 0);
+        for &c in b"*NINTENDO-HVC*" {
+            assert_eq!(next_byte(&mut fds), c);
+        }
+    }
+
+    #[test]
+    fn test_disk_irq() {
+        let mut fds = build_fds();
+        fds.write_prg_byte(0x4023, 1);
+        fds.write_prg_byte(0x4025, 0b1110_0101);
+        while !fds.irq_flag() {
+            fds.tick();
+        }
+        // The first IRQ is for the block code after the start mark
+        assert_eq!(fds.read_prg_byte(0x4031), Some(1));
+        assert!(!fds.irq_flag());
+    }
+
+    #[test]
+    fn test_set_disk_side() {
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[0] = 1;
+        let mut disk = build_disk(false);
+        disk.extend(build_disk(false));
+        let mut fds = Fds::try_new(&bios, &disk).unwrap();
+        fds.write_prg_byte(0x4023, 1);
+
+        fds.set_disk_side(1);
+        assert_eq!(fds.side, 1);
+        assert_eq!(fds.read_prg_byte(0x4032).unwrap() & 0b001, 1);
+        for _ in 0..EJECT_CYCLES {
+            fds.tick();
+        }
+        assert_eq!(fds.read_prg_byte(0x4032).unwrap() & 0b001, 0);
+    }
+}
@@ -1,15 +1,101 @@
 use super::Mirroring;
 
-pub trait Mapper {
+// What's at an address in the cartridge's part of the CPU's address space,
+// $4020-$FFFF, or of the PPU's, $0000-$1FFF
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AddressKind {
+    PrgRom,
+    PrgRam,
+    ChrRom,
+    ChrRam,
+    Unmapped,
+}
+
+// The usual layout, with PRG-RAM at $6000-$7FFF and PRG-ROM from $8000
+pub fn standard_address_kind(address: u16, prg_ram: bool, chr_ram: bool) -> AddressKind {
+    match address {
+        0x0000...0x1FFF if chr_ram => AddressKind::ChrRam,
+        0x0000...0x1FFF => AddressKind::ChrRom,
+        0x6000...0x7FFF if prg_ram => AddressKind::PrgRam,
+        0x8000...0xFFFF => AddressKind::PrgRom,
+        _ => AddressKind::Unmapped,
+    }
+}
+
+// A mapper's IRQ counter, for debuggers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IrqState {
+    pub counter: u16,
+    // What the counter starts from again after it fires
+    pub reload: u16,
+    pub enabled: bool,
+    pub pending: bool,
+}
+
+// Lets a boxed mapper be cloned, for snapshots of the whole console
+pub trait MapperClone {
+    fn clone_box(&self) -> Box<dyn Mapper>;
+}
+
+impl<T: 'static + Mapper + Clone> MapperClone for T {
+    fn clone_box(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
+
+pub trait Mapper: MapperClone {
     fn signal_scanline(&mut self) {
         // A blank placeholder is fine for most mappers
     }
-    fn read_prg_byte(&self, address: u16) -> u8;
+    fn signal_ppu_address(&mut self, _address: u16) {
+        // Called with each address the PPU fetches from while rendering
+    }
+    // Cartridges see $4020-$FFFF. Reads of anything a board doesn't decode
+    // return None, leaving the bus to supply its open bus value, and writes
+    // are ignored.
+    fn read_prg_byte(&self, address: u16) -> Option<u8>;
     fn write_prg_byte(&mut self, address: u16, value: u8);
+    // For debuggers. Like read_prg_byte, but reading a register doesn't
+    // change anything.
+    fn peek_prg_byte(&self, address: u16) -> Option<u8> {
+        self.read_prg_byte(address)
+    }
     fn read_chr_byte(&self, address: u16) -> u8;
     fn write_chr_byte(&mut self, address: u16, value: u8);
     fn mirroring(&self) -> Mirroring;
+    fn address_kind(&self, address: u16) -> AddressKind;
     fn irq_flag(&self) -> bool {
         false
     }
+    fn irq_state(&self) -> Option<IrqState> {
+        // Only mappers with an IRQ counter have anything to report
+        None
+    }
+    fn tick(&mut self) {
+        // Called every CPU cycle, for mappers with cycle counters or audio
+    }
+    // Expansion audio, on the same 0.0 to 1.0 scale as the APU mixer so it
+    // can simply be added to the APU's output
+    fn mapper_audio_sample(&self) -> f32 {
+        0.0
+    }
+    fn set_disk_side(&mut self, _side: u8) {
+        // Only the Famicom Disk System has disks
+    }
+    fn set_bus_conflicts(&mut self, _enabled: bool) {
+        // For boards where only some copies have bus conflicts
+    }
+    // Puts the registers back how they were at power on. RAM is kept.
+    fn reset(&mut self) {
+        // Nothing to do for mappers without registers
+    }
+    // For boards that can put console nametable RAM in the pattern tables,
+    // which page of it is at a PPU address
+    fn chr_console_ram_page(&self, _address: u16) -> Option<u8> {
+        None
+    }
+    // PRG-RAM that loses its contents when the power is off
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
 }
@@ -2,11 +2,13 @@
 // http://wiki.nesdev.com/w/index.php/NROM
 
 use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
 use super::Mapper;
 use super::Mirroring;
 use super::pager::Page;
 use super::pager::PageSize;
 
+#[derive(Clone)]
 pub struct Mapper0 {
     data: CartridgeData,
 }
@@ -18,29 +20,30 @@ impl Mapper0 {
 }
 
 impl Mapper for Mapper0 {
-    fn read_prg_byte(&self, address: u16) -> u8 {
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
         match address {
-            0x6000...0x7FFF => self.data
+            0x6000...0x7FFF => Some(self.data
                 .prg_ram
-                .read(Page::First(PageSize::EightKb), address - 0x6000),
-            0x8000...0xBFFF => self.data
+                .read(Page::First(PageSize::EightKb), address - 0x6000)),
+            0x8000...0xBFFF => Some(self.data
                 .prg_rom
-                .read(Page::First(PageSize::SixteenKb), address - 0x8000),
-            0xC000...0xFFFF => self.data
+                .read(Page::First(PageSize::SixteenKb), address - 0x8000)),
+            0xC000...0xFFFF => Some(self.data
                 .prg_rom
-                .read(Page::Last(PageSize::SixteenKb), address - 0xC000),
-            a => panic!("bad address: {:04X}", a),
+                .read(Page::Last(PageSize::SixteenKb), address - 0xC000)),
+            _ => None,
         }
     }
 
     fn write_prg_byte(&mut self, address: u16, value: u8) {
-        match address {
-            0x6000...0x7FFF => {
-                self.data
-                    .prg_ram
-                    .write(Page::First(PageSize::EightKb), address - 0x6000, value)
-            }
-            _ => panic!("bad address"),
+        if let 0x6000...0x7FFF = address {
+            self.data
+                .prg_ram
+                .write(Page::First(PageSize::EightKb), address - 0x6000, value)
         }
     }
 
@@ -64,6 +67,10 @@ impl Mapper for Mapper0 {
         }
     }
 
+    fn address_kind(&self, address: u16) -> AddressKind {
+        standard_address_kind(address, true, self.data.header.chr_rom_pages == 0)
+    }
+
     fn mirroring(&self) -> Mirroring {
         self.data.header.mirroring
     }
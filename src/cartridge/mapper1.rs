@@ -2,6 +2,7 @@
 // https://wiki.nesdev.com/w/index.php/MMC1
 
 use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
 use super::Mapper;
 use super::Mirroring;
 use super::pager::Page;
@@ -38,9 +39,10 @@ bitfield!{
 impl ControlRegister {
     fn mirroring(&self) -> Mirroring {
         match self.nt_mode_id() {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
             2 => Mirroring::Vertical,
-            3 => Mirroring::Horizontal,
-            _ => panic!("Unsupported Mirroring"), // TODO: Implement additional modes
+            _ => Mirroring::Horizontal,
         }
     }
 
@@ -62,6 +64,7 @@ impl ControlRegister {
     }
 }
 
+#[derive(Clone)]
 struct ShiftRegister {
     value: u8,
     bit_index: u8,
@@ -95,11 +98,13 @@ impl ShiftRegister {
     }
 }
 
+#[derive(Clone)]
 pub struct Mapper1 {
     data: CartridgeData,
     shift: ShiftRegister,
     control: ControlRegister,
     prg_0: usize,
+    prg_ram_disabled: bool,
     chr_0: usize,
     chr_1: usize,
 }
@@ -113,6 +118,7 @@ impl Mapper1 {
             chr_0: 0,
             chr_1: 0,
             prg_0: 0,
+            prg_ram_disabled: false,
         }
     }
 
@@ -122,12 +128,26 @@ impl Mapper1 {
                 0x8000...0x9FFF => self.control = ControlRegister(shift_value),
                 0xA000...0xBFFF => self.chr_0 = shift_value as usize & 0b1_1111,
                 0xC000...0xDFFF => self.chr_1 = shift_value as usize & 0b1_1111,
-                0xE000...0xFFFF => self.prg_0 = shift_value as usize & 0b1111,
+                0xE000...0xFFFF => {
+                    self.prg_0 = shift_value as usize & 0b1111;
+                    self.prg_ram_disabled = shift_value & 0b1_0000 != 0;
+                }
                 _ => panic!("Invalid address"),
             }
         }
     }
 
+    // Bit 4 of the PRG bank disables PRG-RAM. SNROM boards, with CHR-RAM,
+    // also wire bit 4 of the first CHR bank to the RAM's enable line. Boards
+    // with more than 256kb of PRG-ROM, like SUROM and SXROM, use that bit to
+    // select the PRG-ROM half instead.
+    fn prg_ram_enabled(&self) -> bool {
+        let header = &self.data.header;
+        let snrom = header.chr_rom_pages == 0 && header.prg_rom_pages <= 16;
+        let snrom_disabled = snrom && self.chr_0 & 0b1_0000 != 0;
+        !self.prg_ram_disabled && !snrom_disabled
+    }
+
     fn read_paged_prg_ram(&self, offset: u16) -> u8 {
         self.data
             .prg_ram
@@ -193,20 +213,32 @@ impl Mapper1 {
 }
 
 impl Mapper for Mapper1 {
-    fn read_prg_byte(&self, address: u16) -> u8 {
+    fn reset(&mut self) {
+        *self = Mapper1::new(self.data.clone());
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
         match address {
-            0x6000...0x7FFF => self.read_paged_prg_ram(address - 0x6000),
-            0x8000...0xBFFF => self.read_paged_prg_rom(AddressRange::Low, address - 0x8000),
-            0xC000...0xFFFF => self.read_paged_prg_rom(AddressRange::High, address - 0xC000),
-            _ => panic!("bad address"),
+            0x6000...0x7FFF if self.prg_ram_enabled() => {
+                Some(self.read_paged_prg_ram(address - 0x6000))
+            }
+            0x8000...0xBFFF => Some(self.read_paged_prg_rom(AddressRange::Low, address - 0x8000)),
+            0xC000...0xFFFF => Some(self.read_paged_prg_rom(AddressRange::High, address - 0xC000)),
+            _ => None,
         }
     }
 
     fn write_prg_byte(&mut self, address: u16, value: u8) {
         match address {
-            0x6000...0x7FFF => self.write_paged_prg_ram(address - 0x6000, value),
+            0x6000...0x7FFF if self.prg_ram_enabled() => {
+                self.write_paged_prg_ram(address - 0x6000, value)
+            }
             0x8000...0xFFFF => self.write_shift(address, value),
-            _ => panic!("bad address"),
+            _ => (),
         }
     }
 
@@ -228,6 +260,11 @@ impl Mapper for Mapper1 {
         }
     }
 
+    fn address_kind(&self, address: u16) -> AddressKind {
+        let chr_ram = self.data.header.chr_rom_pages == 0;
+        standard_address_kind(address, self.prg_ram_enabled(), chr_ram)
+    }
+
     fn mirroring(&self) -> Mirroring {
         // Todo - what about the mirroring mode from the ines file header?
         self.control.mirroring()
@@ -237,6 +274,7 @@ impl Mapper for Mapper1 {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::rc::Rc;
 
     #[test]
     fn test_shift() {
@@ -291,7 +329,7 @@ mod test {
             data.push(i as u8);
         }
 
-        CartridgeData::new(&data)
+        CartridgeData::try_new(&data).unwrap()
     }
 
     fn configure_mapper(mapper: &mut Mapper1, address: u16, value: u8) {
@@ -315,6 +353,11 @@ mod test {
         assert_eq!(mapper.control.mirroring(), Mirroring::Vertical);
         assert_eq!(mapper.control.prg_mode(), PrgMode::Consecutive);
         assert_eq!(mapper.control.chr_mode(), ChrMode::NonConsecutive);
+
+        configure_mapper(&mut mapper, 0x8000, 0b00000);
+        assert_eq!(mapper.control.mirroring(), Mirroring::SingleScreenLower);
+        configure_mapper(&mut mapper, 0x8000, 0b00001);
+        assert_eq!(mapper.control.mirroring(), Mirroring::SingleScreenUpper);
     }
 
     #[test]
@@ -348,7 +391,51 @@ mod test {
     fn test_prg_ram() {
         let mut mapper = Mapper1::new(build_cartridge_data());
         mapper.write_prg_byte(0x6001, 0xFA);
-        assert_eq!(mapper.read_prg_byte(0x6001), 0xFA);
+        assert_eq!(mapper.read_prg_byte(0x6001), Some(0xFA));
+    }
+
+    #[test]
+    fn test_prg_ram_disabled() {
+        let mut mapper = Mapper1::new(build_cartridge_data());
+        mapper.write_prg_byte(0x6001, 0xFA);
+        configure_mapper(&mut mapper, 0xE000, 0b1_0011);
+        assert_eq!(mapper.prg_0, 3);
+
+        // Disabled RAM is open bus, and writes to it are ignored
+        mapper.write_prg_byte(0x6001, 0x12);
+        assert_eq!(mapper.read_prg_byte(0x6001), None);
+        assert_eq!(mapper.address_kind(0x6001), AddressKind::Unmapped);
+
+        configure_mapper(&mut mapper, 0xE000, 0b0_0011);
+        assert_eq!(mapper.read_prg_byte(0x6001), Some(0xFA));
+    }
+
+    #[test]
+    fn test_snrom_prg_ram_disabled() {
+        let mut data = build_cartridge_data();
+        data.header.chr_rom_pages = 0;
+        let mut mapper = Mapper1::new(data);
+        mapper.write_prg_byte(0x6001, 0xFA);
+
+        configure_mapper(&mut mapper, 0xA000, 0b1_0000);
+        mapper.write_prg_byte(0x6001, 0x12);
+        assert_eq!(mapper.read_prg_byte(0x6001), None);
+
+        configure_mapper(&mut mapper, 0xA000, 0b0_0000);
+        assert_eq!(mapper.read_prg_byte(0x6001), Some(0xFA));
+    }
+
+    #[test]
+    fn test_surom_prg_ram_enabled() {
+        // 512kb of PRG-ROM, where the CHR bank bit picks the PRG-ROM half
+        let mut data = build_cartridge_data();
+        data.header.chr_rom_pages = 0;
+        data.header.prg_rom_pages = 32;
+        let mut mapper = Mapper1::new(data);
+        mapper.write_prg_byte(0x6001, 0xFA);
+
+        configure_mapper(&mut mapper, 0xA000, 0b1_0000);
+        assert_eq!(mapper.read_prg_byte(0x6001), Some(0xFA));
     }
 
     #[test]
@@ -360,12 +447,12 @@ mod test {
         assert_eq!(mapper.prg_0, 3);
 
         // Test the low addr range
-        mapper.data.prg_rom.data[1] = 0xFC;
-        assert_eq!(mapper.read_prg_byte(0x8001), 0xFC);
+        Rc::make_mut(&mut mapper.data.prg_rom).data[1] = 0xFC;
+        assert_eq!(mapper.read_prg_byte(0x8001), Some(0xFC));
 
         // Test the high addr range
-        mapper.data.prg_rom.data[PageSize::SixteenKb as usize * 3 + 5] = 0xFB;
-        assert_eq!(mapper.read_prg_byte(0xC005), 0xFB);
+        Rc::make_mut(&mut mapper.data.prg_rom).data[PageSize::SixteenKb as usize * 3 + 5] = 0xFB;
+        assert_eq!(mapper.read_prg_byte(0xC005), Some(0xFB));
     }
 
     #[test]
@@ -379,12 +466,12 @@ mod test {
         assert_eq!(mapper.chr_1, 5);
 
         // Test the low addr range
-        mapper.data.chr_rom.data[PageSize::FourKb as usize * 3 + 8] = 0xFC;
+        Rc::make_mut(&mut mapper.data.chr_rom).data[PageSize::FourKb as usize * 3 + 8] = 0xFC;
         assert_eq!(mapper.read_chr_byte(0x0008), 0xFC);
 
         // Test the high addr range
 
-        mapper.data.chr_rom.data[PageSize::FourKb as usize * 5 + 9] = 0xFD;
+        Rc::make_mut(&mut mapper.data.chr_rom).data[PageSize::FourKb as usize * 5 + 9] = 0xFD;
         assert_eq!(mapper.read_chr_byte(0x1009), 0xFD);
     }
 
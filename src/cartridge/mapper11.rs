@@ -0,0 +1,161 @@
+// Mapper11 implements ines mapper 11 (Color Dreams)
+// https://wiki.nesdev.com/w/index.php/Color_Dreams
+//
+// Writes to $8000-$FFFF pick a 32kb PRG-ROM bank with the low 2 bits and an
+// 8kb CHR-ROM bank with the high 4 bits. Some boards have bus conflicts, where
+// the ROM drives the data bus along with the CPU, so the register gets the
+// written value ANDed with the ROM byte. That's off unless it's asked for.
+
+use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
+use super::Mapper;
+use super::Mirroring;
+use super::pager::Page;
+use super::pager::PageSize;
+
+#[derive(Clone)]
+pub struct Mapper11 {
+    data: CartridgeData,
+    prg_0: usize,
+    chr_0: usize,
+    bus_conflicts: bool,
+}
+
+impl Mapper11 {
+    pub fn new(data: CartridgeData) -> Self {
+        Mapper11 {
+            data,
+            prg_0: 0,
+            chr_0: 0,
+            bus_conflicts: false,
+        }
+    }
+}
+
+impl Mapper for Mapper11 {
+    fn reset(&mut self) {
+        let bus_conflicts = self.bus_conflicts;
+        *self = Mapper11::new(self.data.clone());
+        self.bus_conflicts = bus_conflicts;
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000...0xFFFF => Some(self.data.prg_rom.read(
+                Page::Number(self.prg_0, PageSize::ThirtyTwoKb),
+                address - 0x8000,
+            )),
+            _ => None,
+        }
+    }
+
+    fn write_prg_byte(&mut self, address: u16, value: u8) {
+        if let 0x8000...0xFFFF = address {
+            let value = if self.bus_conflicts {
+                value & self.read_prg_byte(address).unwrap_or(value)
+            } else {
+                value
+            };
+            let prg_banks = (self.data.header.prg_rom_pages / 2).max(1);
+            let chr_banks = self.data.header.chr_rom_pages.max(1);
+            self.prg_0 = (value as usize & 0b11) % prg_banks;
+            self.chr_0 = (value as usize >> 4) % chr_banks;
+        }
+    }
+
+    fn read_chr_byte(&self, address: u16) -> u8 {
+        self.data
+            .chr_rom
+            .read(Page::Number(self.chr_0, PageSize::EightKb), address)
+    }
+
+    fn write_chr_byte(&mut self, _address: u16, _value: u8) {}
+
+    fn address_kind(&self, address: u16) -> AddressKind {
+        standard_address_kind(address, false, false)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.data.header.mirroring
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Each 4kb page of PRG-ROM and CHR-ROM is filled with its page number
+    fn build_cartridge_data() -> CartridgeData {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x08, // 8 x 16kb prg rom
+            0x10, // 16 x 8kb chr rom
+            0xB0, // Mapper 11
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        for i in 0..0x4000 * 8 {
+            data.push((i / 0x1000) as u8);
+        }
+        for i in 0..0x2000 * 16 {
+            data.push((i / 0x1000) as u8);
+        }
+        CartridgeData::try_new(&data).unwrap()
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = Mapper11::new(build_cartridge_data());
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(0));
+        assert_eq!(mapper.read_chr_byte(0x0000), 0);
+
+        mapper.write_prg_byte(0x8000, 0x52);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(2 * 8));
+        assert_eq!(mapper.read_prg_byte(0xFFFF), Some(2 * 8 + 7));
+        assert_eq!(mapper.read_chr_byte(0x0000), 5 * 2);
+        assert_eq!(mapper.read_chr_byte(0x1FFF), 5 * 2 + 1);
+
+        mapper.write_prg_byte(0xFFFF, 0xF3);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(3 * 8));
+        assert_eq!(mapper.read_chr_byte(0x0000), 15 * 2);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut mapper = Mapper11::new(build_cartridge_data());
+        mapper.set_bus_conflicts(true);
+
+        // $8000 holds 0, so the write is lost
+        mapper.write_prg_byte(0x8000, 0x52);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(0));
+        assert_eq!(mapper.read_chr_byte(0x0000), 0);
+
+        // $F000 holds 7
+        mapper.write_prg_byte(0xF000, 0x12);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(2 * 8));
+        assert_eq!(mapper.read_chr_byte(0x0000), 0);
+
+        // The setting survives a reset
+        mapper.reset();
+        mapper.write_prg_byte(0x8000, 0x52);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(0));
+    }
+}
@@ -0,0 +1,136 @@
+// Mapper13 implements ines mapper 13 (CPROM)
+// https://wiki.nesdev.com/w/index.php/CPROM
+//
+// 32KB of fixed PRG-ROM and 16KB of CHR-RAM. $0000-$0FFF is always the
+// first 4KB of CHR-RAM, and $1000-$1FFF is switched between all four by
+// writes to $8000-$FFFF. Videomation is the only game that uses it.
+
+use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
+use super::Mapper;
+use super::Mirroring;
+use super::pager::Page;
+use super::pager::PageSize;
+use super::pager::Pager;
+
+const CHR_RAM_SIZE: usize = 0x4000;
+
+#[derive(Clone)]
+pub struct Mapper13 {
+    data: CartridgeData,
+    chr_1: usize,
+}
+
+impl Mapper13 {
+    pub fn new(mut data: CartridgeData) -> Self {
+        data.chr_ram = Pager::new(vec![0u8; CHR_RAM_SIZE]);
+        Mapper13 { data, chr_1: 0 }
+    }
+
+    fn chr_page(&self, address: u16) -> Page {
+        match address {
+            0x0000...0x0FFF => Page::First(PageSize::FourKb),
+            0x1000...0x1FFF => Page::Number(self.chr_1, PageSize::FourKb),
+            _ => panic!("bad address"),
+        }
+    }
+}
+
+impl Mapper for Mapper13 {
+    fn reset(&mut self) {
+        self.chr_1 = 0;
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000...0xFFFF => Some(self.data
+                .prg_rom
+                .read(Page::First(PageSize::ThirtyTwoKb), address - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn write_prg_byte(&mut self, address: u16, value: u8) {
+        if let 0x8000...0xFFFF = address {
+            self.chr_1 = value as usize & 0b11;
+        }
+    }
+
+    fn read_chr_byte(&self, address: u16) -> u8 {
+        let page = self.chr_page(address);
+        self.data.chr_ram.read(page, address % 0x1000)
+    }
+
+    fn write_chr_byte(&mut self, address: u16, value: u8) {
+        let page = self.chr_page(address);
+        self.data.chr_ram.write(page, address % 0x1000, value);
+    }
+
+    fn address_kind(&self, address: u16) -> AddressKind {
+        standard_address_kind(address, false, true)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.data.header.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_cartridge_data() -> CartridgeData {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x02, // 2 x 16kb prg rom
+            0x00, // CHR-RAM
+            0xD0, // Mapper 13
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        for i in 0..0x8000 {
+            data.push((i >> 8) as u8);
+        }
+
+        CartridgeData::try_new(&data).unwrap()
+    }
+
+    #[test]
+    fn test_prg_rom() {
+        let mut mapper = Mapper13::new(build_cartridge_data());
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(0x00));
+        assert_eq!(mapper.read_prg_byte(0xC000), Some(0x40));
+        mapper.write_prg_byte(0x8000, 3);
+        assert_eq!(mapper.read_prg_byte(0xFFFF), Some(0x7F));
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = Mapper13::new(build_cartridge_data());
+        for bank in 0..4 {
+            mapper.write_prg_byte(0x8000, bank);
+            mapper.write_chr_byte(0x1005, 0x10 + bank);
+        }
+
+        for bank in 0..4 {
+            mapper.write_prg_byte(0xC000 + bank as u16, 0b1111_1100 | bank);
+            assert_eq!(mapper.read_chr_byte(0x1005), 0x10 + bank);
+            // The low half is always the first bank
+            assert_eq!(mapper.read_chr_byte(0x0005), 0x10);
+        }
+    }
+}
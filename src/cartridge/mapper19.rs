@@ -0,0 +1,419 @@
+// Mapper19 implements ines mapper 19 (Namco 163)
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_019
+// https://wiki.nesdev.com/w/index.php/Namco_163_audio
+//
+// The nametable registers can map any 1kb of CHR-ROM or console RAM into
+// each nametable, but only console RAM is supported here. The CHR registers
+// can map console RAM into the pattern tables the same way.
+
+use std::cell::Cell;
+
+use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
+use super::Mapper;
+use super::Mirroring;
+use super::pager::Page;
+use super::pager::PageSize;
+
+const SOUND_RAM_SIZE: usize = 0x80;
+
+// Sound registers for channel 7 start at 0x78, and each channel below it
+// is 8 bytes earlier. 0x7F also holds the number of enabled channels.
+const CHANNEL_REGISTERS: usize = 0x40;
+
+// Only one channel is updated at a time, every 15 CPU cycles
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+// A full volume channel on its own is roughly as loud as both APU pulse
+// channels at full volume.
+const AUDIO_SCALE: f32 = 0.00188;
+
+#[derive(Clone)]
+pub struct Mapper19 {
+    data: CartridgeData,
+    prg: [usize; 3],
+    chr: [usize; 8],
+    nametables: [u8; 4],
+    // Stops CHR values of 0xE0 and up selecting console RAM, for each
+    // pattern table
+    console_ram_disabled: [bool; 2],
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_flag: bool,
+    sound_ram: [u8; SOUND_RAM_SIZE],
+    sound_address: Cell<u8>,
+    sound_auto_increment: bool,
+    sound_disabled: bool,
+    sound_cycles: u8,
+    current_channel: usize,
+    channel_outputs: [i16; 8],
+}
+
+impl Mapper19 {
+    pub fn new(data: CartridgeData) -> Self {
+        Mapper19 {
+            data,
+            prg: [0; 3],
+            chr: [0; 8],
+            nametables: [0xE0, 0xE1, 0xE0, 0xE1],
+            console_ram_disabled: [false; 2],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_flag: false,
+            sound_ram: [0; SOUND_RAM_SIZE],
+            sound_address: Cell::new(0),
+            sound_auto_increment: false,
+            sound_disabled: false,
+            sound_cycles: 0,
+            current_channel: 7,
+            channel_outputs: [0; 8],
+        }
+    }
+
+    fn read_sound_data(&self) -> u8 {
+        let address = self.sound_address.get();
+        if self.sound_auto_increment {
+            self.sound_address.set((address + 1) & 0x7F);
+        }
+        self.sound_ram[address as usize]
+    }
+
+    fn write_sound_data(&mut self, value: u8) {
+        let address = self.sound_address.get();
+        self.sound_ram[address as usize] = value;
+        if self.sound_auto_increment {
+            self.sound_address.set((address + 1) & 0x7F);
+        }
+    }
+
+    fn enabled_channels(&self) -> usize {
+        ((self.sound_ram[0x7F] >> 4) & 0b111) as usize + 1
+    }
+
+    // Channels are updated in turn from 7 downwards, through the enabled ones
+    fn tick_sound(&mut self) {
+        self.sound_cycles += 1;
+        if self.sound_cycles < CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.sound_cycles = 0;
+
+        let channel = self.current_channel;
+        self.channel_outputs[channel] = self.update_channel(channel);
+
+        let lowest = 8 - self.enabled_channels();
+        self.current_channel = if channel <= lowest { 7 } else { channel - 1 };
+    }
+
+    fn update_channel(&mut self, channel: usize) -> i16 {
+        let base = CHANNEL_REGISTERS + channel * 8;
+        let registers = &mut self.sound_ram[base..base + 8];
+
+        let frequency = registers[0] as u32
+            | (registers[2] as u32) << 8
+            | (registers[4] as u32 & 0b11) << 16;
+        let length = (256 - (registers[4] as u32 & 0xFC)) << 16;
+        let mut phase =
+            registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        phase = (phase + frequency) % length;
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+
+        let sample_address = ((phase >> 16) + registers[6] as u32) & 0xFF;
+        let volume = (registers[7] & 0x0F) as i16;
+
+        let byte = self.sound_ram[sample_address as usize / 2];
+        let sample = if sample_address & 1 == 0 {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        (sample as i16 - 8) * volume
+    }
+
+    fn tick_irq(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_flag = true;
+            }
+        }
+    }
+}
+
+impl Mapper for Mapper19 {
+    fn reset(&mut self) {
+        let sound_ram = self.sound_ram;
+        *self = Mapper19::new(self.data.clone());
+        self.sound_ram = sound_ram;
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
+        let value = match address {
+            0x4800...0x4FFF => self.read_sound_data(),
+            0x5000...0x57FF => self.irq_counter as u8,
+            0x5800...0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000...0x7FFF => self.data
+                .prg_ram
+                .read(Page::First(PageSize::EightKb), address - 0x6000),
+            0x8000...0xDFFF => {
+                let bank = self.prg[(address as usize - 0x8000) / 0x2000];
+                self.data
+                    .prg_rom
+                    .read(Page::Number(bank, PageSize::EightKb), address % 0x2000)
+            }
+            0xE000...0xFFFF => self.data
+                .prg_rom
+                .read(Page::Last(PageSize::EightKb), address - 0xE000),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // Without moving on the sound RAM address
+    fn peek_prg_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4FFF => Some(self.sound_ram[self.sound_address.get() as usize]),
+            _ => self.read_prg_byte(address),
+        }
+    }
+
+    fn write_prg_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0x4800...0x4FFF => self.write_sound_data(value),
+            0x5000...0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_flag = false;
+            }
+            0x5800...0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16 & 0x7F) << 8;
+                self.irq_enabled = value & 0b1000_0000 != 0;
+                self.irq_flag = false;
+            }
+            0x6000...0x7FFF => self.data.prg_ram.write(
+                Page::First(PageSize::EightKb),
+                address - 0x6000,
+                value,
+            ),
+            0x8000...0xBFFF => self.chr[(address as usize - 0x8000) / 0x800] = value as usize,
+            0xC000...0xDFFF => self.nametables[(address as usize - 0xC000) / 0x800] = value,
+            0xE000...0xE7FF => {
+                self.prg[0] = value as usize & 0x3F;
+                self.sound_disabled = value & 0b0100_0000 != 0;
+            }
+            0xE800...0xEFFF => {
+                self.prg[1] = value as usize & 0x3F;
+                self.console_ram_disabled = [value & 0b0100_0000 != 0, value & 0b1000_0000 != 0];
+            }
+            0xF000...0xF7FF => self.prg[2] = value as usize & 0x3F,
+            0xF800...0xFFFF => {
+                self.sound_address.set(value & 0x7F);
+                self.sound_auto_increment = value & 0b1000_0000 != 0;
+            }
+            _ => (),
+        }
+    }
+
+    fn read_chr_byte(&self, address: u16) -> u8 {
+        let banks = (self.data.header.chr_rom_pages * 8).max(1);
+        let bank = self.chr[address as usize / 0x400] % banks;
+        self.data
+            .chr_rom
+            .read(Page::Number(bank, PageSize::OneKb), address % 0x400)
+    }
+
+    fn chr_console_ram_page(&self, address: u16) -> Option<u8> {
+        let bank = self.chr[address as usize / 0x400];
+        if bank >= 0xE0 && !self.console_ram_disabled[address as usize / 0x1000] {
+            Some(bank as u8 & 1)
+        } else {
+            None
+        }
+    }
+
+    fn write_chr_byte(&mut self, _: u16, _: u8) {}
+
+    fn address_kind(&self, address: u16) -> AddressKind {
+        standard_address_kind(address, true, false)
+    }
+
+    // Values of 0xE0 and up select a page of console RAM, by their low bit
+    fn mirroring(&self) -> Mirroring {
+        let n = self.nametables;
+        let pages = [n[0] & 1, n[1] & 1, n[2] & 1, n[3] & 1];
+        match pages {
+            [0, 1, 0, 1] => Mirroring::Vertical,
+            [0, 0, 1, 1] => Mirroring::Horizontal,
+            [0, 0, 0, 0] => Mirroring::SingleScreenLower,
+            [1, 1, 1, 1] => Mirroring::SingleScreenUpper,
+            _ => Mirroring::Custom(pages),
+        }
+    }
+
+    fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn tick(&mut self) {
+        self.tick_irq();
+        if !self.sound_disabled {
+            self.tick_sound();
+        }
+    }
+
+    // The chip plays one channel at a time, so the more channels are
+    // enabled the quieter each one is.
+    fn mapper_audio_sample(&self) -> f32 {
+        let enabled = self.enabled_channels();
+        let sum: i16 = self.channel_outputs[8 - enabled..].iter().sum();
+        sum as f32 / enabled as f32 * AUDIO_SCALE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_cartridge_data() -> CartridgeData {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x08, // 8 x 16kb prg rom
+            0x04, // 4 x 8kb chr rom
+            0x30, // Mapper 19
+            0x10,
+            0x01, // One page of PRG-RAM
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+
+        // Each 1kb page of PRG-ROM and CHR-ROM is filled with its page number
+        for i in 0..0x4000 * 8 {
+            data.push((i / 0x400) as u8);
+        }
+        for i in 0..0x2000 * 4 {
+            data.push((i / 0x400) as u8);
+        }
+
+        CartridgeData::try_new(&data).unwrap()
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = Mapper19::new(build_cartridge_data());
+        mapper.write_prg_byte(0xE000, 3);
+        mapper.write_prg_byte(0xF000, 9);
+        mapper.write_prg_byte(0x8800, 20);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(3 * 8));
+        assert_eq!(mapper.read_prg_byte(0xC000), Some(9 * 8));
+        assert_eq!(mapper.read_prg_byte(0xE000), Some(15 * 8));
+        assert_eq!(mapper.read_chr_byte(0x0400), 20);
+    }
+
+    #[test]
+    fn test_chr_banks_wrap() {
+        let mut mapper = Mapper19::new(build_cartridge_data());
+        mapper.write_prg_byte(0x8000, 0x45);
+        assert_eq!(mapper.read_chr_byte(0x0000), 5);
+
+        // 0xE0 and up are console RAM unless that's disabled
+        mapper.write_prg_byte(0xB800, 0xFF);
+        assert_eq!(mapper.chr_console_ram_page(0x1C00), Some(1));
+        mapper.write_prg_byte(0xE800, 0b1000_0000);
+        assert_eq!(mapper.chr_console_ram_page(0x1C00), None);
+        assert_eq!(mapper.read_chr_byte(0x1C00), 31);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = Mapper19::new(build_cartridge_data());
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.write_prg_byte(0xC800, 0xE0);
+        mapper.write_prg_byte(0xD000, 0xE1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        // Arrangements without a standard name are passed on as they are
+        mapper.write_prg_byte(0xC000, 0xE1);
+        assert_eq!(mapper.mirroring(), Mirroring::Custom([1, 0, 1, 1]));
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = Mapper19::new(build_cartridge_data());
+        mapper.write_prg_byte(0x5000, 0xFD);
+        mapper.write_prg_byte(0x5800, 0xFF);
+        assert_eq!(mapper.read_prg_byte(0x5000), Some(0xFD));
+        assert_eq!(mapper.read_prg_byte(0x5800), Some(0xFF));
+        mapper.tick();
+        assert!(!mapper.irq_flag());
+        mapper.tick();
+        assert!(mapper.irq_flag());
+
+        // The counter stops at 0x7FFF, and writing it acknowledges the IRQ
+        mapper.tick();
+        assert_eq!(mapper.read_prg_byte(0x5000), Some(0xFF));
+        mapper.write_prg_byte(0x5000, 0);
+        assert!(!mapper.irq_flag());
+    }
+
+    #[test]
+    fn test_sound_ram_auto_increment() {
+        let mut mapper = Mapper19::new(build_cartridge_data());
+        mapper.write_prg_byte(0xF800, 0x80 | 0x10);
+        mapper.write_prg_byte(0x4800, 0x12);
+        mapper.write_prg_byte(0x4800, 0x34);
+        assert_eq!(mapper.sound_ram[0x10..0x12], [0x12, 0x34]);
+
+        mapper.write_prg_byte(0xF800, 0x80 | 0x10);
+        assert_eq!(mapper.read_prg_byte(0x4800), Some(0x12));
+        assert_eq!(mapper.read_prg_byte(0x4800), Some(0x34));
+
+        // Peeking doesn't move the address on
+        assert_eq!(mapper.peek_prg_byte(0x4800), Some(0));
+        mapper.write_prg_byte(0xF800, 0x80 | 0x10);
+        assert_eq!(mapper.peek_prg_byte(0x4800), Some(0x12));
+        assert_eq!(mapper.peek_prg_byte(0x4800), Some(0x12));
+        assert_eq!(mapper.read_prg_byte(0x4800), Some(0x12));
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut mapper = Mapper19::new(build_cartridge_data());
+        assert_eq!(mapper.mapper_audio_sample(), 0.0);
+
+        // A wave of 4 samples at 0xF at the start of sound RAM
+        mapper.write_prg_byte(0xF800, 0x80);
+        mapper.write_prg_byte(0x4800, 0xFF);
+        mapper.write_prg_byte(0x4800, 0xFF);
+
+        // Channel 7 plays it at full volume, alone
+        mapper.write_prg_byte(0xF800, 0x80 | 0x78);
+        for &value in &[0x00, 0x00, 0x00, 0x00, 0xFC, 0x00, 0x00, 0x0F] {
+            mapper.write_prg_byte(0x4800, value);
+        }
+
+        for _ in 0..CYCLES_PER_CHANNEL {
+            mapper.tick();
+        }
+        assert_eq!(mapper.channel_outputs[7], 7 * 15);
+        assert!(mapper.mapper_audio_sample() > 0.0);
+
+        // Enabling a second, silent channel halves the level
+        let alone = mapper.mapper_audio_sample();
+        mapper.write_prg_byte(0xF800, 0x7F);
+        mapper.write_prg_byte(0x4800, 0x1F);
+        assert_eq!(mapper.mapper_audio_sample(), alone / 2.0);
+    }
+}
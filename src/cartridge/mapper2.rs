@@ -2,11 +2,13 @@
 // https://wiki.nesdev.com/w/index.php/UxROM
 
 use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
 use super::Mapper;
 use super::Mirroring;
 use super::pager::Page;
 use super::pager::PageSize;
 
+#[derive(Clone)]
 pub struct Mapper2 {
     data: CartridgeData,
     prg_0: usize,
@@ -22,25 +24,30 @@ impl Mapper2 {
 }
 
 impl Mapper for Mapper2 {
-    fn read_prg_byte(&self, address: u16) -> u8 {
+    fn reset(&mut self) {
+        *self = Mapper2::new(self.data.clone());
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
         match address {
-            0x8000...0xBFFF => self.data.prg_rom.read(
+            0x8000...0xBFFF => Some(self.data.prg_rom.read(
                 Page::Number(self.prg_0, PageSize::SixteenKb),
                 address - 0x8000,
-            ),
-            0xC000...0xFFFF => self.data
+            )),
+            0xC000...0xFFFF => Some(self.data
                 .prg_rom
-                .read(Page::Last(PageSize::SixteenKb), address - 0xC000),
-            a => panic!("bad address: {:04X}", a),
+                .read(Page::Last(PageSize::SixteenKb), address - 0xC000)),
+            _ => None,
         }
     }
 
     fn write_prg_byte(&mut self, address: u16, value: u8) {
-        match address {
-            0x8000...0xFFFF => {
-                self.prg_0 = value as usize & 0x0F;
-            }
-            _ => panic!("bad address"),
+        if let 0x8000...0xFFFF = address {
+            self.prg_0 = value as usize & 0x0F;
         }
     }
 
@@ -64,6 +71,10 @@ impl Mapper for Mapper2 {
         }
     }
 
+    fn address_kind(&self, address: u16) -> AddressKind {
+        standard_address_kind(address, false, self.data.header.chr_rom_pages == 0)
+    }
+
     fn mirroring(&self) -> Mirroring {
         self.data.header.mirroring
     }
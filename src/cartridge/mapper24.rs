@@ -0,0 +1,571 @@
+// Mapper24 implements ines mappers 24 and 26 (Konami VRC6)
+// https://wiki.nesdev.com/w/index.php/VRC6
+//
+// The two boards are identical except that mapper 26 has the A0 and A1
+// address lines swapped, which is undone before decoding a register write.
+
+use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind, IrqState};
+use super::Mapper;
+use super::Mirroring;
+use super::pager::Page;
+use super::pager::PageSize;
+
+// The same approximation the APU mixer uses for its own pulse channels,
+// which the VRC6 channels roughly match in volume.
+const AUDIO_SCALE: f32 = 0.00752;
+
+#[derive(Clone)]
+pub struct Mapper24 {
+    data: CartridgeData,
+    swap_address_lines: bool,
+    prg_16k: usize,
+    prg_8k: usize,
+    chr: [usize; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_enabled: bool,
+    irq_enabled_after_ack: bool,
+    irq_cycle_mode: bool,
+    irq_flag: bool,
+    audio_halted: bool,
+    pulse_0: Vrc6Pulse,
+    pulse_1: Vrc6Pulse,
+    sawtooth: Vrc6Sawtooth,
+}
+
+impl Mapper24 {
+    pub fn new(data: CartridgeData) -> Self {
+        Mapper24 {
+            data,
+            swap_address_lines: false,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr: [0; 8],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: 341,
+            irq_enabled: false,
+            irq_enabled_after_ack: false,
+            irq_cycle_mode: false,
+            irq_flag: false,
+            audio_halted: false,
+            pulse_0: Vrc6Pulse::new(),
+            pulse_1: Vrc6Pulse::new(),
+            sawtooth: Vrc6Sawtooth::new(),
+        }
+    }
+
+    // Mapper 26
+    pub fn new_swapped(data: CartridgeData) -> Self {
+        let mut mapper = Mapper24::new(data);
+        mapper.swap_address_lines = true;
+        mapper
+    }
+
+    fn register_address(&self, address: u16) -> u16 {
+        let address = address & 0xF003;
+        if self.swap_address_lines {
+            (address & 0xF000) | (address & 1) << 1 | (address & 2) >> 1
+        } else {
+            address
+        }
+    }
+
+    // Banks past the end of the ROM wrap around, as on boards that don't
+    // connect the higher bank lines
+    fn write_prg_bank(&mut self, address: u16, value: u8) {
+        let pages = self.data.header.prg_rom_pages;
+        if address < 0xC000 {
+            self.prg_16k = (value as usize & 0x0F) % pages.max(1);
+        } else {
+            self.prg_8k = (value as usize & 0x1F) % (pages * 2).max(1);
+        }
+    }
+
+    // Boards without CHR-ROM have 8kb of CHR-RAM, which is 8 banks
+    fn write_chr_bank(&mut self, index: usize, value: u8) {
+        let banks = (self.data.header.chr_rom_pages * 8).max(8);
+        self.chr[index] = value as usize % banks;
+    }
+
+    fn chr_page(&self, address: u16) -> Page {
+        Page::Number(self.chr[address as usize / 0x400], PageSize::OneKb)
+    }
+
+    fn write_banking_control(&mut self, value: u8) {
+        self.prg_ram_enabled = value & 0b1000_0000 != 0;
+        self.mirroring = match (value >> 2) & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        };
+    }
+
+    fn write_irq_control(&mut self, value: u8) {
+        self.irq_enabled_after_ack = value & 0b001 != 0;
+        self.irq_enabled = value & 0b010 != 0;
+        self.irq_cycle_mode = value & 0b100 != 0;
+        self.irq_flag = false;
+        if self.irq_enabled {
+            self.irq_counter = self.irq_latch;
+            self.irq_prescaler = 341;
+        }
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_flag = false;
+        self.irq_enabled = self.irq_enabled_after_ack;
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_flag = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+
+    // In scanline mode the counter is clocked every 113 2/3 CPU cycles,
+    // which the prescaler tracks in thirds of a cycle.
+    fn tick_irq(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        if self.irq_cycle_mode {
+            self.clock_irq_counter();
+        } else {
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += 341;
+                self.clock_irq_counter();
+            }
+        }
+    }
+}
+
+impl Mapper for Mapper24 {
+    fn reset(&mut self) {
+        let swap_address_lines = self.swap_address_lines;
+        *self = Mapper24::new(self.data.clone());
+        self.swap_address_lines = swap_address_lines;
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
+        let value = match address {
+            0x6000...0x7FFF if self.prg_ram_enabled => self.data
+                .prg_ram
+                .read(Page::First(PageSize::EightKb), address - 0x6000),
+            0x8000...0xBFFF => self.data.prg_rom.read(
+                Page::Number(self.prg_16k, PageSize::SixteenKb),
+                address - 0x8000,
+            ),
+            0xC000...0xDFFF => self.data.prg_rom.read(
+                Page::Number(self.prg_8k, PageSize::EightKb),
+                address - 0xC000,
+            ),
+            0xE000...0xFFFF => self.data
+                .prg_rom
+                .read(Page::Last(PageSize::EightKb), address - 0xE000),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn write_prg_byte(&mut self, address: u16, value: u8) {
+        if let 0x6000...0x7FFF = address {
+            if self.prg_ram_enabled {
+                self.data
+                    .prg_ram
+                    .write(Page::First(PageSize::EightKb), address - 0x6000, value);
+            }
+            return;
+        }
+
+        let address = self.register_address(address);
+        match address {
+            0x8000...0x8003 => self.write_prg_bank(address, value),
+            0x9000...0x9002 => self.pulse_0.write_register(address & 0b11, value),
+            0x9003 => self.audio_halted = value & 1 != 0,
+            0xA000...0xA002 => self.pulse_1.write_register(address & 0b11, value),
+            0xB000...0xB002 => self.sawtooth.write_register(address & 0b11, value),
+            0xB003 => self.write_banking_control(value),
+            0xC000...0xC003 => self.write_prg_bank(address, value),
+            0xD000...0xD003 => self.write_chr_bank(address as usize & 0b11, value),
+            0xE000...0xE003 => self.write_chr_bank(4 + (address as usize & 0b11), value),
+            0xF000 => self.irq_latch = value,
+            0xF001 => self.write_irq_control(value),
+            0xF002 => self.acknowledge_irq(),
+            _ => (),
+        }
+    }
+
+    fn read_chr_byte(&self, address: u16) -> u8 {
+        let page = self.chr_page(address);
+        if self.data.header.chr_rom_pages == 0 {
+            self.data.chr_ram.read(page, address % 0x400)
+        } else {
+            self.data.chr_rom.read(page, address % 0x400)
+        }
+    }
+
+    fn write_chr_byte(&mut self, address: u16, value: u8) {
+        if self.data.header.chr_rom_pages == 0 {
+            let page = self.chr_page(address);
+            self.data.chr_ram.write(page, address % 0x400, value);
+        }
+    }
+
+    fn address_kind(&self, address: u16) -> AddressKind {
+        let chr_ram = self.data.header.chr_rom_pages == 0;
+        standard_address_kind(address, self.prg_ram_enabled, chr_ram)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn irq_state(&self) -> Option<IrqState> {
+        // The counter counts up, firing as it wraps past $FF
+        Some(IrqState {
+            counter: self.irq_counter as u16,
+            reload: self.irq_latch as u16,
+            enabled: self.irq_enabled,
+            pending: self.irq_flag,
+        })
+    }
+
+    fn tick(&mut self) {
+        self.tick_irq();
+        if !self.audio_halted {
+            self.pulse_0.tick();
+            self.pulse_1.tick();
+            self.sawtooth.tick();
+        }
+    }
+
+    fn mapper_audio_sample(&self) -> f32 {
+        let output = self.pulse_0.sample() + self.pulse_1.sample() + self.sawtooth.sample();
+        output as f32 * AUDIO_SCALE
+    }
+}
+
+#[derive(Clone)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    constant: bool,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn new() -> Self {
+        Vrc6Pulse {
+            volume: 0,
+            duty: 0,
+            constant: false,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.constant = value & 0b1000_0000 != 0;
+                self.duty = (value >> 4) & 0b111;
+                self.volume = value & 0b1111;
+            }
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (value as u16 & 0x0F) << 8;
+                self.enabled = value & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sample(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Vrc6Sawtooth {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Sawtooth {
+    fn new() -> Self {
+        Vrc6Sawtooth {
+            rate: 0,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0b11_1111,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (value as u16 & 0x0F) << 8;
+                self.enabled = value & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    // The accumulator gains the rate on every other clock, and is cleared
+    // after the 14th, giving a sawtooth with 7 steps.
+    fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn sample(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_cartridge_data() -> CartridgeData {
+        build_cartridge_data_with_chr(4)
+    }
+
+    fn build_cartridge_data_with_chr(chr_pages: u8) -> CartridgeData {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x08,      // 8 x 16kb prg rom
+            chr_pages, // 8kb pages of chr rom
+            0x80, // Mapper 24
+            0x10,
+            0x01, // One page of PRG-RAM
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+
+        // Each 1kb page of PRG-ROM and CHR-ROM is filled with its page number
+        for i in 0..0x4000 * 8 {
+            data.push((i / 0x400) as u8);
+        }
+        for i in 0..0x2000 * chr_pages as usize {
+            data.push((i / 0x400) as u8);
+        }
+
+        CartridgeData::try_new(&data).unwrap()
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0x8000, 2);
+        mapper.write_prg_byte(0xC000, 5);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(2 * 16));
+        assert_eq!(mapper.read_prg_byte(0xBFFF), Some(2 * 16 + 15));
+        assert_eq!(mapper.read_prg_byte(0xC000), Some(5 * 8));
+        assert_eq!(mapper.read_prg_byte(0xE000), Some(15 * 8));
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0x6000, 0x55);
+        assert_eq!(mapper.read_prg_byte(0x6000), None);
+        mapper.write_prg_byte(0xB003, 0b1000_0000);
+        mapper.write_prg_byte(0x6000, 0x55);
+        assert_eq!(mapper.read_prg_byte(0x6000), Some(0x55));
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0xD001, 7);
+        mapper.write_prg_byte(0xE003, 30);
+        assert_eq!(mapper.read_chr_byte(0x0400), 7);
+        assert_eq!(mapper.read_chr_byte(0x1C00), 30);
+    }
+
+    #[test]
+    fn test_banks_wrap() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0x8000, 0xFF);
+        mapper.write_prg_byte(0xC000, 0xFF);
+        mapper.write_prg_byte(0xD000, 0xFF);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(7 * 16));
+        assert_eq!(mapper.read_prg_byte(0xC000), Some(15 * 8));
+        assert_eq!(mapper.read_chr_byte(0x0000), 31);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut mapper = Mapper24::new(build_cartridge_data_with_chr(0));
+        mapper.write_prg_byte(0xD000, 0xFF);
+        mapper.write_chr_byte(0x0000, 0x12);
+        assert_eq!(mapper.read_chr_byte(0x0000), 0x12);
+        mapper.write_prg_byte(0xD001, 7);
+        assert_eq!(mapper.read_chr_byte(0x0400), 0x12);
+        assert_eq!(mapper.address_kind(0x0000), AddressKind::ChrRam);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0xB003, 0b0000);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.write_prg_byte(0xB003, 0b0100);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.write_prg_byte(0xB003, 0b1000);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        mapper.write_prg_byte(0xB003, 0b1100);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_swapped_address_lines() {
+        let mut mapper = Mapper24::new_swapped(build_cartridge_data());
+        // 0xB001 on mapper 26 is 0xB002 on mapper 24, so this isn't mirroring
+        mapper.write_prg_byte(0xB001, 0b0100);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.write_prg_byte(0xB003, 0b0100);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_irq_cycle_mode() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0xF000, 0xF0);
+        mapper.write_prg_byte(0xF001, 0b110);
+        for _ in 0..15 {
+            mapper.tick();
+        }
+        assert!(!mapper.irq_flag());
+        mapper.tick();
+        assert!(mapper.irq_flag());
+
+        mapper.write_prg_byte(0xF002, 0);
+        assert!(!mapper.irq_flag());
+    }
+
+    #[test]
+    fn test_irq_scanline_mode() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0xF000, 0xFE);
+        mapper.write_prg_byte(0xF001, 0b010);
+        // Two scanlines of 341 PPU dots
+        for _ in 0..227 {
+            mapper.tick();
+        }
+        assert!(!mapper.irq_flag());
+        mapper.tick();
+        assert!(mapper.irq_flag());
+    }
+
+    #[test]
+    fn test_pulse() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0x9000, 0b0011_1010); // Duty 3, volume 10
+        mapper.write_prg_byte(0x9001, 0);
+        mapper.write_prg_byte(0x9002, 0b1000_0000); // Enabled, period 0
+
+        let mut outputs = Vec::new();
+        for _ in 0..16 {
+            mapper.tick();
+            outputs.push(mapper.pulse_0.sample());
+        }
+        assert_eq!(outputs.iter().filter(|&&v| v == 10).count(), 4);
+        assert_eq!(outputs.iter().filter(|&&v| v == 0).count(), 12);
+        assert!(mapper.mapper_audio_sample() >= 0.0);
+    }
+
+    #[test]
+    fn test_sawtooth() {
+        let mut mapper = Mapper24::new(build_cartridge_data());
+        mapper.write_prg_byte(0xB000, 42);
+        mapper.write_prg_byte(0xB001, 0);
+        mapper.write_prg_byte(0xB002, 0b1000_0000);
+
+        let mut outputs = Vec::new();
+        for _ in 0..14 {
+            mapper.tick();
+            outputs.push(mapper.sawtooth.sample());
+        }
+        assert_eq!(outputs, [0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0]);
+        assert_eq!(mapper.mapper_audio_sample(), 0.0);
+    }
+}
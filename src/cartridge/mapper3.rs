@@ -2,11 +2,13 @@
 // https://wiki.nesdev.com/w/index.php/INES_Mapper_003
 
 use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
 use super::Mapper;
 use super::Mirroring;
 use super::pager::Page;
 use super::pager::PageSize;
 
+#[derive(Clone)]
 pub struct Mapper3 {
     data: CartridgeData,
     chr_0: usize,
@@ -22,15 +24,23 @@ impl Mapper3 {
 }
 
 impl Mapper for Mapper3 {
-    fn read_prg_byte(&self, address: u16) -> u8 {
+    fn reset(&mut self) {
+        *self = Mapper3::new(self.data.clone());
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
         match address {
-            0x8000...0xBFFF => self.data
+            0x8000...0xBFFF => Some(self.data
                 .prg_rom
-                .read(Page::First(PageSize::SixteenKb), address - 0x8000),
-            0xC000...0xFFFF => self.data
+                .read(Page::First(PageSize::SixteenKb), address - 0x8000)),
+            0xC000...0xFFFF => Some(self.data
                 .prg_rom
-                .read(Page::Last(PageSize::SixteenKb), address - 0xC000),
-            a => panic!("bad address: {:04X}", a),
+                .read(Page::Last(PageSize::SixteenKb), address - 0xC000)),
+            _ => None,
         }
     }
 
@@ -51,6 +61,10 @@ impl Mapper for Mapper3 {
 
     fn write_chr_byte(&mut self, _: u16, _: u8) {}
 
+    fn address_kind(&self, address: u16) -> AddressKind {
+        standard_address_kind(address, false, false)
+    }
+
     fn mirroring(&self) -> Mirroring {
         self.data.header.mirroring
     }
@@ -0,0 +1,207 @@
+// Mapper34 implements ines mapper 34, which is two unrelated boards
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_034
+//
+// BNROM has CHR-RAM and switches 32kb of PRG-ROM with writes to $8000-$FFFF.
+// NINA-001 has CHR-ROM and PRG-RAM, and its registers at $7FFD-$7FFF switch
+// 32kb of PRG-ROM and two 4kb banks of CHR-ROM.
+
+use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
+use super::Mapper;
+use super::Mirroring;
+use super::pager::Page;
+use super::pager::PageSize;
+
+#[derive(Clone)]
+pub struct Mapper34 {
+    data: CartridgeData,
+    nina: bool,
+    prg_0: usize,
+    chr_0: usize,
+    chr_1: usize,
+}
+
+impl Mapper34 {
+    pub fn new(data: CartridgeData) -> Self {
+        Mapper34 {
+            nina: data.header.chr_rom_pages != 0,
+            data,
+            prg_0: 0,
+            chr_0: 0,
+            chr_1: 1,
+        }
+    }
+
+    fn prg_bank(&self, value: u8) -> usize {
+        value as usize % (self.data.header.prg_rom_pages / 2).max(1)
+    }
+
+    fn chr_bank(&self, value: u8) -> usize {
+        value as usize % (self.data.header.chr_rom_pages * 2)
+    }
+}
+
+impl Mapper for Mapper34 {
+    fn reset(&mut self) {
+        *self = Mapper34::new(self.data.clone());
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000...0x7FFF if self.nina => Some(self.data
+                .prg_ram
+                .read(Page::First(PageSize::EightKb), address - 0x6000)),
+            0x8000...0xFFFF => Some(self.data.prg_rom.read(
+                Page::Number(self.prg_0, PageSize::ThirtyTwoKb),
+                address - 0x8000,
+            )),
+            _ => None,
+        }
+    }
+
+    fn write_prg_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000...0x7FFF if self.nina => {
+                self.data
+                    .prg_ram
+                    .write(Page::First(PageSize::EightKb), address - 0x6000, value);
+                match address {
+                    0x7FFD => self.prg_0 = self.prg_bank(value & 0x01),
+                    0x7FFE => self.chr_0 = self.chr_bank(value & 0x0F),
+                    0x7FFF => self.chr_1 = self.chr_bank(value & 0x0F),
+                    _ => (),
+                }
+            }
+            0x8000...0xFFFF if !self.nina => {
+                self.prg_0 = self.prg_bank(value);
+            }
+            _ => (),
+        }
+    }
+
+    fn read_chr_byte(&self, address: u16) -> u8 {
+        if !self.nina {
+            return self.data
+                .chr_ram
+                .read(Page::First(PageSize::EightKb), address);
+        }
+        match address {
+            0x0000...0x0FFF => self.data
+                .chr_rom
+                .read(Page::Number(self.chr_0, PageSize::FourKb), address),
+            0x1000...0x1FFF => self.data
+                .chr_rom
+                .read(Page::Number(self.chr_1, PageSize::FourKb), address - 0x1000),
+            a => panic!("bad address: {:04X}", a),
+        }
+    }
+
+    fn write_chr_byte(&mut self, address: u16, value: u8) {
+        if !self.nina {
+            self.data
+                .chr_ram
+                .write(Page::First(PageSize::EightKb), address, value)
+        }
+    }
+
+    fn address_kind(&self, address: u16) -> AddressKind {
+        standard_address_kind(address, self.nina, !self.nina)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.data.header.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Each 4kb page of PRG-ROM and CHR-ROM is filled with its page number
+    fn build_cartridge_data(chr_rom_pages: u8) -> CartridgeData {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x08, // 8 x 16kb prg rom
+            chr_rom_pages,
+            0x20, // Mapper 34
+            0x20,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        for i in 0..0x4000 * 8 {
+            data.push((i / 0x1000) as u8);
+        }
+        for i in 0..0x2000 * chr_rom_pages as usize {
+            data.push((i / 0x1000) as u8);
+        }
+        CartridgeData::try_new(&data).unwrap()
+    }
+
+    #[test]
+    fn test_bnrom() {
+        let mut mapper = Mapper34::new(build_cartridge_data(0));
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(0));
+        assert_eq!(mapper.read_prg_byte(0xFFFF), Some(7));
+
+        mapper.write_prg_byte(0x8000, 2);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(2 * 8));
+        assert_eq!(mapper.read_prg_byte(0xFFFF), Some(2 * 8 + 7));
+
+        // Banks past the end of the ROM wrap around
+        mapper.write_prg_byte(0xC123, 5);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(8));
+
+        // CHR is RAM, and there's no PRG-RAM for the NINA-001 registers
+        mapper.write_chr_byte(0x1234, 0x56);
+        assert_eq!(mapper.read_chr_byte(0x1234), 0x56);
+        mapper.write_prg_byte(0x7FFD, 3);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(8));
+        assert_eq!(mapper.address_kind(0x6000), AddressKind::Unmapped);
+        assert_eq!(mapper.address_kind(0x0000), AddressKind::ChrRam);
+    }
+
+    #[test]
+    fn test_nina_001() {
+        let mut mapper = Mapper34::new(build_cartridge_data(4));
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(0));
+        assert_eq!(mapper.read_chr_byte(0x0000), 0);
+        assert_eq!(mapper.read_chr_byte(0x1000), 1);
+
+        mapper.write_prg_byte(0x7FFD, 1);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(8));
+        assert_eq!(mapper.read_prg_byte(0xFFFF), Some(15));
+
+        mapper.write_prg_byte(0x7FFE, 5);
+        mapper.write_prg_byte(0x7FFF, 2);
+        assert_eq!(mapper.read_chr_byte(0x0FFF), 5);
+        assert_eq!(mapper.read_chr_byte(0x1000), 2);
+
+        // The registers are also written to PRG-RAM underneath
+        assert_eq!(mapper.read_prg_byte(0x7FFE), Some(5));
+        mapper.write_prg_byte(0x6000, 0x12);
+        assert_eq!(mapper.read_prg_byte(0x6000), Some(0x12));
+
+        // Writes to ROM don't switch banks on this board
+        mapper.write_prg_byte(0x8000, 0);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(8));
+
+        // CHR-ROM isn't writable
+        mapper.write_chr_byte(0x0000, 0xFF);
+        assert_eq!(mapper.read_chr_byte(0x0000), 5);
+        assert_eq!(mapper.address_kind(0x6000), AddressKind::PrgRam);
+        assert_eq!(mapper.address_kind(0x0000), AddressKind::ChrRom);
+    }
+}
@@ -2,11 +2,17 @@
 // https://wiki.nesdev.com/w/index.php/MMC3
 
 use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind, IrqState};
 use super::Mapper;
 use super::Mirroring;
 use super::pager::Page;
 use super::pager::PageSize;
 
+// A12 has to be low for this many CPU cycles before a rise clocks the IRQ
+// counter, which filters out the rises between background tile fetches.
+const A12_FILTER_CYCLES: u8 = 3;
+
+#[derive(Clone)]
 pub struct Mapper4 {
     data: CartridgeData,
     registers: [usize; 8],
@@ -19,30 +25,102 @@ pub struct Mapper4 {
     irq_enabled: bool,
     irq_reset: bool,
     irq_flag: bool,
+    a12: bool,
+    a12_low_cycles: u8,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
 }
 
 impl Mapper4 {
     pub fn new(data: CartridgeData) -> Self {
+        let mirroring = if data.header.mirroring == Mirroring::FourScreen {
+            Mirroring::FourScreen
+        } else {
+            Mirroring::Horizontal
+        };
         Mapper4 {
             data: data,
             registers: [0; 8],
             index: 0,
             prg_mode: false,
             chr_mode: false,
-            mirroring: Mirroring::Horizontal,
+            mirroring,
             irq_counter: 0,
             irq_period: 0,
             irq_enabled: false,
             irq_reset: false,
             irq_flag: false,
+            a12: false,
+            a12_low_cycles: 0,
+            // Plenty of games never write $A001, so PRG-RAM starts usable
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+        }
+    }
+
+    // $0000-$03FF 	R0 AND $FE 	R2
+    // $0400-$07FF 	R0 OR 1 	R3
+    // $0800-$0BFF 	R1 AND $FE 	R4
+    // $0C00-$0FFF 	R1 OR 1 	R5
+    // $1000-$13FF 	R2 	R0 AND $FE
+    // $1400-$17FF 	R3 	R0 OR 1
+    // $1800-$1BFF 	R4 	R1 AND $FE
+    // $1C00-$1FFF 	R5 	R1 OR 1
+    fn chr_page(&self, address: u16) -> Page {
+        let bank = match (address, self.chr_mode) {
+            (0x0000...0x03FF, false) => self.registers[0] & !1,
+            (0x0000...0x03FF, true) => self.registers[2],
+            (0x0400...0x07FF, false) => self.registers[0] | 1,
+            (0x0400...0x07FF, true) => self.registers[3],
+            (0x0800...0x0BFF, false) => self.registers[1] & !1,
+            (0x0800...0x0BFF, true) => self.registers[4],
+            (0x0C00...0x0FFF, false) => self.registers[1] | 1,
+            (0x0C00...0x0FFF, true) => self.registers[5],
+
+            (0x1000...0x13FF, false) => self.registers[2],
+            (0x1000...0x13FF, true) => self.registers[0] & !1,
+            (0x1400...0x17FF, false) => self.registers[3],
+            (0x1400...0x17FF, true) => self.registers[0] | 1,
+            (0x1800...0x1BFF, false) => self.registers[4],
+            (0x1800...0x1BFF, true) => self.registers[1] & !1,
+            (0x1C00...0x1FFF, false) => self.registers[5],
+            (0x1C00...0x1FFF, true) => self.registers[1] | 1,
+            _ => panic!(),
+        };
+
+        // CHR-RAM is only 8KB, so higher banks wrap around
+        if self.data.header.chr_rom_pages == 0 {
+            Page::Number(bank % 8, PageSize::OneKb)
+        } else {
+            Page::Number(bank, PageSize::OneKb)
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reset {
+            self.irq_counter = self.irq_period;
+            self.irq_reset = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_flag = true;
         }
     }
 }
 
 impl Mapper for Mapper4 {
-    fn read_prg_byte(&self, address: u16) -> u8 {
-        match (address, self.prg_mode) {
-            (0x6000...0x7FFF, _) => self.data
+    fn reset(&mut self) {
+        *self = Mapper4::new(self.data.clone());
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
+        let value = match (address, self.prg_mode) {
+            (0x6000...0x7FFF, _) if self.prg_ram_enabled => self.data
                 .prg_ram
                 .read(Page::First(PageSize::EightKb), address - 0x6000),
             (0x8000...0x9FFF, false) => self.data.prg_rom.read(
@@ -66,13 +144,14 @@ impl Mapper for Mapper4 {
             (0xE000...0xFFFF, _) => self.data
                 .prg_rom
                 .read(Page::FromEnd(0, PageSize::EightKb), address - 0xE000),
-            (a, _m) => panic!("bad address: {:04X}", a),
-        }
+            _ => return None,
+        };
+        Some(value)
     }
 
     fn write_prg_byte(&mut self, address: u16, value: u8) {
         match (address, address % 2) {
-            (0x6000...0x7FFF, _) => {
+            (0x6000...0x7FFF, _) if self.prg_ram_enabled && !self.prg_ram_write_protected => {
                 self.data
                     .prg_ram
                     .write(Page::First(PageSize::EightKb), address - 0x6000, value)
@@ -85,60 +164,49 @@ impl Mapper for Mapper4 {
             (0x8000...0x9FFF, 1) => {
                 self.registers[self.index] = value as usize;
             }
-            (0xA000...0xBFFF, 0) => {
+            // Four-screen boards ignore the mirroring register
+            (0xA000...0xBFFF, 0) if self.mirroring != Mirroring::FourScreen => {
                 self.mirroring = if value & 1 == 0 {
                     Mirroring::Vertical
                 } else {
                     Mirroring::Horizontal
                 };
             }
+            (0xA000...0xBFFF, 1) => {
+                self.prg_ram_enabled = value & 0b1000_0000 != 0;
+                self.prg_ram_write_protected = value & 0b0100_0000 != 0;
+            }
             (0xC000...0xDFFF, 0) => self.irq_period = value,
             (0xC000...0xDFFF, 1) => self.irq_reset = true,
             (0xE000...0xFFFF, 0) => {
                 self.irq_enabled = false;
                 self.irq_flag = false;
             }
-            (0xF000...0xFFFF, 1) => self.irq_enabled = true,
+            (0xE000...0xFFFF, 1) => self.irq_enabled = true,
 
             _ => (),
         }
     }
-    // $0000-$03FF 	R0 AND $FE 	R2
-    // $0400-$07FF 	R0 OR 1 	R3
-    // $0800-$0BFF 	R1 AND $FE 	R4
-    // $0C00-$0FFF 	R1 OR 1 	R5
-    // $1000-$13FF 	R2 	R0 AND $FE
-    // $1400-$17FF 	R3 	R0 OR 1
-    // $1800-$1BFF 	R4 	R1 AND $FE
-    // $1C00-$1FFF 	R5 	R1 OR 1
     fn read_chr_byte(&self, address: u16) -> u8 {
-        let bank = match (address, self.chr_mode) {
-            (0x0000...0x03FF, false) => self.registers[0] & !1,
-            (0x0000...0x03FF, true) => self.registers[2],
-            (0x0400...0x07FF, false) => self.registers[0] | 1,
-            (0x0400...0x07FF, true) => self.registers[3],
-            (0x0800...0x0BFF, false) => self.registers[1] & !1,
-            (0x0800...0x0BFF, true) => self.registers[4],
-            (0x0C00...0x0FFF, false) => self.registers[1] | 1,
-            (0x0C00...0x0FFF, true) => self.registers[5],
-
-            (0x1000...0x13FF, false) => self.registers[2],
-            (0x1000...0x13FF, true) => self.registers[0] & !1,
-            (0x1400...0x17FF, false) => self.registers[3],
-            (0x1400...0x17FF, true) => self.registers[0] | 1,
-            (0x1800...0x1BFF, false) => self.registers[4],
-            (0x1800...0x1BFF, true) => self.registers[1] & !1,
-            (0x1C00...0x1FFF, false) => self.registers[5],
-            (0x1C00...0x1FFF, true) => self.registers[1] | 1,
-            _ => panic!(),
-        };
+        let page = self.chr_page(address);
+        if self.data.header.chr_rom_pages == 0 {
+            self.data.chr_ram.read(page, address % 0x0400)
+        } else {
+            self.data.chr_rom.read(page, address % 0x0400)
+        }
+    }
 
-        self.data
-            .chr_rom
-            .read(Page::Number(bank, PageSize::OneKb), address % 0x0400)
+    fn write_chr_byte(&mut self, address: u16, value: u8) {
+        if self.data.header.chr_rom_pages == 0 {
+            let page = self.chr_page(address);
+            self.data.chr_ram.write(page, address % 0x0400, value);
+        }
     }
 
-    fn write_chr_byte(&mut self, _: u16, _: u8) {}
+    fn address_kind(&self, address: u16) -> AddressKind {
+        let chr_ram = self.data.header.chr_rom_pages == 0;
+        standard_address_kind(address, self.prg_ram_enabled, chr_ram)
+    }
 
     fn mirroring(&self) -> Mirroring {
         self.mirroring
@@ -147,14 +215,275 @@ impl Mapper for Mapper4 {
     fn irq_flag(&self) -> bool {
         self.irq_flag
     }
+    fn irq_state(&self) -> Option<IrqState> {
+        Some(IrqState {
+            counter: self.irq_counter as u16,
+            reload: self.irq_period as u16,
+            enabled: self.irq_enabled,
+            pending: self.irq_flag,
+        })
+    }
     fn signal_scanline(&mut self) {
-        if self.irq_counter == 0 || self.irq_reset {
-            if self.irq_enabled {
-                self.irq_flag = true;
+        self.clock_irq_counter();
+    }
+
+    fn signal_ppu_address(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 {
+            if !self.a12 && self.a12_low_cycles >= A12_FILTER_CYCLES {
+                self.clock_irq_counter();
             }
-            self.irq_counter = self.irq_period;
-        } else {
-            self.irq_counter -= 1;
+            self.a12_low_cycles = 0;
         }
+        self.a12 = a12;
+    }
+
+    fn tick(&mut self) {
+        if !self.a12 {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_mapper() -> Mapper4 {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x02, // Two pages of PRG-ROM
+            0x01, // One page of CHR-ROM
+            0x40, // Mapper 4
+            0x00,
+            0x01, // One page of PRG-RAM
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        data.extend_from_slice(&[0u8; 2 * 0x4000 + 0x2000]);
+
+        let mut mapper = Mapper4::new(CartridgeData::try_new(&data).unwrap());
+        mapper.write_prg_byte(0xC000, 2); // IRQ period
+        mapper.write_prg_byte(0xC001, 0); // Reload the counter
+        mapper.write_prg_byte(0xE001, 0); // Enable IRQs
+        mapper
+    }
+
+    // The pattern of a scanline with the background at 0x0000 and sprites
+    // at 0x1000
+    fn signal_scanline_addresses(mapper: &mut Mapper4) {
+        mapper.signal_ppu_address(0x0000);
+        for _ in 0..A12_FILTER_CYCLES {
+            mapper.tick();
+        }
+        mapper.signal_ppu_address(0x1000);
+        mapper.signal_ppu_address(0x1008);
+    }
+
+    #[test]
+    fn test_a12_clocks_irq_counter() {
+        let mut mapper = build_mapper();
+        signal_scanline_addresses(&mut mapper);
+        assert_eq!(mapper.irq_counter, 2);
+        signal_scanline_addresses(&mut mapper);
+        assert_eq!(mapper.irq_counter, 1);
+        assert!(!mapper.irq_flag());
+        signal_scanline_addresses(&mut mapper);
+        assert_eq!(mapper.irq_counter, 0);
+        assert!(mapper.irq_flag());
+
+        // Then it's reloaded
+        signal_scanline_addresses(&mut mapper);
+        assert_eq!(mapper.irq_counter, 2);
+    }
+
+    #[test]
+    fn test_a12_filter() {
+        let mut mapper = build_mapper();
+        signal_scanline_addresses(&mut mapper);
+        assert_eq!(mapper.irq_counter, 2);
+
+        // A12 wasn't low for long enough, as between background fetches
+        mapper.signal_ppu_address(0x0000);
+        mapper.tick();
+        mapper.signal_ppu_address(0x1000);
+        assert_eq!(mapper.irq_counter, 2);
+    }
+
+    #[test]
+    fn test_signal_scanline() {
+        let mut mapper = build_mapper();
+        mapper.signal_scanline();
+        mapper.signal_scanline();
+        assert_eq!(mapper.irq_counter, 1);
+    }
+
+    #[test]
+    fn test_irq_state() {
+        let mut mapper = build_mapper();
+        mapper.write_prg_byte(0xC000, 5); // IRQ period
+        signal_scanline_addresses(&mut mapper);
+        signal_scanline_addresses(&mut mapper);
+        assert_eq!(
+            mapper.irq_state(),
+            Some(IrqState {
+                counter: 4,
+                reload: 5,
+                enabled: true,
+                pending: false,
+            })
+        );
+
+        mapper.write_prg_byte(0xE000, 0); // Disable and acknowledge IRQs
+        assert_eq!(mapper.irq_state().map(|s| s.enabled), Some(false));
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut data = vec![
+            0x4e, 0x45, 0x53, 0x1a, 0x02, 0x00, 0x40, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        data.extend_from_slice(&[0u8; 2 * 0x4000]);
+        let mut mapper = Mapper4::new(CartridgeData::try_new(&data).unwrap());
+        assert_eq!(mapper.address_kind(0x1000), AddressKind::ChrRam);
+
+        // $1000-$13FF is banked by R2
+        mapper.write_prg_byte(0x8000, 2);
+        mapper.write_prg_byte(0x8001, 5);
+        mapper.write_chr_byte(0x1010, 0xAB);
+        assert_eq!(mapper.read_chr_byte(0x1010), 0xAB);
+        assert_eq!(mapper.data.chr_ram.data[5 * 0x400 + 0x10], 0xAB);
+
+        // Swapping the pattern tables maps R2 to $0000-$03FF
+        mapper.write_prg_byte(0x8000, 0x80);
+        assert_eq!(mapper.read_chr_byte(0x1010), 0);
+        assert_eq!(mapper.read_chr_byte(0x0010), 0xAB);
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mapper = build_mapper();
+        mapper.write_prg_byte(0x6000, 0x12);
+        assert_eq!(mapper.read_prg_byte(0x6000), Some(0x12));
+
+        // Write protected
+        mapper.write_prg_byte(0xA001, 0b1100_0000);
+        mapper.write_prg_byte(0x6000, 0x34);
+        assert_eq!(mapper.read_prg_byte(0x6000), Some(0x12));
+
+        // Disabled, so writes are dropped and reads are open bus
+        mapper.write_prg_byte(0xA001, 0);
+        mapper.write_prg_byte(0x6000, 0x56);
+        assert_eq!(mapper.read_prg_byte(0x6000), None);
+        assert_eq!(mapper.address_kind(0x6000), AddressKind::Unmapped);
+
+        mapper.write_prg_byte(0xA001, 0b1000_0000);
+        assert_eq!(mapper.read_prg_byte(0x6000), Some(0x12));
+    }
+}
+
+// Runs the PPU with an MMC3 to check which scanline the IRQ lands on
+#[cfg(test)]
+mod irq_test {
+    use bus::Bus;
+
+    fn build_bus() -> Bus {
+        let mut rom = vec![
+            0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x40, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        rom.extend_from_slice(&[0u8; 2 * 0x4000 + 0x2000]);
+        let mut bus = Bus::new();
+        bus.load_rom_from_memory(&rom).unwrap();
+
+        // Background at 0x0000 and sprites at 0x1000, so A12 rises once a
+        // scanline during the sprite fetches
+        bus.write_byte(0x2000u16, 0x08);
+        bus.write_byte(0x2001u16, 0x18);
+        run_until(&mut bus, 261);
+        bus
+    }
+
+    // Just the mapper's IRQ, as the APU's frame IRQ is on at power on too
+    fn irq(bus: &Bus) -> bool {
+        bus.cartridge.as_ref().unwrap().borrow().irq_flag()
+    }
+
+    fn run_until(bus: &mut Bus, scanline: usize) {
+        while bus.ppu.position().0 != scanline {
+            bus.tick();
+        }
+    }
+
+    // The scanlines the IRQ is raised on through to the end of the visible
+    // frame, acknowledging it each time
+    fn irq_scanlines(bus: &mut Bus) -> Vec<usize> {
+        if bus.ppu.position().0 == 261 {
+            run_until(bus, 0);
+        }
+        let mut scanlines = vec![];
+        while bus.ppu.position().0 < 240 {
+            bus.tick();
+            if irq(bus) {
+                scanlines.push(bus.ppu.position().0);
+                bus.write_byte(0xE000u16, 0u8);
+                bus.write_byte(0xE001u16, 0u8);
+            }
+        }
+        scanlines
+    }
+
+    #[test]
+    fn test_irq_scanline() {
+        let mut bus = build_bus();
+        bus.write_byte(0xC000u16, 20);
+        bus.write_byte(0xC001u16, 0u8);
+        bus.write_byte(0xE001u16, 0u8);
+
+        // Reloaded on the pre-render line, then counted down once a line.
+        // It carries on reloading every 21 lines.
+        let expected: Vec<usize> = (0..11).map(|i| 19 + i * 21).collect();
+        assert_eq!(irq_scanlines(&mut bus), expected);
+    }
+
+    #[test]
+    fn test_irq_reload() {
+        let mut bus = build_bus();
+        bus.write_byte(0xC000u16, 20);
+        bus.write_byte(0xC001u16, 0u8);
+        bus.write_byte(0xE001u16, 0u8);
+
+        // Clearing the counter part way through reloads it on the next clock,
+        // and a new period only takes effect on a reload
+        run_until(&mut bus, 10);
+        bus.write_byte(0xC000u16, 5);
+        bus.write_byte(0xC001u16, 0u8);
+        assert_eq!(irq_scanlines(&mut bus)[..3], [15, 21, 27]);
+    }
+
+    #[test]
+    fn test_irq_disable() {
+        let mut bus = build_bus();
+        bus.write_byte(0xC000u16, 20);
+        bus.write_byte(0xC001u16, 0u8);
+
+        // The counter runs while IRQs are disabled, but nothing is raised
+        assert!(irq_scanlines(&mut bus).is_empty());
+
+        // Disabling IRQs also acknowledges a pending one
+        bus.write_byte(0xE001u16, 0u8);
+        run_until(&mut bus, 30);
+        assert!(irq(&bus));
+        bus.write_byte(0xE000u16, 0u8);
+        assert!(!irq(&bus));
     }
 }
@@ -0,0 +1,161 @@
+// Mapper71 implements ines mapper 71 (Camerica/Codemasters)
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_071
+//
+// It's UxROM with the bank register at $C000-$FFFF. Fire Hawk's board also
+// picks a single screen nametable with bit 4 of writes to $8000-$9FFF; other
+// boards never write there, so they keep the header's mirroring.
+
+use super::CartridgeData;
+use super::mapper::{standard_address_kind, AddressKind};
+use super::Mapper;
+use super::Mirroring;
+use super::pager::Page;
+use super::pager::PageSize;
+
+#[derive(Clone)]
+pub struct Mapper71 {
+    data: CartridgeData,
+    prg_0: usize,
+    mirroring: Option<Mirroring>,
+}
+
+impl Mapper71 {
+    pub fn new(data: CartridgeData) -> Self {
+        Mapper71 {
+            data,
+            prg_0: 0,
+            mirroring: None,
+        }
+    }
+}
+
+impl Mapper for Mapper71 {
+    fn reset(&mut self) {
+        *self = Mapper71::new(self.data.clone());
+    }
+
+    fn volatile_prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.data.volatile_prg_ram()
+    }
+
+    fn read_prg_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000...0xBFFF => Some(self.data.prg_rom.read(
+                Page::Number(self.prg_0, PageSize::SixteenKb),
+                address - 0x8000,
+            )),
+            0xC000...0xFFFF => Some(self.data
+                .prg_rom
+                .read(Page::Last(PageSize::SixteenKb), address - 0xC000)),
+            _ => None,
+        }
+    }
+
+    fn write_prg_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000...0x9FFF => {
+                self.mirroring = Some(if value & 0x10 == 0 {
+                    Mirroring::SingleScreenLower
+                } else {
+                    Mirroring::SingleScreenUpper
+                });
+            }
+            0xC000...0xFFFF => {
+                self.prg_0 = (value as usize & 0x0F) % self.data.header.prg_rom_pages.max(1);
+            }
+            _ => (),
+        }
+    }
+
+    fn read_chr_byte(&self, address: u16) -> u8 {
+        if self.data.header.chr_rom_pages == 0 {
+            self.data
+                .chr_ram
+                .read(Page::First(PageSize::EightKb), address)
+        } else {
+            self.data
+                .chr_rom
+                .read(Page::First(PageSize::EightKb), address)
+        }
+    }
+
+    fn write_chr_byte(&mut self, address: u16, value: u8) {
+        if self.data.header.chr_rom_pages == 0 {
+            self.data
+                .chr_ram
+                .write(Page::First(PageSize::EightKb), address, value)
+        }
+    }
+
+    fn address_kind(&self, address: u16) -> AddressKind {
+        standard_address_kind(address, false, self.data.header.chr_rom_pages == 0)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.unwrap_or(self.data.header.mirroring)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_cartridge_data() -> CartridgeData {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x08, // 8 x 16kb prg rom
+            0x00, // CHR-RAM
+            0x71, // Mapper 71, vertical mirroring
+            0x40,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+
+        // Each 16kb page of PRG-ROM is filled with its page number
+        for i in 0..0x4000 * 8 {
+            data.push((i / 0x4000) as u8);
+        }
+
+        CartridgeData::try_new(&data).unwrap()
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = Mapper71::new(build_cartridge_data());
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(0));
+        assert_eq!(mapper.read_prg_byte(0xC000), Some(7));
+
+        mapper.write_prg_byte(0xC000, 3);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(3));
+        assert_eq!(mapper.read_prg_byte(0xBFFF), Some(3));
+        assert_eq!(mapper.read_prg_byte(0xFFFF), Some(7));
+
+        mapper.write_prg_byte(0xF123, 5);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(5));
+
+        // Unlike UxROM, $8000-$BFFF isn't the bank register
+        mapper.write_prg_byte(0xA000, 1);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(5));
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = Mapper71::new(build_cartridge_data());
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        mapper.write_prg_byte(0x9000, 0x10);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+        mapper.write_prg_byte(0x9000, 0x00);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        assert_eq!(mapper.read_prg_byte(0x8000), Some(0));
+    }
+}
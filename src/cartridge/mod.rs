@@ -2,34 +2,103 @@ mod pager;
 mod cartridge_header;
 mod cartridge_data;
 mod mapper;
+mod fds;
 mod mapper0;
 mod mapper1;
 mod mapper2;
 mod mapper3;
 mod mapper4;
+mod mapper11;
+mod mapper13;
+mod mapper19;
+mod mapper24;
+mod mapper34;
+mod mapper71;
 
 use self::cartridge_data::CartridgeData;
 use self::mapper::Mapper;
+pub use self::mapper::{AddressKind, IrqState};
+use self::fds::Fds;
 use self::mapper0::Mapper0;
 use self::mapper1::Mapper1;
 use self::mapper2::Mapper2;
 use self::mapper3::Mapper3;
 use self::mapper4::Mapper4;
+use self::mapper11::Mapper11;
+use self::mapper13::Mapper13;
+use self::mapper19::Mapper19;
+use self::mapper24::Mapper24;
+use self::mapper34::Mapper34;
+use self::mapper71::Mapper71;
+use ram_init::RamInit;
+use std::error::Error;
+use std::fmt;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+    // Which page of console RAM each of the four nametables uses
+    Custom([u8; 4]),
     None,
 }
 
+// Why a ROM couldn't be loaded
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RomError {
+    // Shorter than the 16 byte iNES header
+    TooShort,
+    // Doesn't start with "NES<EOF>"
+    BadMagic,
+    UnsupportedMapper(u8),
+    // Shorter than the ROM sizes given in the header
+    SizeMismatch,
+    // An FDS BIOS that isn't 8kb
+    BadFdsBios,
+    // An FDS disk image that isn't a whole number of sides
+    BadDiskImage,
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::TooShort => write!(f, "ROM is too short to have an iNES header"),
+            RomError::BadMagic => write!(f, "ROM is missing the iNES header"),
+            RomError::UnsupportedMapper(n) => write!(f, "Mapper {} not implemented", n),
+            RomError::SizeMismatch => write!(f, "ROM is shorter than its header says"),
+            RomError::BadFdsBios => write!(f, "FDS BIOS must be 8kb"),
+            RomError::BadDiskImage => write!(f, "Disk image must be a whole number of sides"),
+        }
+    }
+}
+
+impl Error for RomError {}
+
 pub struct Cartridge {
     mapper: Box<Mapper>,
 }
 
+impl Clone for Cartridge {
+    fn clone(&self) -> Self {
+        Cartridge {
+            mapper: self.mapper.clone_box(),
+        }
+    }
+}
+
 impl Cartridge {
     pub fn new(data: &[u8]) -> Self {
-        let data = CartridgeData::new(data);
+        match Cartridge::try_new(data) {
+            Ok(c) => c,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(data: &[u8]) -> Result<Self, RomError> {
+        let data = CartridgeData::try_new(data)?;
 
         let mapper: Box<Mapper> = match data.header.mapper_number {
             0 => Box::new(Mapper0::new(data)),
@@ -37,20 +106,42 @@ impl Cartridge {
             2 => Box::new(Mapper2::new(data)),
             3 => Box::new(Mapper3::new(data)),
             4 => Box::new(Mapper4::new(data)),
-            n => panic!("Mapper {} not implemented", n),
+            11 => Box::new(Mapper11::new(data)),
+            13 => Box::new(Mapper13::new(data)),
+            19 => Box::new(Mapper19::new(data)),
+            24 => Box::new(Mapper24::new(data)),
+            26 => Box::new(Mapper24::new_swapped(data)),
+            34 => Box::new(Mapper34::new(data)),
+            71 => Box::new(Mapper71::new(data)),
+            n => return Err(RomError::UnsupportedMapper(n)),
         };
 
-        Cartridge { mapper: mapper }
+        Ok(Cartridge { mapper: mapper })
+    }
+
+    // A Famicom Disk System, which runs from a BIOS image rather than ROMs
+    pub fn try_new_fds(bios: &[u8], disk: &[u8]) -> Result<Self, RomError> {
+        Ok(Cartridge {
+            mapper: Box::new(Fds::try_new(bios, disk)?),
+        })
     }
 
     pub fn signal_scanline(&mut self) {
         self.mapper.signal_scanline();
     }
 
-    pub fn read_prg_byte(&self, address: u16) -> u8 {
+    pub fn signal_ppu_address(&mut self, address: u16) {
+        self.mapper.signal_ppu_address(address);
+    }
+
+    pub fn read_prg_byte(&self, address: u16) -> Option<u8> {
         self.mapper.read_prg_byte(address)
     }
 
+    pub fn peek_prg_byte(&self, address: u16) -> Option<u8> {
+        self.mapper.peek_prg_byte(address)
+    }
+
     pub fn write_prg_byte(&mut self, address: u16, value: u8) {
         self.mapper.write_prg_byte(address, value);
     }
@@ -63,6 +154,10 @@ impl Cartridge {
         self.mapper.write_chr_byte(address, value)
     }
 
+    pub fn chr_console_ram_page(&self, address: u16) -> Option<u8> {
+        self.mapper.chr_console_ram_page(address)
+    }
+
     pub fn mirroring(&self) -> Mirroring {
         self.mapper.mirroring()
     }
@@ -70,6 +165,46 @@ impl Cartridge {
     pub fn irq_flag(&self) -> bool {
         self.mapper.irq_flag()
     }
+
+    pub fn irq_state(&self) -> Option<IrqState> {
+        self.mapper.irq_state()
+    }
+
+    pub fn tick(&mut self) {
+        self.mapper.tick();
+    }
+
+    pub fn mapper_audio_sample(&self) -> f32 {
+        self.mapper.mapper_audio_sample()
+    }
+
+    pub fn set_disk_side(&mut self, side: u8) {
+        self.mapper.set_disk_side(side);
+    }
+
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.mapper.set_bus_conflicts(enabled);
+    }
+
+    // The console's reset button doesn't reach the cartridge, so this is
+    // only for debugging
+    pub fn reset(&mut self) {
+        self.mapper.reset();
+    }
+
+    // Turning the console off and on. The mapper's registers go back to
+    // how they start, as does PRG-RAM unless there's a battery for it.
+    pub fn power_on(&mut self, ram_init: RamInit) {
+        self.mapper.reset();
+        if let Some(prg_ram) = self.mapper.volatile_prg_ram() {
+            ram_init.fill(prg_ram);
+        }
+    }
+
+    // For debuggers, with the mapper's current configuration
+    pub fn address_kind(&self, address: u16) -> AddressKind {
+        self.mapper.address_kind(address)
+    }
 }
 
 #[cfg(test)]
@@ -117,9 +252,9 @@ mod ppu_test {
         let cartridge = build_cartridge(false);
         for i in 0..0x8000u16 {
             if i % 2 == 0 {
-                assert_eq!(cartridge.read_prg_byte(0x8000 + i), ((i / 2) >> 8) as u8);
+                assert_eq!(cartridge.read_prg_byte(0x8000 + i), Some(((i / 2) >> 8) as u8));
             } else {
-                assert_eq!(cartridge.read_prg_byte(0x8000 + i), (i / 2) as u8);
+                assert_eq!(cartridge.read_prg_byte(0x8000 + i), Some((i / 2) as u8));
             }
         }
     }
@@ -129,7 +264,7 @@ mod ppu_test {
         let mut cartridge = build_cartridge(false);
         for i in 0x6000u16..0x7000u16 {
             cartridge.write_prg_byte(i, i as u8);
-            assert_eq!(cartridge.read_prg_byte(i), i as u8);
+            assert_eq!(cartridge.read_prg_byte(i), Some(i as u8));
         }
     }
 
@@ -154,3 +289,108 @@ mod ppu_test {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_rom(mapper: u8) -> Vec<u8> {
+        let mut data = vec![
+            0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01, mapper << 4, mapper & 0xF0, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        data.extend_from_slice(&[0; 0x4000 + 0x2000]);
+        data
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(Cartridge::try_new(&build_rom(0)).is_ok());
+        assert_eq!(Cartridge::try_new(&build_rom(0)[..10]).err(), Some(RomError::TooShort));
+
+        let mut rom = build_rom(0);
+        rom[3] = 0;
+        assert_eq!(Cartridge::try_new(&rom).err(), Some(RomError::BadMagic));
+
+        assert_eq!(
+            Cartridge::try_new(&build_rom(0xFF)).err(),
+            Some(RomError::UnsupportedMapper(0xFF))
+        );
+
+        let rom = build_rom(0);
+        assert_eq!(
+            Cartridge::try_new(&rom[..rom.len() - 1]).err(),
+            Some(RomError::SizeMismatch)
+        );
+    }
+
+    #[test]
+    fn test_address_kind() {
+        let cartridge = Cartridge::new(&build_rom(0));
+        assert_eq!(cartridge.address_kind(0x8000), AddressKind::PrgRom);
+        assert_eq!(cartridge.address_kind(0xFFFF), AddressKind::PrgRom);
+        assert_eq!(cartridge.address_kind(0x6000), AddressKind::PrgRam);
+        assert_eq!(cartridge.address_kind(0x5000), AddressKind::Unmapped);
+        assert_eq!(cartridge.address_kind(0x0000), AddressKind::ChrRom);
+    }
+
+    #[test]
+    fn test_out_of_range_banks() {
+        // The smallest ROMs, where almost any bank number is past the end
+        for &mapper in [0, 1, 2, 3, 4, 11, 13, 19, 24, 26, 34, 71].iter() {
+            for &chr_pages in [0, 1].iter() {
+                let mut rom = build_rom(mapper);
+                rom[5] = chr_pages;
+                rom.truncate(16 + 0x4000 + 0x2000 * chr_pages as usize);
+                let mut cartridge = Cartridge::new(&rom);
+
+                // 0x7F gets through MMC1's shift register, which 0xFF resets
+                for &value in [0xFF, 0x7F].iter() {
+                    for address in 0x4020..0x10000 {
+                        cartridge.write_prg_byte(address as u16, value);
+                    }
+                    for address in 0x4020..0x10000 {
+                        cartridge.read_prg_byte(address as u16);
+                    }
+                    for address in 0..0x2000 {
+                        cartridge.write_chr_byte(address, value);
+                        cartridge.read_chr_byte(address);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_power_on() {
+        // MMC3 with PRG-RAM disabled
+        let mut cartridge = Cartridge::new(&build_rom(4));
+        cartridge.write_prg_byte(0x6000, 0x12);
+        cartridge.write_prg_byte(0xA001, 0x00);
+        assert_eq!(cartridge.read_prg_byte(0x6000), None);
+
+        cartridge.power_on(RamInit::Ones);
+        assert_eq!(cartridge.read_prg_byte(0x6000), Some(0xFF));
+
+        // With a battery, PRG-RAM survives
+        let mut rom = build_rom(4);
+        rom[6] |= 0b10;
+        let mut cartridge = Cartridge::new(&rom);
+        cartridge.write_prg_byte(0x6000, 0x12);
+        cartridge.write_prg_byte(0xA001, 0x00);
+        cartridge.power_on(RamInit::Ones);
+        assert_eq!(cartridge.read_prg_byte(0x6000), Some(0x12));
+    }
+
+    #[test]
+    fn test_expansion_area() {
+        // None of these boards decode $4020-$5FFF, so it's open bus
+        for &mapper in [0, 1, 2, 3, 4, 11, 13, 24, 26, 34, 71].iter() {
+            let mut cartridge = Cartridge::new(&build_rom(mapper));
+            cartridge.write_prg_byte(0x5C00, 0x12);
+            cartridge.write_prg_byte(0x4020, 0x12);
+            assert_eq!(cartridge.read_prg_byte(0x5C00), None, "mapper {}", mapper);
+            assert_eq!(cartridge.read_prg_byte(0x4020), None, "mapper {}", mapper);
+        }
+    }
+}
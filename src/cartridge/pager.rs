@@ -4,6 +4,7 @@ pub enum PageSize {
     FourKb = 0x1000,
     EightKb = 0x2000,
     SixteenKb = 0x4000,
+    ThirtyTwoKb = 0x8000,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -14,6 +15,7 @@ pub enum Page {
     FromEnd(usize, PageSize),
 }
 
+#[derive(Clone)]
 pub struct Pager {
     pub data: Vec<u8>,
 }
@@ -23,46 +25,48 @@ impl Pager {
         Pager { data }
     }
 
+    // Boards with nothing behind a page, like CHR-ROM on one that uses
+    // CHR-RAM, read as 0 and ignore writes
     pub fn read(&self, page: Page, offset: u16) -> u8 {
-        let i = self.index(page, offset);
-        self.data[i]
+        match self.index(page, offset) {
+            Some(i) => self.data[i],
+            None => 0,
+        }
     }
 
     pub fn write(&mut self, page: Page, offset: u16, value: u8) {
-        let i = self.index(page, offset);
-        self.data[i] = value;
+        if let Some(i) = self.index(page, offset) {
+            self.data[i] = value;
+        }
     }
 
+    // Data smaller than a page still counts as one page
     fn page_count(&self, size: PageSize) -> usize {
-        if self.data.len() % (size as usize) != 0 {
-            panic!("Page size must divide evenly into data length")
-        }
-
-        self.data.len() / (size as usize)
+        (self.data.len() / (size as usize)).max(1)
     }
 
-    fn index(&self, page: Page, offset: u16) -> usize {
-        match page {
-            Page::First(size) => self.index(Page::Number(0, size), offset),
-            Page::Last(size) => {
-                let last_page = self.page_count(size) - 1;
-                self.index(Page::Number(last_page, size), offset)
-            }
-            Page::Number(n, size) => {
-                let last_page = self.page_count(size) - 1;
-                if (offset as usize) > (size as usize) {
-                    panic!("Offset cannot exceed page bounds")
-                }
-                if n > last_page {
-                    panic!("Page out of bounds")
-                }
-                n * (size as usize) + (offset as usize)
-            }
+    // Pages past the end wrap around, like the ROM's unconnected address
+    // lines would, as does data smaller than the page size.
+    fn index(&self, page: Page, offset: u16) -> Option<usize> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let (n, size) = match page {
+            Page::First(size) => (0, size),
+            Page::Last(size) => (self.page_count(size) - 1, size),
+            Page::Number(n, size) => (n, size),
             Page::FromEnd(n, size) => {
-                let last_page = self.page_count(size) - 1;
-                self.index(Page::Number(last_page - n, size), offset)
+                let page_count = self.page_count(size);
+                (page_count - 1 - n % page_count, size)
             }
+        };
+        if (offset as usize) > (size as usize) {
+            panic!("Offset cannot exceed page bounds")
         }
+
+        let n = n % self.page_count(size);
+        Some((n * (size as usize) + (offset as usize)) % self.data.len())
     }
 }
 
@@ -89,15 +93,15 @@ mod test {
     #[test]
     fn test_index_first() {
         let pager = build_pager();
-        assert_eq!(4, pager.index(Page::First(PageSize::SixteenKb), 4));
-        assert_eq!(8, pager.index(Page::First(PageSize::SixteenKb), 8));
+        assert_eq!(Some(4), pager.index(Page::First(PageSize::SixteenKb), 4));
+        assert_eq!(Some(8), pager.index(Page::First(PageSize::SixteenKb), 8));
     }
 
     #[test]
     fn test_index_last() {
         let pager = build_pager();
         assert_eq!(
-            0x4000 * 3 + 42,
+            Some(0x4000 * 3 + 42),
             pager.index(Page::Last(PageSize::SixteenKb), 42)
         );
     }
@@ -106,7 +110,7 @@ mod test {
     fn test_index_number() {
         let pager = build_pager();
         assert_eq!(
-            0x1000 * 3 + 36,
+            Some(0x1000 * 3 + 36),
             pager.index(Page::Number(3, PageSize::FourKb), 36)
         );
     }
@@ -122,10 +126,30 @@ mod test {
     }
 
     #[test]
-    #[should_panic]
-    fn test_index_nopage() {
+    fn test_index_wraps() {
         let pager = build_pager();
-        pager.index(Page::Number(100, PageSize::SixteenKb), 0);
+        assert_eq!(
+            Some(0x4000 * 2 + 5),
+            pager.index(Page::Number(6, PageSize::SixteenKb), 5)
+        );
+        assert_eq!(
+            Some(0x4000 * 3),
+            pager.index(Page::FromEnd(4, PageSize::SixteenKb), 0)
+        );
+
+        // A 32kb page of 16kb of data is the data twice
+        let pager = Pager::new(vec![0; 0x4000]);
+        assert_eq!(
+            Some(5),
+            pager.index(Page::Last(PageSize::ThirtyTwoKb), 0x4005)
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut pager = Pager::new(Vec::new());
+        pager.write(Page::Last(PageSize::EightKb), 5, 0x66);
+        assert_eq!(0, pager.read(Page::Last(PageSize::EightKb), 5));
     }
 
     #[test]
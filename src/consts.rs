@@ -0,0 +1,15 @@
+// Canonical NES figures, useful to frontends as well as the emulator itself.
+// Timings are from http://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+// Rows hidden at the top and bottom by most TVs
+pub const OVERSCAN_ROWS: usize = 8;
+
+pub const NTSC_FPS: f64 = 60.0988;
+pub const PAL_FPS: f64 = 50.0070;
+
+pub const NTSC_CPU_HZ: f64 = 1_789_773.0;
+pub const PAL_CPU_HZ: f64 = 1_662_607.0;
+
+pub const AUDIO_SAMPLE_RATE_DEFAULT: f64 = 44100.0;
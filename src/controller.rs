@@ -1,3 +1,6 @@
+use paddle::Paddle;
+use zapper::Zapper;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Button {
     A = 0b0000_0001,
@@ -10,10 +13,26 @@ pub enum Button {
     Right = 0b1000_0000,
 }
 
+pub const BUTTONS: [Button; 8] = [
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+];
+
+#[derive(Clone)]
 pub struct Controller {
     button_states: u8,
     strobe: bool,
     cursor: usize,
+    turbo_buttons: u8,
+    turbo_divider: usize,
+    turbo_frames: usize,
+    turbo_released: bool,
 }
 
 impl Controller {
@@ -22,6 +41,10 @@ impl Controller {
             button_states: 0,
             strobe: false,
             cursor: 0,
+            turbo_buttons: 0,
+            turbo_divider: 1,
+            turbo_frames: 0,
+            turbo_released: false,
         }
     }
 
@@ -34,7 +57,7 @@ impl Controller {
 
     pub fn read_register(&mut self) -> u8 {
         let v = if self.cursor < 8 {
-            self.button_states >> self.cursor & 1
+            self.button_states() >> self.cursor & 1
         } else {
             1
         };
@@ -43,7 +66,7 @@ impl Controller {
             self.cursor += 1;
         }
 
-        0x40 | v
+        v
     }
 
     pub fn set_button_state(&mut self, button: Button, pressed: bool) {
@@ -52,4 +75,209 @@ impl Controller {
             self.button_states |= button as u8;
         }
     }
+
+    // Held turbo buttons read as released for every other turbo period
+    pub fn button_states(&self) -> u8 {
+        if self.turbo_released {
+            self.button_states & !self.turbo_buttons
+        } else {
+            self.button_states
+        }
+    }
+
+    pub fn set_turbo(&mut self, button: Button, enabled: bool) {
+        self.turbo_buttons &= !(button as u8);
+        if enabled {
+            self.turbo_buttons |= button as u8;
+        }
+    }
+
+    // How many frames turbo buttons stay pressed, and then released, for
+    pub fn set_turbo_divider(&mut self, frames: usize) {
+        self.turbo_divider = frames.max(1);
+    }
+
+    pub fn tick_frame(&mut self) {
+        self.turbo_frames += 1;
+        if self.turbo_frames >= self.turbo_divider {
+            self.turbo_frames = 0;
+            self.turbo_released = !self.turbo_released;
+        }
+    }
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// What's plugged into a controller port
+#[derive(Clone)]
+pub enum InputDevice {
+    Controller(Controller),
+    Zapper(Zapper),
+    Paddle(Paddle),
+}
+
+impl InputDevice {
+    pub fn write_register(&mut self, value: u8) {
+        match *self {
+            InputDevice::Controller(ref mut c) => c.write_register(value),
+            InputDevice::Paddle(ref mut p) => p.write_register(value),
+            InputDevice::Zapper(_) => (),
+        }
+    }
+
+    pub fn read_register(&mut self) -> u8 {
+        match *self {
+            InputDevice::Controller(ref mut c) => c.read_register(),
+            InputDevice::Zapper(ref z) => z.read_register(),
+            InputDevice::Paddle(ref mut p) => p.read_register(),
+        }
+    }
+
+    // Buttons are ignored by anything other than a controller
+    pub fn set_button_state(&mut self, button: Button, pressed: bool) {
+        if let InputDevice::Controller(ref mut c) = *self {
+            c.set_button_state(button, pressed);
+        }
+    }
+
+    pub fn button_states(&self) -> u8 {
+        match *self {
+            InputDevice::Controller(ref c) => c.button_states(),
+            InputDevice::Zapper(_) | InputDevice::Paddle(_) => 0,
+        }
+    }
+
+    pub fn tick_frame(&mut self) {
+        if let InputDevice::Controller(ref mut c) = *self {
+            c.tick_frame();
+        }
+    }
+}
+
+// The signatures sent after both controllers on each port, which is how
+// games detect a Four Score
+const FOUR_SCORE_SIGNATURES: [u8; 2] = [0b0001_0000, 0b0010_0000];
+
+// FourScore implements the four player adapter
+// http://wiki.nesdev.com/w/index.php/Four_player_adapters
+//
+// Each port sends the buttons of two controllers one after the other, then
+// a signature byte, then all 1s.
+#[derive(Clone)]
+pub struct FourScore {
+    strobe: bool,
+    cursors: [usize; 2],
+}
+
+impl FourScore {
+    pub fn new() -> Self {
+        FourScore {
+            strobe: false,
+            cursors: [0; 2],
+        }
+    }
+
+    pub fn write_register(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.cursors = [0; 2];
+        }
+    }
+
+    pub fn read_register(&mut self, port: usize, first: u8, second: u8) -> u8 {
+        let cursor = self.cursors[port];
+        let v = match cursor {
+            0...7 => first >> cursor & 1,
+            8...15 => second >> (cursor - 8) & 1,
+            16...23 => FOUR_SCORE_SIGNATURES[port] >> (cursor - 16) & 1,
+            _ => 1,
+        };
+
+        if !self.strobe {
+            self.cursors[port] += 1;
+        }
+
+        v
+    }
+}
+
+impl Default for FourScore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_a(controller: &mut Controller) -> u8 {
+        controller.write_register(1);
+        controller.write_register(0);
+        controller.read_register() & 1
+    }
+
+    #[test]
+    fn test_read_sequence() {
+        let mut controller = Controller::new();
+        controller.set_button_state(Button::A, true);
+        controller.write_register(1);
+        controller.write_register(0);
+
+        let mut bits: Vec<u8> = (0..4).map(|_| controller.read_register() & 1).collect();
+
+        // Buttons are read live, so only ones not yet shifted out change
+        controller.set_button_state(Button::A, false);
+        controller.set_button_state(Button::Left, true);
+        bits.extend((0..4).map(|_| controller.read_register() & 1));
+        assert_eq!(bits, [1, 0, 0, 0, 0, 0, 1, 0]);
+
+        // Past the 8th read it returns 1 until strobed again
+        for _ in 0..20 {
+            assert_eq!(controller.read_register() & 1, 1);
+        }
+        controller.write_register(1);
+        controller.write_register(0);
+        assert_eq!(controller.read_register() & 1, 0);
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut controller = Controller::new();
+        controller.set_button_state(Button::A, true);
+        controller.set_button_state(Button::B, true);
+        controller.set_turbo(Button::A, true);
+
+        let mut a = vec![];
+        for _ in 0..4 {
+            a.push(read_a(&mut controller));
+            assert_eq!(controller.button_states() & Button::B as u8, Button::B as u8);
+            controller.tick_frame();
+        }
+        assert_eq!(a, [1, 0, 1, 0]);
+
+        // A released turbo button stays released
+        controller.set_button_state(Button::A, false);
+        controller.tick_frame();
+        assert_eq!(read_a(&mut controller), 0);
+    }
+
+    #[test]
+    fn test_turbo_divider() {
+        let mut controller = Controller::new();
+        controller.set_button_state(Button::A, true);
+        controller.set_turbo(Button::A, true);
+        controller.set_turbo_divider(2);
+
+        let mut a = vec![];
+        for _ in 0..6 {
+            a.push(read_a(&mut controller));
+            controller.tick_frame();
+        }
+        assert_eq!(a, [1, 1, 0, 0, 1, 1]);
+    }
 }
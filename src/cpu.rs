@@ -1,6 +1,9 @@
 use bus::Bus;
+use hang::HangDetector;
+#[cfg(feature = "debug-tools")]
 use std::fmt::Write;
 
+#[cfg(feature = "debug-tools")]
 use cpu_debug::{INSTRUCTION_NAMES, INSTRUCTION_SIZES};
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
@@ -42,6 +45,18 @@ enum Interrupt {
     Break,
 }
 
+// The CPU's registers at one moment, for debuggers and tests
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CpuState {
+    pub pc: u16,
+    pub sp: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+}
+
+#[derive(Clone)]
 pub struct Cpu {
     pub bus: Bus,
     pc: u16,
@@ -50,6 +65,9 @@ pub struct Cpu {
     x: u8,
     y: u8,
     p: u8,
+    unofficial_opcodes: bool,
+    jammed: bool,
+    hang_detector: Option<HangDetector>,
 }
 
 impl Cpu {
@@ -62,10 +80,54 @@ impl Cpu {
             x: 0,
             y: 0,
             p: 0,
+            unofficial_opcodes: true,
+            jammed: false,
+            hang_detector: None,
+        }
+    }
+
+    // A copy of the console which shares nothing but ROM with this one. It
+    // has no output buffers, so give it some with restore_output before
+    // running it.
+    pub fn snapshot(&mut self) -> Self {
+        let output = self.bus.take_output();
+        let mut cpu = self.clone();
+        self.bus.restore_output(output);
+        cpu.bus.detach_cartridge();
+        cpu
+    }
+
+    // Unofficial opcodes are run by default. Without them they're treated
+    // like undefined opcodes and jam the CPU, which shows whether a game
+    // relies on them.
+    pub fn set_unofficial_opcodes_enabled(&mut self, enabled: bool) {
+        self.unofficial_opcodes = enabled;
+    }
+
+    // Watches for the CPU getting stuck in a tight loop, for test harnesses.
+    // Off by default, as it compares registers before every instruction.
+    pub fn set_hang_detection(&mut self, limit: Option<usize>) {
+        self.hang_detector = limit.map(HangDetector::new);
+    }
+
+    // Undefined opcodes stop the CPU until the next reset, with the PC left
+    // at the opcode. The rest of the console keeps running.
+    pub fn jammed(&self) -> bool {
+        self.jammed
+    }
+
+    pub fn hung(&self) -> bool {
+        if self.jammed {
+            return true;
+        }
+        match self.hang_detector {
+            Some(ref detector) => detector.hung(),
+            None => false,
         }
     }
 
     pub fn reset(&mut self) {
+        self.jammed = false;
         self.sp = 0xFF;
         self.p = 0x34;
         self.interrupt(Interrupt::Reset);
@@ -244,14 +306,15 @@ impl Cpu {
         self.pc = self.bus.read_word(address);
     }
 
+    #[cfg(feature = "debug-tools")]
     #[allow(dead_code)]
     pub fn log_next_instruction(&mut self) {
         let pc = self.pc;
         let rom_offset = 15 + (self.pc % 0x4000);
-        let opcode = self.bus.unclocked_read_byte(pc) as usize;
+        let opcode = self.bus.peek_byte(pc) as usize;
         let mut args = String::new();
         for i in 1..INSTRUCTION_SIZES[opcode] {
-            write!(&mut args, "{:02X} ", self.bus.unclocked_read_byte(pc + i)).expect("it to work");
+            write!(&mut args, "{:02X} ", self.bus.peek_byte(pc + i)).expect("it to work");
         }
         println!(
             "OFFSET:{:06x}\tPC:{:04x}\tA:{:02x}\tX:{:02x}\tY:{:02x}\tP:{:08b}\tTEST:{:02x}\t[{:02x}] {}\t{}",
@@ -261,28 +324,160 @@ impl Cpu {
             self.x,
             self.y,
             self.p,
-            self.bus.unclocked_read_byte(0x6000),
+            self.bus.peek_byte(0x6000),
             opcode,
             INSTRUCTION_NAMES[opcode as usize],
             args,
         );
     }
 
+    // The next instruction and the registers before it runs, laid out like
+    // the columns of nestest.log:
+    //
+    // C000  4C F5 C5  JMP abs     A:00 X:00 Y:00 P:24 SP:FD CYC:7
+    #[cfg(feature = "debug-tools")]
+    pub fn trace_line(&mut self) -> String {
+        let pc = self.pc;
+        let opcode = self.bus.peek_byte(pc) as usize;
+        let mut bytes = String::new();
+        for i in 0..INSTRUCTION_SIZES[opcode] {
+            let b = self.bus.peek_byte(pc.wrapping_add(i));
+            write!(&mut bytes, "{:02X} ", b).expect("it to work");
+        }
+        format!(
+            "{:04X}  {:<9} {:<11} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            pc,
+            bytes,
+            INSTRUCTION_NAMES[opcode],
+            self.a,
+            self.x,
+            self.y,
+            self.p,
+            self.sp,
+            self.bus.cycles,
+        )
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    // The P register, which never has the Break or Push flags set
+    pub fn status(&self) -> u8 {
+        self.p
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            sp: self.sp,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            status: self.p,
+        }
+    }
+
+    // Runs instructions, along with any cycles the CPU is stalled by DMA,
+    // until the PPU has finished drawing a frame.
+    pub fn run_frame(&mut self) {
+        while !self.bus.draw {
+            self.execute_next_instruction();
+            self.tick_stall_cycles();
+        }
+
+        self.bus.draw = false;
+    }
+
+    // Runs until an NMI has been serviced, leaving the CPU at the first
+    // instruction of the NMI handler. Games run their per-frame logic from
+    // the handler, so this steps in time with the game rather than the video.
+    pub fn run_until_nmi(&mut self) {
+        loop {
+            // A jammed CPU never takes the NMI, so stop at the end of the frame
+            if self.jammed {
+                if self.bus.draw {
+                    break;
+                }
+                self.execute_instruction_at_pc();
+                continue;
+            }
+            let interrupt = self.service_interrupt();
+            if interrupt == Some(Interrupt::Nmi) {
+                self.tick_stall_cycles();
+                break;
+            }
+            self.execute_instruction_at_pc();
+            self.tick_stall_cycles();
+        }
+
+        // The frame was drawn on the way to vblank
+        self.bus.draw = false;
+    }
+
     pub fn execute_next_instruction(&mut self) {
+        if !self.jammed {
+            self.service_interrupt();
+        }
+        self.execute_instruction_at_pc();
+    }
+
+    fn service_interrupt(&mut self) -> Option<Interrupt> {
         if self.bus.nmi.ready() {
             self.bus.nmi.acknowledge();
-            self.interrupt(Interrupt::Nmi)
+            self.interrupt(Interrupt::Nmi);
+            Some(Interrupt::Nmi)
         } else if self.bus.irq() && !self.get_flag(Flag::IrqDisable) {
-            self.interrupt(Interrupt::Irq)
+            self.interrupt(Interrupt::Irq);
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
+    }
+
+    fn execute_instruction_at_pc(&mut self) {
+        if self.jammed {
+            self.bus.tick();
+            return;
         }
 
         #[cfg(feature = "log")]
         self.log_next_instruction();
 
+        if self.hang_detector.is_some() {
+            let state = self.state();
+            if let Some(ref mut detector) = self.hang_detector {
+                detector.check(state);
+            }
+        }
+
         let instruction = self.next_byte();
         self.execute_instruction(instruction);
     }
 
+    fn tick_stall_cycles(&mut self) {
+        let stall_cycles = self.bus.reset_cpu_stall_cycles();
+        for _ in 0..stall_cycles {
+            self.bus.tick_oam_dma_cycle()
+        }
+    }
+
     fn execute_instruction(&mut self, opcode: u8) {
         match opcode {
             // Loads
@@ -479,6 +674,13 @@ impl Cpu {
             // No operation
             0xea => self.nop(),
 
+            _ if self.unofficial_opcodes => self.execute_unofficial_instruction(opcode),
+            _ => self.jam(),
+        }
+    }
+
+    fn execute_unofficial_instruction(&mut self, opcode: u8) {
+        match opcode {
             // Undocumented Operations
             0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => self.nop(),
 
@@ -565,10 +767,16 @@ impl Cpu {
 
             0x02 => println!("----------------PING----------------------"),
 
-            _ => panic!("unimplemented or illegal instruction: 0x{:X}", opcode),
+            _ => self.jam(),
         }
     }
 
+    // Leaves the PC at the opcode, as the real CPU stops before moving past it
+    fn jam(&mut self) {
+        self.pc = self.pc.wrapping_sub(1);
+        self.jammed = true;
+    }
+
     // Loads
     fn lda(&mut self, mode: Mode) {
         let operand = self.read_operand(mode);
@@ -30,7 +30,7 @@ macro_rules! build_cpu {
             // add the PRG-ROM
             rom.extend_from_slice(&[0u8; 2 * 0x4000]);
             let mut bus = Bus::new();
-            bus.load_rom_from_memory(&rom);
+            bus.load_rom_from_memory(&rom).unwrap();
             let mut cpu = Cpu::new(bus);
             cpu.pc = 0;
             let bytes = $bytes;
@@ -527,6 +527,39 @@ fn test_jsr_ret() {
     assert_eq!(cpu.sp, 0xFF);
 }
 
+#[test]
+fn test_push_wraps_within_page_1() {
+    let mut cpu = build_cpu!([0]);
+    cpu.sp = 0x00;
+    cpu.push_byte(0x12);
+    assert_eq!(cpu.bus.ram[0x0100], 0x12);
+    assert_eq!(cpu.sp, 0xFF);
+    cpu.push_byte(0x34);
+    assert_eq!(cpu.bus.ram[0x01FF], 0x34);
+    assert_eq!(cpu.bus.ram[0x0000], 0);
+    assert_eq!(cpu.bus.ram[0x0200], 0);
+}
+
+#[test]
+fn test_pop_wraps_within_page_1() {
+    let mut cpu = build_cpu!([0]);
+    cpu.bus.ram[0x0100] = 0x12;
+    cpu.bus.ram[0x0101] = 0x34;
+    cpu.bus.ram[0x0200] = 0x56;
+    cpu.sp = 0xFF;
+    assert_eq!(cpu.pop_byte(), 0x12);
+    assert_eq!(cpu.sp, 0x00);
+    assert_eq!(cpu.pop_byte(), 0x34);
+    assert_eq!(cpu.sp, 0x01);
+
+    // A word pushed across the wrap comes back in one piece
+    cpu.sp = 0x00;
+    cpu.push_word(0xABCD);
+    assert_eq!((cpu.bus.ram[0x0100], cpu.bus.ram[0x01FF]), (0xAB, 0xCD));
+    assert_eq!(cpu.pop_word(), 0xABCD);
+    assert_eq!(cpu.sp, 0x00);
+}
+
 #[test]
 fn test_brk() {
     let mut cpu = build_cpu!([0]);
@@ -565,6 +598,126 @@ fn test_php_plp() {
     assert_eq!(cpu.bus.cycles, 3); // Really 4 once you add an opcode read.
 }
 
+#[test]
+fn test_run_frame() {
+    let mut cpu = build_cpu!([0]);
+    cpu.reset();
+    cpu.bus.cycles = 0;
+    cpu.run_frame();
+    assert!(!cpu.bus.draw);
+    // The PPU signals a draw as it enters the first vblank scanline, which is
+    // about 240 scanlines of 341 dots at 3 dots per CPU cycle after reset.
+    assert_eq!(cpu.bus.ppu.renderer.scanline, 240);
+    assert!(cpu.bus.cycles > 239 * 341 / 3);
+    assert!(cpu.bus.cycles < 241 * 341 / 3);
+}
+
+#[test]
+fn test_unofficial_opcodes_enabled() {
+    let mut cpu = build_cpu!([0x07, 0x10]); // SLO $10
+    cpu.bus.ram[0x10] = 0x81;
+    cpu.execute_next_instruction();
+    assert_eq!(cpu.bus.ram[0x10], 0x02);
+}
+
+#[test]
+fn test_unofficial_opcodes_disabled() {
+    let mut cpu = build_cpu!([0x07, 0x10]); // SLO $10
+    cpu.bus.ram[0x10] = 0x81;
+    cpu.set_unofficial_opcodes_enabled(false);
+    cpu.execute_next_instruction();
+    assert!(cpu.jammed());
+    assert!(cpu.hung());
+    assert_eq!(cpu.pc(), 0);
+    assert_eq!(cpu.bus.ram[0x10], 0x81);
+
+    // Time still passes, but nothing more is fetched
+    let cycles = cpu.bus.cycles;
+    cpu.execute_next_instruction();
+    assert_eq!(cpu.pc(), 0);
+    assert_eq!(cpu.bus.cycles, cycles + 1);
+
+    cpu.reset();
+    assert!(!cpu.jammed());
+}
+
+#[test]
+fn test_state() {
+    // LDA #$80; LDX #$01; LDY #$02; PHA
+    let mut cpu = build_cpu!([0xA9, 0x80, 0xA2, 0x01, 0xA0, 0x02, 0x48]);
+    cpu.reset();
+    cpu.pc = 0;
+    for _ in 0..4 {
+        cpu.execute_next_instruction();
+    }
+
+    let state = cpu.state();
+    assert_eq!(
+        state,
+        CpuState {
+            pc: 7,
+            sp: 0xFE,
+            a: 0x80,
+            x: 0x01,
+            y: 0x02,
+            status: 0x34,
+        }
+    );
+    assert_eq!(
+        (cpu.pc(), cpu.sp(), cpu.a(), cpu.x(), cpu.y(), cpu.status()),
+        (state.pc, state.sp, state.a, state.x, state.y, state.status)
+    );
+}
+
+fn run_lda_absolute(dmc_fetch: bool) -> Cpu {
+    let mut cpu = build_cpu!([0xAD, 0x00, 0x02]); // LDA $0200
+    cpu.bus.cycles = 0;
+    if dmc_fetch {
+        // Start a one byte sample, which is fetched during the opcode read
+        cpu.bus.apu.write_register(0x4010, 0x0F, 0);
+        cpu.bus.apu.write_register(0x4012, 0x00, 0);
+        cpu.bus.apu.write_register(0x4013, 0x00, 0);
+        cpu.bus.apu.write_register(0x4015, 0x10, 0);
+    }
+    cpu.execute_next_instruction();
+    cpu
+}
+
+#[test]
+fn test_dmc_fetch_stalls_mid_instruction() {
+    let mut plain = run_lda_absolute(false);
+    let mut stalled = run_lda_absolute(true);
+
+    // The 4 stalled cycles have already passed by the end of the instruction
+    assert_eq!(stalled.bus.cycles, plain.bus.cycles + 4);
+    assert_eq!(
+        stalled.bus.ppu.renderer.dot,
+        plain.bus.ppu.renderer.dot + 4 * 3
+    );
+    assert_eq!(plain.bus.reset_cpu_stall_cycles(), 0);
+    assert_eq!(stalled.bus.reset_cpu_stall_cycles(), 0);
+}
+
+#[test]
+fn test_dmc_fetch_without_stall() {
+    let plain = run_lda_absolute(false);
+
+    let mut cpu = build_cpu!([0xAD, 0x00, 0x02]); // LDA $0200
+    cpu.bus.cycles = 0;
+    cpu.bus.apu.dmc.set_dma_stall_enabled(false);
+    cpu.bus.apu.write_register(0x4010, 0x0F, 0);
+    cpu.bus.apu.write_register(0x4012, 0x00, 0);
+    cpu.bus.apu.write_register(0x4013, 0x00, 0);
+    cpu.bus.apu.write_register(0x4015, 0x10, 0);
+    assert!(cpu.bus.apu.dmc.playing());
+    cpu.execute_next_instruction();
+
+    // The byte was fetched, but the CPU didn't wait for it
+    assert!(!cpu.bus.apu.dmc.playing());
+    assert_eq!(cpu.bus.cycles, plain.bus.cycles);
+    assert_eq!(cpu.bus.reset_cpu_stall_cycles(), 0);
+}
+
 #[derive(Debug)]
 struct Op {
     code: u8,
@@ -1636,3 +1789,23 @@ fn opcode(name: &str, mode: Mode) -> Op {
         (_, _) => panic!("invalid instruction"),
     }
 }
+
+#[cfg(feature = "debug-tools")]
+#[test]
+fn test_trace_line() {
+    // LDA #$80; JMP $1234
+    let mut cpu = build_cpu!([0xA9, 0x80, 0x4C, 0x34, 0x12]);
+    cpu.sp = 0xFD;
+    cpu.p = 0x24;
+    cpu.bus.cycles = 7;
+    assert_eq!(
+        cpu.trace_line(),
+        "0000  A9 80     LDA imm     A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+    );
+
+    cpu.execute_next_instruction();
+    assert_eq!(
+        cpu.trace_line(),
+        "0002  4C 34 12  JMP abs     A:80 X:00 Y:00 P:A4 SP:FD CYC:9"
+    );
+}
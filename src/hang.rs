@@ -0,0 +1,89 @@
+use cpu::CpuState;
+use std::collections::VecDeque;
+
+// How many recent states a loop can cycle through and still count as stuck
+const HISTORY: usize = 8;
+
+// Spots the CPU going round a tight loop that nothing can break it out of,
+// like JMP to itself with interrupts off. Each instruction's register state
+// is compared with the last few; once limit instructions in a row have
+// repeated one, the CPU is hung. An interrupt handler runs with different
+// registers, so a game idling between NMIs never gets to the limit as long
+// as it's more than a frame's worth of instructions.
+#[derive(Clone)]
+pub struct HangDetector {
+    history: VecDeque<CpuState>,
+    limit: usize,
+    repeats: usize,
+}
+
+impl HangDetector {
+    pub fn new(limit: usize) -> Self {
+        HangDetector {
+            history: VecDeque::with_capacity(HISTORY),
+            limit,
+            repeats: 0,
+        }
+    }
+
+    // Called with the registers before each instruction
+    pub fn check(&mut self, state: CpuState) {
+        if self.history.contains(&state) {
+            self.repeats += 1;
+        } else {
+            self.repeats = 0;
+        }
+
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(state);
+    }
+
+    pub fn hung(&self) -> bool {
+        self.repeats >= self.limit
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(pc: u16, x: u8) -> CpuState {
+        CpuState {
+            pc,
+            sp: 0xFD,
+            a: 0,
+            x,
+            y: 0,
+            status: 0x24,
+        }
+    }
+
+    #[test]
+    fn test_two_instruction_loop() {
+        // CLC; BCC back to the CLC
+        let mut detector = HangDetector::new(100);
+        for _ in 0..50 {
+            detector.check(state(0x8000, 0));
+            detector.check(state(0x8001, 0));
+        }
+        // The first time round each instruction wasn't a repeat
+        assert!(!detector.hung());
+        detector.check(state(0x8000, 0));
+        assert!(!detector.hung());
+        detector.check(state(0x8001, 0));
+        assert!(detector.hung());
+    }
+
+    #[test]
+    fn test_counting_loop() {
+        // INX; JMP back to the INX goes round all 256 values of X
+        let mut detector = HangDetector::new(100);
+        for x in 0..100 {
+            detector.check(state(0x8000, x));
+            detector.check(state(0x8001, x + 1));
+        }
+        assert!(!detector.hung());
+    }
+}
@@ -6,33 +6,69 @@ extern crate libretro_backend;
 use libretro_backend::{AudioVideoInfo, CoreInfo, GameData, JoypadButton, LoadGameResult,
                        PixelFormat, Region, RuntimeHandle};
 
+#[cfg(feature = "png")]
+extern crate png;
 extern crate rand;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 extern crate time;
 
+pub mod consts;
 mod cpu;
+#[cfg(feature = "debug-tools")]
 mod cpu_debug;
 mod apu;
 mod ppu;
 mod bus;
 mod cartridge;
 mod controller;
-
-use bus::Bus;
-use cpu::Cpu;
-use controller::Button;
+mod hang;
+mod md5;
+mod model;
+mod movie;
+mod paddle;
+mod ram_init;
+mod rewind;
+mod zapper;
+mod nes;
+
+use consts::{AUDIO_SAMPLE_RATE_DEFAULT, NTSC_FPS};
+
+pub use apu::{AudioFormat, Channel, MixingMode};
+pub use cartridge::{AddressKind, IrqState, RomError};
+pub use controller::{Button, Controller, FourScore, InputDevice};
+pub use cpu::CpuState;
+pub use model::ConsoleModel;
+pub use movie::{Movie, MovieError};
+pub use nes::{Component, Nes};
+pub use ppu::{Frame, Palette, PaletteError, Sprite, PATTERN_TABLE_SIZE};
+pub use paddle::Paddle;
+pub use ram_init::RamInit;
+pub use zapper::Zapper;
 
 struct NesCore {
     game_data: Option<GameData>,
-    cpu: Cpu,
+    nes: Nes,
     frame_count: i32,
     frame_second: i32,
 }
 
 impl NesCore {
     fn new() -> NesCore {
+        let mut nes = Nes::new();
+        // Enough stereo samples to cover the longest frame, with short
+        // frames topped up by the APU
+        let samples_per_frame = (AUDIO_SAMPLE_RATE_DEFAULT / NTSC_FPS).ceil() as usize;
+        nes.cpu.bus.apu.set_audio_format(AudioFormat::Stereo);
+        nes.cpu.bus.apu.set_target_samples_per_frame(samples_per_frame);
+        // Famicom cartridge slots carry expansion audio, so games using it
+        // sound right
+        nes.set_model(ConsoleModel::Famicom);
         NesCore {
             game_data: None,
-            cpu: Cpu::new(Bus::new()),
+            nes,
             frame_count: 0,
             frame_second: 0,
         }
@@ -55,20 +91,25 @@ impl libretro_backend::Core for NesCore {
             return LoadGameResult::Failed(game_data);
         }
 
-        let result: Result<(), ()> = if let Some(data) = game_data.data() {
-            self.cpu.bus.load_rom_from_memory(data);
-            self.cpu.reset();
-            self.cpu.bus.reset();
-            Ok(())
+        let result = if let Some(data) = game_data.data() {
+            self.nes.try_load_rom(data)
         } else {
             panic!("Loading roms from files not supported")
         };
 
         match result {
             Ok(_) => {
+                // The size of the frames that will be uploaded, which is
+                // smaller with overscan cropped
+                let frame = self.nes.cpu.bus.ppu.frame();
                 let av_info = AudioVideoInfo::new()
-                    .video(256, 240, 60.0, PixelFormat::ARGB8888)
-                    .audio(44100.0)
+                    .video(
+                        frame.width as u32,
+                        frame.height as u32,
+                        NTSC_FPS,
+                        PixelFormat::ARGB8888,
+                    )
+                    .audio(AUDIO_SAMPLE_RATE_DEFAULT)
                     .region(Region::NTSC);
 
                 self.game_data = Some(game_data);
@@ -86,8 +127,10 @@ impl libretro_backend::Core for NesCore {
         macro_rules! update_controllers {
             ( $( $button:ident ),+ ) => (
                 $(
-                    self.cpu.bus.controller_0.set_button_state(Button::$button, handle.is_joypad_button_pressed( 0, JoypadButton::$button ));
-                    self.cpu.bus.controller_1.set_button_state(Button::$button, handle.is_joypad_button_pressed( 1, JoypadButton::$button ));
+                    self.nes.set_button_state(0, Button::$button, handle.is_joypad_button_pressed( 0, JoypadButton::$button ));
+                    self.nes.set_button_state(1, Button::$button, handle.is_joypad_button_pressed( 1, JoypadButton::$button ));
+                    self.nes.set_button_state(2, Button::$button, handle.is_joypad_button_pressed( 2, JoypadButton::$button ));
+                    self.nes.set_button_state(3, Button::$button, handle.is_joypad_button_pressed( 3, JoypadButton::$button ));
                 )+
             )
         }
@@ -100,40 +143,27 @@ impl libretro_backend::Core for NesCore {
             self.frame_second = second;
         }
 
-        while !self.cpu.bus.draw {
-            self.cpu.execute_next_instruction();
-            let stall_cycles = self.cpu.bus.reset_cpu_stall_cycles();
-            for _ in 0..stall_cycles {
-                self.cpu.bus.tick()
-            }
-        }
-
-        self.cpu.bus.draw = false;
+        self.nes.run_frame();
 
-        let mut video_frame = [0u8; 256 * 240 * 4];
+        let frame = self.nes.cpu.bus.ppu.frame();
+        let mut video_frame = vec![0u8; frame.width * frame.height * 4];
 
         for i in 0..video_frame.len() {
-            let pixel = self.cpu.bus.ppu.renderer.pixels[i / 4];
+            let pixel = frame.pixels[i / 4];
             video_frame[i] = (pixel >> (i % 4 * 8)) as u8;
         }
 
         handle.upload_video_frame(&video_frame);
 
-        let audio_buffer_size = self.cpu.bus.apu.buffer.len();
-        if audio_buffer_size < 1470 {
-            for _ in 0..1470 - audio_buffer_size {
-                self.cpu.bus.apu.buffer.push(0);
-            }
-        }
-        handle.upload_audio_frame(&self.cpu.bus.apu.buffer[..]);
-        self.cpu.bus.apu.buffer.clear();
+        self.nes.cpu.bus.apu.finalize_frame();
+        handle.upload_audio_frame(&self.nes.cpu.bus.apu.buffer[..]);
+        self.nes.cpu.bus.apu.buffer.clear();
 
         self.frame_count += 1;
     }
 
     fn on_reset(&mut self) {
-        self.cpu.bus.reset();
-        self.cpu.reset();
+        self.nes.reset();
     }
 }
 
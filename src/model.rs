@@ -0,0 +1,63 @@
+use consts::{NTSC_FPS, PAL_FPS};
+
+// The console being emulated. Models differ in what's wired to the
+// cartridge and controller ports, and in their frame rate.
+//
+// Emulation always uses NTSC CPU and PPU timing, the frame rate is only
+// reported for frontends.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ConsoleModel {
+    #[default]
+    Nes,
+    Famicom,
+    Dendy,
+    VsSystem,
+    PlayChoice10,
+}
+
+impl ConsoleModel {
+    pub fn frames_per_second(self) -> f64 {
+        match self {
+            ConsoleModel::Dendy => PAL_FPS,
+            _ => NTSC_FPS,
+        }
+    }
+
+    // An NES only hears cartridge audio through an expansion port mod, but
+    // it's kept on so Famicom games sound right under the default model. The
+    // arcade boards never had sound chips on the cartridge.
+    pub fn has_expansion_audio(self) -> bool {
+        !matches!(self, ConsoleModel::VsSystem | ConsoleModel::PlayChoice10)
+    }
+
+    // The Famicom's second controller has a microphone, read on 0x4016
+    pub fn has_microphone(self) -> bool {
+        self == ConsoleModel::Famicom
+    }
+
+    pub fn has_dip_switches(self) -> bool {
+        self == ConsoleModel::VsSystem
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_features() {
+        let nes = ConsoleModel::default();
+        assert_eq!(nes, ConsoleModel::Nes);
+        assert!(nes.has_expansion_audio());
+        assert!(!nes.has_microphone());
+        assert!(!nes.has_dip_switches());
+
+        assert!(ConsoleModel::Famicom.has_expansion_audio());
+        assert!(ConsoleModel::Famicom.has_microphone());
+        assert!(ConsoleModel::VsSystem.has_dip_switches());
+        assert!(!ConsoleModel::VsSystem.has_expansion_audio());
+
+        assert_eq!(nes.frames_per_second(), NTSC_FPS);
+        assert_eq!(ConsoleModel::Dendy.frames_per_second(), PAL_FPS);
+    }
+}
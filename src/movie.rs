@@ -0,0 +1,248 @@
+// A recording of the input given to the standard controllers, one entry per
+// frame, which can be exchanged with FCEUX as an .fm2 movie. Movies are
+// assumed to have been recorded from power on.
+
+use std::error::Error;
+use std::fmt;
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// fm2 lists buttons from the most significant bit down
+const FM2_BUTTONS: &[u8] = b"RLDUTSBA";
+
+// Why an .fm2 movie couldn't be read
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MovieError {
+    // The ROM checksum isn't 16 bytes of base64
+    BadChecksum,
+    // An input line, numbered from 1, without both controllers' fields
+    BadInputLine(usize),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::BadChecksum => write!(f, "Movie has an invalid ROM checksum"),
+            MovieError::BadInputLine(n) => write!(f, "Movie has an invalid input line {}", n),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+pub struct Movie {
+    rom_hash: [u8; 16],
+    frames: Vec<[u8; 2]>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: [u8; 16]) -> Self {
+        Movie {
+            rom_hash,
+            frames: Vec::new(),
+        }
+    }
+
+    // Only the checksum and input lines are read, the rest of the header
+    // describes FCEUX's settings
+    pub fn from_fm2(fm2: &str) -> Result<Self, MovieError> {
+        let mut rom_hash = [0; 16];
+        let mut frames = Vec::new();
+        for (i, line) in fm2.lines().enumerate() {
+            if let Some(checksum) = line.strip_prefix("romChecksum base64:") {
+                match unbase64(checksum) {
+                    Some(ref decoded) if decoded.len() == 16 => rom_hash.copy_from_slice(decoded),
+                    _ => return Err(MovieError::BadChecksum),
+                }
+            } else if line.starts_with('|') {
+                let fields: Vec<&str> = line.split('|').collect();
+                if fields.len() < 4 {
+                    return Err(MovieError::BadInputLine(i + 1));
+                }
+                frames.push([parse_fm2_buttons(fields[2]), parse_fm2_buttons(fields[3])]);
+            }
+        }
+        Ok(Movie { rom_hash, frames })
+    }
+
+    pub fn rom_hash(&self) -> [u8; 16] {
+        self.rom_hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut fm2 = String::new();
+        fm2.push_str("version 3\n");
+        fm2.push_str("emuVersion 22020\n");
+        fm2.push_str("rerecordCount 0\n");
+        fm2.push_str("palFlag 0\n");
+        fm2.push_str("romFilename rom\n");
+        fm2.push_str(&format!("romChecksum base64:{}\n", base64(&self.rom_hash)));
+        fm2.push_str("guid 00000000-0000-0000-0000-000000000000\n");
+        fm2.push_str("fourscore 0\n");
+        fm2.push_str("port0 1\n");
+        fm2.push_str("port1 1\n");
+        fm2.push_str("port2 0\n");
+
+        for frame in &self.frames {
+            fm2.push_str(&format!("|0|{}|{}||\n", fm2_buttons(frame[0]), fm2_buttons(frame[1])));
+        }
+        fm2
+    }
+}
+
+// Captures the controller states at the start of each frame
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    pub fn new(rom_hash: [u8; 16]) -> Self {
+        MovieRecorder {
+            movie: Movie::new(rom_hash),
+        }
+    }
+
+    pub fn record_frame(&mut self, port_0: u8, port_1: u8) {
+        self.movie.frames.push([port_0, port_1]);
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// Gives back a movie's controller states a frame at a time
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer { movie, frame: 0 }
+    }
+
+    pub fn next_frame(&mut self) -> Option<[u8; 2]> {
+        let states = self.movie.frames.get(self.frame).cloned();
+        self.frame += 1;
+        states
+    }
+}
+
+fn fm2_buttons(states: u8) -> String {
+    FM2_BUTTONS
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if states & (0x80 >> i) != 0 { b as char } else { '.' })
+        .collect()
+}
+
+// Released buttons are written as '.', though some tools use spaces
+fn parse_fm2_buttons(buttons: &str) -> u8 {
+    buttons
+        .bytes()
+        .take(8)
+        .enumerate()
+        .fold(0, |states, (i, b)| if b == b'.' || b == b' ' { states } else { states | 0x80 >> i })
+}
+
+fn base64(data: &[u8]) -> String {
+    let mut s = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+// None if there's anything other than base64 characters and padding
+fn unbase64(s: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in s.trim().bytes().take_while(|&c| c != b'=') {
+        let v = BASE64.iter().position(|&b| b == c)? as u32;
+        n = n << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((n >> bits) as u8);
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+    }
+
+    #[test]
+    fn test_fm2_buttons() {
+        assert_eq!(fm2_buttons(0x00), "........");
+        assert_eq!(fm2_buttons(0x81), "R......A");
+        assert_eq!(fm2_buttons(0xFF), "RLDUTSBA");
+    }
+
+    #[test]
+    fn test_unbase64() {
+        assert_eq!(unbase64("TWFu"), Some(b"Man".to_vec()));
+        assert_eq!(unbase64("TWE="), Some(b"Ma".to_vec()));
+        assert_eq!(unbase64("TQ=="), Some(b"M".to_vec()));
+        assert_eq!(unbase64("TQ!="), None);
+    }
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let mut recorder = MovieRecorder::new([7; 16]);
+        recorder.record_frame(0x00, 0xFF);
+        recorder.record_frame(0x81, 0x10);
+        let movie = recorder.finish();
+
+        let imported = Movie::from_fm2(&movie.to_fm2()).unwrap();
+        assert_eq!(imported.rom_hash(), [7; 16]);
+        assert_eq!(imported.frames, movie.frames);
+
+        let mut player = MoviePlayer::new(imported);
+        assert_eq!(player.next_frame(), Some([0x00, 0xFF]));
+        assert_eq!(player.next_frame(), Some([0x81, 0x10]));
+        assert_eq!(player.next_frame(), None);
+    }
+
+    #[test]
+    fn test_bad_fm2() {
+        let fm2 = MovieRecorder::new([7; 16]).finish().to_fm2();
+        let short = fm2.replace("romChecksum base64:Bw", "romChecksum base64:");
+        assert_eq!(Movie::from_fm2(&short).err(), Some(MovieError::BadChecksum));
+        let invalid = fm2.replace("romChecksum base64:Bw", "romChecksum base64:B!");
+        assert_eq!(Movie::from_fm2(&invalid).err(), Some(MovieError::BadChecksum));
+
+        let truncated = format!("{}|0|RLDUTSBA\n", fm2);
+        let line = truncated.lines().count();
+        assert_eq!(Movie::from_fm2(&truncated).err(), Some(MovieError::BadInputLine(line)));
+    }
+}
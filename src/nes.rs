@@ -0,0 +1,744 @@
+use apu::Channel;
+use bus::Bus;
+use cartridge::{IrqState, RomError};
+use cpu::Cpu;
+use md5::md5;
+use model::ConsoleModel;
+use controller::{Button, InputDevice, BUTTONS};
+use movie::{Movie, MoviePlayer, MovieRecorder};
+use ram_init::RamInit;
+use rewind::Rewind;
+
+const REWIND_SECONDS: f64 = 10.0;
+const REWIND_INTERVAL: usize = 5;
+
+// The parts of the console that can be reset on their own
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Component {
+    Cpu,
+    Ppu,
+    Apu,
+    Mapper,
+}
+
+type VideoCallback = Box<dyn FnMut(&[u32])>;
+type AudioCallback = Box<dyn FnMut(&[i16])>;
+
+// Nes is the whole console. It owns the CPU, which in turn owns everything
+// else via the bus, and is the entry point for frontends.
+pub struct Nes {
+    pub cpu: Cpu,
+    rom_hash: [u8; 16],
+    recorder: Option<MovieRecorder>,
+    movie: Option<Movie>,
+    player: Option<MoviePlayer>,
+    rewind: Rewind,
+    video_callback: Option<VideoCallback>,
+    audio_callback: Option<AudioCallback>,
+}
+
+impl Nes {
+    pub fn new() -> Self {
+        Nes {
+            cpu: Cpu::new(Bus::new()),
+            rom_hash: [0; 16],
+            recorder: None,
+            movie: None,
+            player: None,
+            rewind: Rewind::new(REWIND_SECONDS, REWIND_INTERVAL),
+            video_callback: None,
+            audio_callback: None,
+        }
+    }
+
+    // A console whose power-on RAM and open bus decay come from the seed
+    // rather than chance, so everything it does follows from the seed, the
+    // ROM and the input it's given. A fuzzer that hits a panic can report
+    // the seed and ROM, and replaying the same input reproduces the crash.
+    pub fn from_fuzz_seed(seed: u64, rom: &[u8]) -> Result<Self, RomError> {
+        let mut nes = Nes::new();
+        nes.set_ram_init(RamInit::Random(seed));
+        nes.cpu.bus.ppu.set_decay_seed(seed);
+        nes.try_load_rom(rom)?;
+        Ok(nes)
+    }
+
+    // Inserts an iNES ROM and powers the console back up
+    pub fn load_rom(&mut self, data: &[u8]) {
+        if let Err(e) = self.try_load_rom(data) {
+            panic!("{}", e);
+        }
+    }
+
+    // Like load_rom, but leaves the console as it was if the ROM is invalid
+    pub fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomError> {
+        self.cpu.bus.load_rom_from_memory(data)?;
+        // Like FCEUX, the hash covers the ROM data but not the iNES header
+        self.rom_hash = md5(&data[16..]);
+        self.power_cycle();
+        Ok(())
+    }
+
+    // Pressing the reset button
+    pub fn reset(&mut self) {
+        self.cpu.bus.reset();
+        self.cpu.reset();
+    }
+
+    // Resets just the APU, as the reset button would, leaving the CPU, PPU
+    // and cartridge alone. Handy for isolating audio glitches.
+    pub fn reset_apu(&mut self) {
+        self.cpu.bus.apu.reset();
+    }
+
+    // Resets one part of the console and leaves the rest alone, to narrow
+    // down which part's reset behaviour a problem comes from
+    pub fn reset_component(&mut self, component: Component) {
+        match component {
+            Component::Cpu => self.cpu.reset(),
+            Component::Ppu => self.cpu.bus.ppu.reset(),
+            Component::Apu => self.reset_apu(),
+            Component::Mapper => {
+                if let Some(ref c) = self.cpu.bus.cartridge {
+                    c.borrow_mut().reset();
+                }
+            }
+        }
+    }
+
+    // Turning the console off and on again
+    pub fn power_cycle(&mut self) {
+        self.cpu.bus.power_on();
+        self.cpu.reset();
+    }
+
+    pub fn set_ram_init(&mut self, ram_init: RamInit) {
+        self.cpu.bus.set_ram_init(ram_init);
+    }
+
+    // Off by default. With a limit, the console counts as hung once the CPU
+    // has gone round the same few registers that many instructions in a row.
+    pub fn set_hang_detection(&mut self, limit: Option<usize>) {
+        self.cpu.set_hang_detection(limit);
+    }
+
+    // True once hang detection trips, or whenever the CPU has jammed on an
+    // undefined opcode
+    pub fn is_hung(&self) -> bool {
+        self.cpu.hung()
+    }
+
+    pub fn run_frame(&mut self) {
+        self.tick_movie();
+        self.cpu.run_frame();
+        self.flush_output();
+    }
+
+    // Called with the pixels of each finished frame, as an alternative to
+    // reading them from the PPU after run_frame
+    pub fn set_video_callback<F: FnMut(&[u32]) + 'static>(&mut self, callback: F) {
+        self.video_callback = Some(Box::new(callback));
+    }
+
+    // Called with each frame's samples, which are then cleared from the APU
+    // buffer. Without a callback they pile up there until the frontend
+    // drains them.
+    pub fn set_audio_callback<F: FnMut(&[i16]) + 'static>(&mut self, callback: F) {
+        self.audio_callback = Some(Box::new(callback));
+    }
+
+    fn flush_output(&mut self) {
+        if let Some(ref mut callback) = self.video_callback {
+            callback(self.cpu.bus.ppu.frame().pixels);
+        }
+
+        if let Some(ref mut callback) = self.audio_callback {
+            let apu = &mut self.cpu.bus.apu;
+            callback(&apu.buffer);
+            apu.buffer.clear();
+        }
+    }
+
+    // A playing movie sets the controllers before the frame is run, and the
+    // recorder captures them, including any set by the movie
+    fn tick_movie(&mut self) {
+        let states = self.player.as_mut().and_then(|p| p.next_frame());
+        match states {
+            Some([port_0, port_1]) => for &button in BUTTONS.iter() {
+                let bit = button as u8;
+                let bus = &mut self.cpu.bus;
+                bus.controller_0.set_button_state(button, port_0 & bit != 0);
+                bus.controller_1.set_button_state(button, port_1 & bit != 0);
+            },
+            None => self.player = None,
+        }
+
+        if let Some(ref mut recorder) = self.recorder {
+            let port_0 = self.cpu.bus.controller_0.button_states();
+            let port_1 = self.cpu.bus.controller_1.button_states();
+            recorder.record_frame(port_0, port_1);
+        }
+    }
+
+    // Records the controller input given to each following frame, replacing
+    // any earlier recording
+    pub fn begin_recording(&mut self) {
+        self.recorder = Some(MovieRecorder::new(self.rom_hash));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<&Movie> {
+        if let Some(recorder) = self.recorder.take() {
+            self.movie = Some(recorder.finish());
+        }
+        self.movie.as_ref()
+    }
+
+    // Exports the movie being recorded, or else the last one recorded. None
+    // if nothing has been recorded yet.
+    pub fn export_movie_fm2(&self) -> Option<String> {
+        if let Some(ref recorder) = self.recorder {
+            return Some(recorder.movie().to_fm2());
+        }
+        self.movie.as_ref().map(|movie| movie.to_fm2())
+    }
+
+    // Plays a movie's input from the next frame on. Movies start from power
+    // on, so this is usually called straight after loading the ROM.
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.player = Some(MoviePlayer::new(movie));
+    }
+
+    pub fn playing_movie(&self) -> bool {
+        self.player.is_some()
+    }
+
+    // Sets how far back rewinding can go, and how many frames apart its
+    // snapshots are. Any snapshots already taken are dropped.
+    pub fn set_rewind(&mut self, seconds: f64, interval: usize) {
+        self.rewind = Rewind::new(seconds, interval);
+    }
+
+    // Called each frame by frontends that support rewinding
+    pub fn rewind_push(&mut self) {
+        self.rewind.push(&mut self.cpu);
+    }
+
+    // Restores the most recent snapshot, returning false once there are none.
+    // The frame buffer and any audio not yet played carry over.
+    pub fn rewind_pop(&mut self) -> bool {
+        match self.rewind.pop() {
+            Some(mut cpu) => {
+                cpu.bus.restore_output(self.cpu.bus.take_output());
+                self.cpu = cpu;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Advances to the start of the game's next frame, as marked by it
+    // entering its NMI handler, rather than to the end of the video frame.
+    pub fn frame_advance(&mut self) {
+        self.cpu.run_until_nmi();
+        self.flush_output();
+    }
+
+    // Flips or swaps the disk in a Famicom Disk System. The disk is ejected
+    // for a moment first so the BIOS sees the change. Sides the disk doesn't
+    // have are ignored.
+    pub fn set_disk_side(&mut self, side: u8) {
+        if let Some(ref c) = self.cpu.bus.cartridge {
+            c.borrow_mut().set_disk_side(side);
+        }
+    }
+
+    // Some boards only have bus conflicts in some copies, so they're left off
+    // unless this turns them on
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        if let Some(ref c) = self.cpu.bus.cartridge {
+            c.borrow_mut().set_bus_conflicts(enabled);
+        }
+    }
+
+    // The cartridge's IRQ counter, for mappers that have one
+    pub fn irq_state(&self) -> Option<IrqState> {
+        match self.cpu.bus.cartridge {
+            Some(ref c) => c.borrow().irq_state(),
+            None => None,
+        }
+    }
+
+    pub fn set_model(&mut self, model: ConsoleModel) {
+        self.cpu.bus.set_model(model);
+    }
+
+    pub fn model(&self) -> ConsoleModel {
+        self.cpu.bus.model()
+    }
+
+    // Controllers are numbered from 0. Controller 1's buttons only count
+    // while it's plugged into the second port, and controllers 2 and 3 are
+    // only read through a Four Score.
+    pub fn set_button_state(&mut self, controller: usize, button: Button, pressed: bool) {
+        let bus = &mut self.cpu.bus;
+        match controller {
+            0 => bus.controller_0.set_button_state(button, pressed),
+            1 => bus.controller_1.set_button_state(button, pressed),
+            2 => bus.controller_2.set_button_state(button, pressed),
+            3 => bus.controller_3.set_button_state(button, pressed),
+            _ => (),
+        }
+    }
+
+    // The second port can hold a Zapper or paddle instead of a controller
+    pub fn set_input_device(&mut self, device: InputDevice) {
+        self.cpu.bus.controller_1 = device;
+    }
+
+    // For updating a Zapper or paddle each frame
+    pub fn input_device_mut(&mut self) -> &mut InputDevice {
+        &mut self.cpu.bus.controller_1
+    }
+
+    pub fn set_four_score(&mut self, enabled: bool) {
+        self.cpu.bus.set_four_score(enabled);
+    }
+
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        self.cpu.bus.apu.set_channel_enabled(channel, enabled);
+    }
+
+    pub fn set_palette(&mut self, palette: [u32; 64]) {
+        self.cpu.bus.ppu.set_palette(palette);
+    }
+
+    pub fn set_crop_overscan(&mut self, crop: bool) {
+        self.cpu.bus.ppu.set_crop_overscan(crop);
+    }
+
+    // Hands the PPU a 256 * 240 buffer to draw the next frames into, and
+    // takes back the one it was using. Swapping again after a frame gets
+    // that frame without copying it.
+    pub fn swap_frame_buffer(&mut self, pixels: &mut Vec<u32>) {
+        self.cpu.bus.ppu.swap_frame_buffer(pixels);
+    }
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zapper::Zapper;
+    use rand::{Rng, SeedableRng, StdRng};
+    use std::cell::{Cell, RefCell};
+    use std::panic;
+    use std::rc::Rc;
+
+    const NMI_HANDLER: u16 = 0x8010;
+
+    fn build_nes() -> Nes {
+        // RTI
+        build_nes_with_nmi_handler(&[0x40])
+    }
+
+    fn build_nes_with_nmi_handler(handler: &[u8]) -> Nes {
+        let mut nes = Nes::new();
+        nes.load_rom(&build_rom_with_nmi_handler(handler));
+        nes
+    }
+
+    fn build_rom_with_nmi_handler(handler: &[u8]) -> Vec<u8> {
+        let mut rom = vec![
+            0x4e, 0x45, 0x53, 0x1a, // NES<EOF>
+            0x02, // Two pages of PRG-ROM
+            0x00, // Zero pages CHR-ROM means use CHR-RAM
+            0x01, // Vertical mirroring
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut prg = vec![0u8; 2 * 0x4000];
+        // LDA #$80; STA $2000 to enable NMIs, then JMP to itself forever
+        prg[..8].copy_from_slice(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80]);
+        let start = (NMI_HANDLER - 0x8000) as usize;
+        prg[start..start + handler.len()].copy_from_slice(handler);
+        // NMI, reset and IRQ vectors
+        prg[0x7FFA..].copy_from_slice(&[0x10, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        rom.extend_from_slice(&prg);
+        rom
+    }
+
+    #[test]
+    fn test_frame_advance() {
+        let mut nes = build_nes();
+        nes.frame_advance();
+        assert_eq!(nes.cpu.pc(), NMI_HANDLER);
+        assert_eq!(nes.cpu.bus.ppu.renderer.scanline, 241);
+        assert!(!nes.cpu.bus.draw);
+
+        // The next advance lands on the following frame's NMI
+        nes.frame_advance();
+        assert_eq!(nes.cpu.pc(), NMI_HANDLER);
+        assert_eq!(nes.cpu.bus.ppu.renderer.scanline, 241);
+    }
+
+    #[test]
+    fn test_frames_without_rendering() {
+        let mut nes = build_nes();
+        assert_eq!(nes.cpu.pc(), 0x8000);
+        nes.cpu.execute_next_instruction();
+        nes.cpu.execute_next_instruction();
+        assert_eq!(nes.cpu.pc(), 0x8005);
+        nes.cpu.bus.ppu.registers.vram.palettes[0] = 0x21;
+
+        // Rendering is never enabled, but frames are still drawn, one per
+        // frame's worth of cycles
+        nes.run_frame();
+        let mut draws = 0;
+        let end = nes.cpu.bus.cycles + 3 * 29781 + 1000;
+        while nes.cpu.bus.cycles < end {
+            nes.cpu.execute_next_instruction();
+            if nes.cpu.bus.draw {
+                nes.cpu.bus.draw = false;
+                draws += 1;
+            }
+        }
+        assert_eq!(draws, 3);
+
+        let backdrop = nes.cpu.bus.ppu.palette_rgb()[0];
+        assert!(nes.cpu.bus.ppu.renderer.pixels.iter().all(|&p| p == backdrop));
+    }
+
+    #[test]
+    fn test_export_movie_fm2() {
+        let mut nes = build_nes();
+        assert_eq!(nes.export_movie_fm2(), None);
+        nes.begin_recording();
+        nes.run_frame();
+        nes.cpu.bus.controller_0.set_button_state(Button::A, true);
+        nes.cpu.bus.controller_0.set_button_state(Button::Right, true);
+        nes.run_frame();
+        nes.stop_recording();
+        nes.run_frame();
+
+        let fm2 = nes.export_movie_fm2().unwrap();
+        let lines: Vec<&str> = fm2.lines().collect();
+        assert_eq!(lines[0], "version 3");
+        assert!(lines.contains(&"port0 1"));
+        let checksum = lines.iter().find(|l| l.starts_with("romChecksum ")).unwrap();
+        assert!(checksum.starts_with("romChecksum base64:"));
+        assert_eq!(checksum.len(), "romChecksum base64:".len() + 24);
+
+        let inputs: Vec<&&str> = lines.iter().filter(|l| l.starts_with('|')).collect();
+        assert_eq!(inputs, [&"|0|........|........||", &"|0|R......A|........||"]);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut nes = build_nes();
+        nes.set_ram_init(RamInit::Ones);
+        nes.run_frame();
+        nes.cpu.bus.write_byte(0x0010u16, 0x12);
+        nes.cpu.bus.write_byte(0x6000u16, 0x34);
+        nes.cpu.bus.write_byte(0x2006u16, 0x23);
+        nes.cpu.bus.write_byte(0x2006u16, 0x45);
+        nes.cpu.bus.write_byte(0x2000u16, 0x01);
+        assert_eq!(nes.cpu.bus.ppu.registers.t_address.address(), 0x2745);
+        assert!(nes.cpu.bus.apu.frame_counter_cycles() > 1000);
+
+        nes.reset();
+        assert_eq!(nes.cpu.pc(), 0x8000);
+        // Restarted 3 cycles into the APU's 11 cycle reset, then run through
+        // the CPU's 7 cycle reset sequence
+        assert_eq!(nes.cpu.bus.apu.frame_counter_cycles(), 11 - 2 + 7);
+        assert_eq!(nes.cpu.bus.ppu.registers.control.0, 0);
+        assert_eq!(nes.cpu.bus.ppu.registers.v_address.address(), 0x2345);
+        assert_eq!(nes.cpu.bus.read_byte(0x0010u16), 0x12);
+        assert_eq!(nes.cpu.bus.read_byte(0x6000u16), 0x34);
+
+        nes.power_cycle();
+        assert_eq!(nes.cpu.bus.ppu.registers.v_address.address(), 0);
+        assert_eq!(nes.cpu.bus.ppu.registers.t_address.address(), 0);
+        assert_eq!(nes.cpu.bus.read_byte(0x0010u16), 0xFF);
+        assert_eq!(nes.cpu.bus.read_byte(0x6000u16), 0xFF);
+    }
+
+    #[test]
+    fn test_reset_apu() {
+        let mut nes = build_nes();
+        // Pulse 1 at full constant volume, with a long length counter
+        nes.cpu.bus.write_byte(0x4015u16, 0x01);
+        nes.cpu.bus.write_byte(0x4000u16, 0xBF);
+        nes.cpu.bus.write_byte(0x4002u16, 0x80);
+        nes.cpu.bus.write_byte(0x4003u16, 0x08);
+        nes.cpu.bus.write_byte(0x0010u16, 0x12);
+        nes.run_frame();
+        assert_eq!(nes.cpu.bus.apu.read_register() & 0x1F, 0x01);
+
+        let pc = nes.cpu.pc();
+        let cycles = nes.cpu.bus.cycles;
+        nes.reset_apu();
+        assert_eq!(nes.cpu.bus.apu.read_register() & 0x1F, 0);
+        assert!(nes.cpu.bus.apu.frame_counter_cycles() <= 11);
+        assert_eq!(nes.cpu.pc(), pc);
+        assert_eq!(nes.cpu.bus.cycles, cycles);
+        assert_eq!(nes.cpu.bus.read_byte(0x0010u16), 0x12);
+    }
+
+    #[test]
+    fn test_reset_ppu_component() {
+        let mut nes = build_nes();
+        nes.run_frame();
+        nes.cpu.bus.write_byte(0x0010u16, 0x12);
+        nes.cpu.bus.write_byte(0x2000u16, 0x80);
+        nes.cpu.bus.write_byte(0x2001u16, 0x1E);
+        let state = nes.cpu.state();
+        let cycles = nes.cpu.bus.cycles;
+
+        nes.reset_component(Component::Ppu);
+        assert_eq!(nes.cpu.bus.ppu.registers.control.0, 0);
+        assert_eq!(nes.cpu.bus.ppu.registers.mask.0, 0);
+        assert_eq!(nes.cpu.bus.ppu.position(), (0, 0));
+        assert_eq!(nes.cpu.state(), state);
+        assert_eq!(nes.cpu.bus.cycles, cycles);
+        assert_eq!(nes.cpu.bus.read_byte(0x0010u16), 0x12);
+    }
+
+    #[test]
+    fn test_reset_mapper_component() {
+        let mut rom = build_rom_with_nmi_handler(&[0x40]);
+        rom[6] = 0x20; // UxROM, with the same banks as NROM at power on
+        let mut nes = Nes::new();
+        nes.load_rom(&rom);
+
+        // Switch the empty second bank in at $8000
+        nes.cpu.bus.write_byte(0x8000u16, 0x01);
+        assert_eq!(nes.cpu.bus.read_byte(0x8000u16), 0x00);
+        let state = nes.cpu.state();
+
+        nes.reset_component(Component::Mapper);
+        assert_eq!(nes.cpu.bus.read_byte(0x8000u16), 0xA9);
+        assert_eq!(nes.cpu.state(), state);
+    }
+
+    #[test]
+    fn test_swap_frame_buffer() {
+        let mut nes = build_nes();
+        // Start from the top of a frame
+        nes.run_frame();
+        nes.set_palette([0x123456; 64]);
+        let mut buffer = vec![0; 256 * 240];
+        let address = buffer.as_ptr();
+
+        nes.swap_frame_buffer(&mut buffer);
+        nes.run_frame();
+        nes.swap_frame_buffer(&mut buffer);
+
+        // The frame was drawn into the same allocation
+        assert_eq!(buffer.as_ptr(), address);
+        assert!(buffer.iter().all(|&p| p & 0xFF_FFFF == 0x123456));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_swap_frame_buffer_wrong_size() {
+        let mut nes = build_nes();
+        nes.swap_frame_buffer(&mut vec![0; 256 * 224]);
+    }
+
+    #[test]
+    fn test_movie_playback() {
+        // Counts the frames A is held for in $10
+        let handler = [
+            0xA9, 0x01, // LDA #$01
+            0x8D, 0x16, 0x40, // STA $4016
+            0x4A, // LSR A
+            0x8D, 0x16, 0x40, // STA $4016
+            0xAD, 0x16, 0x40, // LDA $4016
+            0x29, 0x01, // AND #$01
+            0x18, // CLC
+            0x65, 0x10, // ADC $10
+            0x85, 0x10, // STA $10
+            0x40, // RTI
+        ];
+        let held = [true, false, true, true, false, true];
+
+        let mut nes = build_nes_with_nmi_handler(&handler);
+        nes.begin_recording();
+        let mut recorded = vec![];
+        for &a in held.iter() {
+            nes.cpu.bus.controller_0.set_button_state(Button::A, a);
+            nes.run_frame();
+            recorded.push((nes.cpu.bus.ram[0x10], nes.cpu.bus.ppu.renderer.pixels.clone()));
+        }
+        let fm2 = nes.export_movie_fm2().unwrap();
+        assert_eq!(nes.stop_recording().unwrap().len(), held.len());
+        assert!(nes.cpu.bus.ram[0x10] > 0);
+
+        let mut nes = build_nes_with_nmi_handler(&handler);
+        nes.play_movie(Movie::from_fm2(&fm2).unwrap());
+        for r in recorded.iter() {
+            nes.run_frame();
+            assert_eq!((nes.cpu.bus.ram[0x10], nes.cpu.bus.ppu.renderer.pixels.clone()), *r);
+        }
+        assert!(nes.playing_movie());
+        nes.run_frame();
+        assert!(!nes.playing_movie());
+    }
+
+    #[test]
+    fn test_input_setters() {
+        let mut nes = build_nes();
+        nes.set_button_state(0, Button::Start, true);
+        nes.set_button_state(3, Button::A, true);
+        nes.set_button_state(4, Button::A, true);
+        assert_eq!(nes.cpu.bus.controller_0.button_states(), Button::Start as u8);
+        assert_eq!(nes.cpu.bus.controller_3.button_states(), Button::A as u8);
+
+        nes.set_input_device(InputDevice::Zapper(Zapper::new()));
+        nes.set_button_state(1, Button::A, true);
+        if let InputDevice::Zapper(ref mut z) = *nes.input_device_mut() {
+            z.set_trigger(true);
+        }
+        assert_eq!(nes.cpu.bus.unclocked_read_byte(0x4017) & 0b0001_0000, 0b0001_0000);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut nes = build_nes();
+        nes.set_rewind(1.0, 1);
+        let mut states = vec![];
+        for i in 0..3 {
+            nes.cpu.bus.write_byte(0x6000u16, i);
+            nes.rewind_push();
+            let bus = &nes.cpu.bus;
+            states.push((nes.cpu.pc(), bus.cycles, bus.ppu.renderer.scanline, bus.ram.to_vec()));
+            nes.run_frame();
+        }
+
+        nes.cpu.bus.write_byte(0x0000u16, 0x99);
+        nes.cpu.bus.write_byte(0x6000u16, 0x99);
+        for i in (0..3).rev() {
+            assert!(nes.rewind_pop());
+            let bus = &nes.cpu.bus;
+            let state = (nes.cpu.pc(), bus.cycles, bus.ppu.renderer.scanline, bus.ram.to_vec());
+            assert_eq!(state, states[i as usize]);
+            // PRG-RAM is restored too, as the snapshot has its own cartridge
+            assert_eq!(nes.cpu.bus.unclocked_read_byte(0x6000), i);
+        }
+        assert!(!nes.rewind_pop());
+    }
+
+    #[test]
+    fn test_rewind_keeps_output() {
+        let mut nes = build_nes();
+        nes.set_rewind(1.0, 1);
+        nes.run_frame();
+        nes.rewind_push();
+        nes.run_frame();
+        nes.cpu.bus.apu.buffer.clear();
+        nes.cpu.bus.apu.buffer.push(123);
+        let pixels = nes.cpu.bus.ppu.renderer.pixels.clone();
+
+        // The snapshot's older audio and frame aren't brought back
+        assert!(nes.rewind_pop());
+        assert_eq!(nes.cpu.bus.apu.buffer, vec![123]);
+        assert_eq!(nes.cpu.bus.ppu.renderer.pixels, pixels);
+        nes.run_frame();
+        assert_eq!(nes.cpu.bus.ppu.renderer.pixels.len(), 256 * 240);
+    }
+
+    // Reads the controller, then mixes it into random RAM and feeds the
+    // result to the PPU and APU registers
+    #[rustfmt::skip]
+    const FUZZ_HANDLER: [u8; 30] = [
+        0xA9, 0x01, 0x8D, 0x16, 0x40, 0x4A, 0x8D, 0x16, 0x40, // Strobe $4016
+        0xAD, 0x16, 0x40, 0x45, 0x00, 0x85, 0x00,             // LDA $4016; EOR $00; STA $00
+        0x8D, 0x01, 0x20, 0x8D, 0x05, 0x20,                   // STA $2001; STA $2005
+        0xA6, 0x01, 0x8E, 0x15, 0x40, 0xE6, 0x01,             // LDX $01; STX $4015; INC $01
+        0x40,                                                 // RTI
+    ];
+
+    // Runs frames of random input, generated from the same seed as the
+    // console. A panic here is reproduced by calling this with its seed.
+    fn run_fuzz_seed(seed: u64, frames: usize) -> (Vec<u8>, Vec<u32>) {
+        let rom = build_rom_with_nmi_handler(&FUZZ_HANDLER);
+        let mut nes = Nes::from_fuzz_seed(seed, &rom).unwrap();
+        let input_seed: &[_] = &[seed as usize];
+        let mut rng: StdRng = SeedableRng::from_seed(input_seed);
+        for _ in 0..frames {
+            for &button in BUTTONS.iter() {
+                nes.cpu.bus.controller_0.set_button_state(button, rng.gen());
+            }
+            nes.run_frame();
+        }
+        (nes.cpu.bus.ram.to_vec(), nes.cpu.bus.ppu.renderer.pixels.clone())
+    }
+
+    #[test]
+    fn test_fuzz_seeds() {
+        for seed in 0..4 {
+            let result = panic::catch_unwind(|| run_fuzz_seed(seed, 10));
+            assert!(result.is_ok(), "panicked with seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_from_fuzz_seed() {
+        // The same seed always gives the same run
+        assert!(run_fuzz_seed(3, 5) == run_fuzz_seed(3, 5));
+        assert!(run_fuzz_seed(3, 5) != run_fuzz_seed(4, 5));
+
+        assert_eq!(
+            Nes::from_fuzz_seed(3, &[0; 8]).err(),
+            Some(RomError::TooShort)
+        );
+    }
+
+    #[test]
+    fn test_hang_detection() {
+        // LDA #$00 rather than #$80, so no NMI ever breaks the JMP loop
+        let mut rom = build_rom_with_nmi_handler(&[0x40]);
+        rom[16 + 1] = 0x00;
+        let mut nes = Nes::new();
+        nes.load_rom(&rom);
+        nes.run_frame();
+        assert!(!nes.is_hung());
+
+        nes.set_hang_detection(Some(1000));
+        nes.run_frame();
+        assert!(nes.is_hung());
+    }
+
+    #[test]
+    fn test_hang_detection_with_nmi() {
+        // The same loop waiting for NMIs is just a game idling
+        let mut nes = build_nes();
+        nes.set_hang_detection(Some(20_000));
+        for _ in 0..5 {
+            nes.run_frame();
+        }
+        assert!(!nes.is_hung());
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let samples = Rc::new(Cell::new(0));
+
+        let mut nes = build_nes();
+        let f = frames.clone();
+        nes.set_video_callback(move |pixels| f.borrow_mut().push(pixels.len()));
+        let s = samples.clone();
+        nes.set_audio_callback(move |buffer| s.set(s.get() + buffer.len()));
+
+        nes.run_frame();
+        nes.run_frame();
+        assert_eq!(*frames.borrow(), vec![256 * 240, 256 * 240]);
+        assert!(samples.get() > 0);
+        assert!(nes.cpu.bus.apu.buffer.is_empty());
+    }
+}
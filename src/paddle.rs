@@ -0,0 +1,84 @@
+// Paddle implements the Arkanoid "Vaus" controller
+// http://wiki.nesdev.com/w/index.php/Arkanoid_controller
+//
+// The potentiometer is latched by the strobe and shifted out MSB first,
+// inverted, on bit 4. The fire button is on bit 3.
+#[derive(Clone)]
+pub struct Paddle {
+    position: u8,
+    fire: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Paddle {
+    pub fn new() -> Self {
+        Paddle {
+            position: 0,
+            fire: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    pub fn write_register(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    pub fn read_register(&mut self) -> u8 {
+        let mut v = 0;
+        if self.shift & 0x80 == 0 {
+            v |= 0b0001_0000;
+        }
+        if self.fire {
+            v |= 0b0000_1000;
+        }
+
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+
+        v
+    }
+
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position;
+    }
+
+    pub fn set_fire(&mut self, pressed: bool) {
+        self.fire = pressed;
+    }
+}
+
+impl Default for Paddle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_register() {
+        let mut paddle = Paddle::new();
+        paddle.set_position(0b1011_0010);
+        paddle.set_fire(true);
+        paddle.write_register(1);
+        paddle.write_register(0);
+
+        // Moving the paddle after the strobe doesn't change what's sent
+        paddle.set_position(0);
+
+        let bits: Vec<u8> = (0..8).map(|_| paddle.read_register() >> 4 & 1).collect();
+        assert_eq!(bits, [0, 1, 0, 0, 1, 1, 0, 1]);
+        assert_eq!(paddle.read_register() & 0b0001_1000, 0b0001_1000);
+
+        paddle.set_fire(false);
+        assert_eq!(paddle.read_register() & 0b0000_1000, 0);
+    }
+}
@@ -1,5 +1,9 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 bitfield!{
     #[derive(Copy, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
     pub struct Address(u16);
     impl Debug;
     pub u8,  coarse_x,   set_coarse_x:    4,  0;
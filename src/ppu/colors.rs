@@ -1,3 +1,6 @@
+use std::error::Error;
+use std::fmt;
+
 #[cfg_attr(rustfmt, rustfmt_skip)]
 pub const RGB: [u32; 64] =
   [ 
@@ -10,3 +13,119 @@ pub const RGB: [u32; 64] =
 		0xFFFEFF, 0xC0DFFF, 0xD3D2FF, 0xE8C8FF, 0xFBC2FF, 0xFEC4EA, 0xFECCC5, 0xF7D8A5,
 		0xE4E594, 0xCFEF96, 0xBDF4AB, 0xB3F3CC, 0xB5EBF2, 0xB8B8B8, 0x000000, 0x000000
 	];
+
+// Each emphasis bit darkens the two other color channels. The factor is the
+// commonly measured NTSC attenuation from
+// https://wiki.nesdev.com/w/index.php/NTSC_video
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+// A .pal file is 64 RGB triples. Some also have the seven emphasised
+// versions of those after them, which are ignored as emphasis is applied
+// the same way to any palette.
+const PAL_FILE_SIZE: usize = 64 * 3;
+const PAL_FILE_WITH_EMPHASIS_SIZE: usize = 8 * PAL_FILE_SIZE;
+
+// Why a .pal file couldn't be loaded
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PaletteError {
+    WrongSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaletteError::WrongSize(n) => write!(
+                f,
+                "Palette should be {} or {} bytes, not {}",
+                PAL_FILE_SIZE, PAL_FILE_WITH_EMPHASIS_SIZE, n
+            ),
+        }
+    }
+}
+
+impl Error for PaletteError {}
+
+// Alternatives to the built in RGB colors, for Ppu::set_palette
+pub struct Palette;
+
+impl Palette {
+    pub fn from_pal_bytes(data: &[u8]) -> Result<[u32; 64], PaletteError> {
+        if data.len() != PAL_FILE_SIZE && data.len() != PAL_FILE_WITH_EMPHASIS_SIZE {
+            return Err(PaletteError::WrongSize(data.len()));
+        }
+
+        let mut colors = [0; 64];
+        for (color, rgb) in colors.iter_mut().zip(data.chunks(3)) {
+            *color = (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+        }
+        Ok(colors)
+    }
+}
+
+// Colors indexed first by the three emphasis bits of PPUMASK (red, green and
+// blue from the lowest), then by palette entry.
+pub type EmphasisTable = [[(u8, u8, u8); 64]; 8];
+
+pub fn build_emphasis_table(rgb: &[u32; 64]) -> EmphasisTable {
+    let mut table = [[(0, 0, 0); 64]; 8];
+    for (emphasis, colors) in table.iter_mut().enumerate() {
+        let attenuate = |channel: u32, bit: usize| {
+            let value = channel as f32;
+            let others = 0b111 & !(1 << bit);
+            if emphasis & others != 0 {
+                (value * EMPHASIS_ATTENUATION).round() as u8
+            } else {
+                value as u8
+            }
+        };
+        for (color, &rgb) in colors.iter_mut().zip(rgb.iter()) {
+            *color = (
+                attenuate(rgb >> 16 & 0xFF, 0),
+                attenuate(rgb >> 8 & 0xFF, 1),
+                attenuate(rgb & 0xFF, 2),
+            );
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Only checks the table against EMPHASIS_ATTENUATION. It isn't compared
+    // with a published 512 color palette, so how close the simple per
+    // channel model comes to real hardware is untested.
+    #[test]
+    fn test_build_emphasis_table() {
+        let palette = build_emphasis_table(&RGB);
+        for (i, &rgb) in RGB.iter().enumerate() {
+            let (r, g, b) = palette[0][i];
+            assert_eq!((r as u32) << 16 | (g as u32) << 8 | b as u32, rgb);
+        }
+
+        // 0x30 is 0xFFFEFF, and red emphasis leaves only red at full level
+        assert_eq!(palette[0b001][0x30], (0xFF, 0xBD, 0xBE));
+        assert_eq!(palette[0b100][0x30], (0xBE, 0xBD, 0xFF));
+
+        // With every bit set, all three channels are darkened once
+        assert_eq!(palette[0b111][0x30], (0xBE, 0xBD, 0xBE));
+        assert_eq!(palette[0b111][0x0F], (0, 0, 0));
+    }
+
+    #[test]
+    fn test_from_pal_bytes() {
+        let mut data: Vec<u8> = (0..192).map(|i| i as u8).collect();
+        let colors = Palette::from_pal_bytes(&data).unwrap();
+        assert_eq!(colors[0], 0x000102);
+        assert_eq!(colors[63], 0xBDBEBF);
+
+        data.extend_from_slice(&[0xFF; 7 * 192]);
+        assert_eq!(Palette::from_pal_bytes(&data), Ok(colors));
+
+        assert_eq!(
+            Palette::from_pal_bytes(&data[..191]),
+            Err(PaletteError::WrongSize(191))
+        );
+    }
+}
@@ -1,5 +1,9 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 bitfield!{
     #[derive(Copy, Clone)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
     pub struct Control(u8);
     impl Debug;
     pub nametable,          _: 1, 0;
@@ -63,4 +67,17 @@ mod test {
         assert_eq!(Control(0b0010_0000).sprite_height(), 16);
         assert_eq!(Control(0b0000_0000).sprite_height(), 8);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let control = Control(0b1001_0110);
+        let json = serde_json::to_string(&control).unwrap();
+        assert_eq!(json, "150");
+
+        let restored: Control = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.0, control.0);
+        assert!(restored.nmi_on_vblank());
+        assert_eq!(restored.nametable(), 0b10);
+    }
 }
@@ -0,0 +1,60 @@
+#[cfg(feature = "png")]
+use png;
+#[cfg(feature = "png")]
+use std::io::{self, Write};
+
+// Frame is a read-only view of the most recently rendered picture.
+//
+// Pixels are stored row by row, starting at the top left corner. Each pixel
+// is a u32 in ARGB8888 format (0xAARRGGBB). The alpha byte is always zero.
+#[derive(Debug, Copy, Clone)]
+pub struct Frame<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [u32],
+}
+
+impl<'a> Frame<'a> {
+    // Encodes the frame as an RGBA PNG, with every pixel opaque
+    #[cfg(feature = "png")]
+    pub fn write_png<W: Write>(&self, w: W) -> io::Result<()> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        for &p in self.pixels {
+            data.extend_from_slice(&[(p >> 16) as u8, (p >> 8) as u8, p as u8, 0xFF]);
+        }
+
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "png"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_png() {
+        let mut pixels = vec![0; 256 * 240];
+        pixels[1] = 0x0012_3456;
+        let frame = Frame {
+            width: 256,
+            height: 240,
+            pixels: &pixels,
+        };
+
+        let mut buffer = Vec::new();
+        frame.write_png(&mut buffer).unwrap();
+        assert_eq!(buffer[..8], [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+
+        let mut reader = png::Decoder::new(&buffer[..]).read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+        assert_eq!((info.width, info.height), (256, 240));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(image[4..8], [0x12, 0x34, 0x56, 0xFF]);
+    }
+}
@@ -1,5 +1,9 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 bitfield!{
     #[derive(Copy, Clone)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
     pub struct Mask(u8);
     impl Debug;
     pub greyscale,              _: 0;
@@ -24,6 +28,11 @@ impl Mask {
     pub fn rendering_sprites(&self, x: usize) -> bool {
         self.show_sprites() && (self.show_sprites_left_8() || x >= 8)
     }
+
+    // The emphasis bits together, as an index into the emphasis palettes
+    pub fn emphasis(&self) -> usize {
+        (self.0 >> 5) as usize
+    }
 }
 
 #[cfg(test)]
@@ -59,4 +68,11 @@ mod test {
         assert_eq!(Mask(0b0000_0010).rendering_background(7), false);
         assert_eq!(Mask(0b0000_0010).rendering_background(8), false);
     }
+
+    #[test]
+    fn test_emphasis() {
+        assert_eq!(Mask(0b0001_1110).emphasis(), 0);
+        assert_eq!(Mask(0b0010_0000).emphasis(), 0b001);
+        assert_eq!(Mask(0b1100_0001).emphasis(), 0b110);
+    }
 }
@@ -1,6 +1,7 @@
 mod address;
 mod control;
 mod colors;
+mod frame;
 mod mask;
 mod sprite;
 mod status;
@@ -8,24 +9,37 @@ pub mod vram;
 mod renderer;
 mod registers;
 pub mod result;
+mod viewer;
+
+pub use self::colors::{Palette, PaletteError};
+pub use self::frame::Frame;
+pub use self::sprite::Sprite;
+pub use self::viewer::PATTERN_TABLE_SIZE;
 
 use self::registers::Registers;
 use self::renderer::Renderer;
 use self::result::PpuResult;
 
+use consts::{OVERSCAN_ROWS, SCREEN_HEIGHT, SCREEN_WIDTH};
+#[cfg(feature = "png")]
+use std::io::{self, Write};
+
+#[derive(Clone)]
 pub struct Ppu {
     pub registers: Registers,
     pub renderer: Renderer,
+    crop_overscan: bool,
+    nmi_cancelled: bool,
 }
 
 impl Ppu {
     pub fn new() -> Self {
-        let mut p = Ppu {
+        Ppu {
             registers: Registers::new(),
             renderer: Renderer::new(),
-        };
-        p.reset();
-        p
+            crop_overscan: false,
+            nmi_cancelled: false,
+        }
     }
 
     pub fn tick(&mut self) -> PpuResult {
@@ -39,20 +53,304 @@ impl Ppu {
         self.registers.tick_decay();
     }
 
+    // Replaces the built in colors, for palettes loaded with
+    // Palette::from_pal_bytes
+    pub fn set_palette(&mut self, palette: [u32; 64]) {
+        self.renderer.set_palette(palette);
+    }
+
+    pub fn set_decay_seed(&mut self, seed: u64) {
+        self.registers.set_decay_seed(seed);
+    }
+
+    pub fn power_on(&mut self) {
+        self.registers.power_on();
+        self.renderer.reset();
+    }
+
     pub fn reset(&mut self) {
         self.registers.reset();
         self.renderer.reset();
     }
 
+    // With overscan cropped the frame is 256x224, as the top and bottom
+    // rows are left out. Rendering is the same either way.
+    pub fn frame(&self) -> Frame<'_> {
+        let rows = if self.crop_overscan { OVERSCAN_ROWS } else { 0 };
+        let start = rows * SCREEN_WIDTH;
+        let end = (SCREEN_HEIGHT - rows) * SCREEN_WIDTH;
+        Frame {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT - 2 * rows,
+            pixels: &self.renderer.pixels[start..end],
+        }
+    }
+
+    pub fn set_crop_overscan(&mut self, crop: bool) {
+        self.crop_overscan = crop;
+    }
+
+    // Saves the current frame as a PNG, for screenshots and comparing
+    // against reference images
+    #[cfg(feature = "png")]
+    pub fn write_png<W: Write>(&self, w: W) -> io::Result<()> {
+        self.frame().write_png(w)
+    }
+
+    // The (scanline, dot) the PPU will run next. Scanlines 0-239 are
+    // visible, 241-260 are vblank and 261 is the pre-render line.
+    pub fn position(&self) -> (usize, usize) {
+        (self.renderer.scanline, self.renderer.dot)
+    }
+
+    // For frontends that want frames drawn into their own buffers rather
+    // than copied out of the PPU. The buffer must hold 256 * 240 pixels.
+    pub fn swap_frame_buffer(&mut self, pixels: &mut Vec<u32>) {
+        self.renderer.swap_pixels(pixels);
+    }
+
+    // The raw internal scroll state as (t, v, fine x, write latch), for
+    // debugging split scrolling and raster effects.
+    pub fn loopy_registers(&self) -> (u16, u16, u8, bool) {
+        (
+            self.registers.t_address.get(),
+            self.registers.v_address.get(),
+            self.registers.fine_x,
+            self.registers.latch(),
+        )
+    }
+
+    // The scanline and dot where sprite 0 hit was set during the current
+    // frame, if it has been. Cleared on the pre-render line.
+    pub fn last_sprite_zero_hit(&self) -> Option<(usize, usize)> {
+        self.renderer.sprite_zero_hit_at
+    }
+
+    // Renders nametable 0 to 3 at screen size, for debugging tools.
+    pub fn render_nametable(&self, index: u8) -> Vec<u32> {
+        viewer::render_nametable(&self.registers, self.renderer.colors(), index)
+    }
+
+    // Renders all four nametables as a 2x2 grid at twice the screen size.
+    pub fn render_nametables(&self) -> Vec<u32> {
+        viewer::render_nametables(&self.registers, self.renderer.colors())
+    }
+
+    // Renders pattern table 0 or 1 as a PATTERN_TABLE_SIZE square using
+    // one of the eight palettes.
+    pub fn render_pattern_table(&self, table: u8, palette: u8) -> Vec<u32> {
+        viewer::render_pattern_table(&self.registers, self.renderer.colors(), table, palette)
+    }
+
+    // All 64 sprites decoded from OAM, for debugging tools.
+    pub fn oam_sprites(&self) -> Vec<Sprite> {
+        viewer::oam_sprites(&self.registers)
+    }
+
+    // The 32 palette entries as RGB, backgrounds first then sprites.
+    pub fn palette_rgb(&self) -> [u32; 32] {
+        viewer::palette_rgb(&self.registers, self.renderer.colors())
+    }
+
     pub fn write_register(&mut self, address: u16, value: u8) {
         self.registers.write_register(address, value);
     }
 
+    // Reading $2002 the dot before vblank starts keeps the flag and NMI
+    // from happening at all, which Registers handles. Reading it on the dot
+    // vblank starts or the one after sees the flag set, but the NMI that
+    // was just sent is cancelled.
     pub fn read_register(&mut self, address: u16) -> u8 {
+        if address % 8 == 2 && self.renderer.scanline == 241 {
+            if let 2...3 = self.renderer.dot {
+                self.nmi_cancelled = true;
+            }
+        }
         self.registers.read_register(address)
     }
+
+    // Whether a read since the last call cancelled the vblank NMI
+    pub fn take_nmi_cancelled(&mut self) -> bool {
+        let cancelled = self.nmi_cancelled;
+        self.nmi_cancelled = false;
+        cancelled
+    }
 }
 
 fn nth_bit<T: Into<u16>, U: Into<u16>>(x: T, n: U) -> u8 {
     ((x.into() >> n.into()) & 1) as u8
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        let mut ppu = Ppu::new();
+        ppu.renderer.pixels[SCREEN_WIDTH + 2] = 0x00FF_0000;
+        let frame = ppu.frame();
+        assert_eq!(frame.width, 256);
+        assert_eq!(frame.height, 240);
+        assert_eq!(frame.pixels.len(), frame.width * frame.height);
+        assert_eq!(frame.pixels[frame.width + 2], 0x00FF_0000);
+    }
+
+    #[test]
+    fn test_crop_overscan() {
+        let mut ppu = Ppu::new();
+        for (i, p) in ppu.renderer.pixels.iter_mut().enumerate() {
+            *p = i as u32;
+        }
+        ppu.set_crop_overscan(true);
+
+        let frame = ppu.frame();
+        assert_eq!((frame.width, frame.height), (256, 224));
+        assert_eq!(frame.pixels.len(), 256 * 224);
+        let full = &ppu.renderer.pixels;
+        assert_eq!(frame.pixels[..256], full[8 * 256..9 * 256]);
+        assert_eq!(frame.pixels[223 * 256..], full[231 * 256..232 * 256]);
+
+        ppu.set_crop_overscan(false);
+        assert_eq!(ppu.frame().height, 240);
+    }
+
+    #[test]
+    fn test_loopy_registers() {
+        // The example sequence from http://wiki.nesdev.com/w/index.php/PPU_scrolling
+        let mut ppu = Ppu::new();
+        ppu.write_register(0x2000, 0x00);
+        ppu.write_register(0x2005, 0x7D);
+        assert_eq!(ppu.loopy_registers(), (0x000F, 0x0000, 0b101, true));
+        ppu.write_register(0x2005, 0x5E);
+        assert_eq!(ppu.loopy_registers(), (0x616F, 0x0000, 0b101, false));
+        ppu.write_register(0x2006, 0x3D);
+        assert_eq!(ppu.loopy_registers(), (0x3D6F, 0x0000, 0b101, true));
+        ppu.write_register(0x2006, 0xF0);
+        assert_eq!(ppu.loopy_registers(), (0x3DF0, 0x3DF0, 0b101, false));
+    }
+
+    #[test]
+    fn test_position() {
+        let mut ppu = Ppu::new();
+        assert_eq!(ppu.position(), (0, 0));
+
+        // Vblank, where nothing is fetched from the cartridge
+        ppu.renderer.scanline = 241;
+        for _ in 0..340 {
+            ppu.tick();
+        }
+        assert_eq!(ppu.position(), (241, 340));
+        ppu.tick();
+        assert_eq!(ppu.position(), (242, 0));
+        for _ in 0..341 * 19 {
+            ppu.tick();
+        }
+        assert_eq!(ppu.position(), (261, 0));
+    }
+
+    fn count_nmis(ppu: &mut Ppu, dots: usize) -> usize {
+        (0..dots).filter(|_| ppu.tick() == PpuResult::Nmi).count()
+    }
+
+    #[test]
+    fn test_nmi_edges() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(0x2000, 0x80);
+        ppu.renderer.scanline = 241;
+        ppu.renderer.dot = 0;
+
+        // Holding vblank with NMI enabled is a single edge
+        assert_eq!(count_nmis(&mut ppu, 341), 1);
+
+        // Enabling NMI again while it's already enabled isn't an edge
+        ppu.write_register(0x2000, 0x80);
+        assert_eq!(count_nmis(&mut ppu, 341), 0);
+
+        // Toggling it off and on during vblank is
+        ppu.write_register(0x2000, 0x00);
+        ppu.write_register(0x2000, 0x80);
+        assert_eq!(count_nmis(&mut ppu, 341), 1);
+        ppu.write_register(0x2000, 0x00);
+        assert_eq!(count_nmis(&mut ppu, 341), 0);
+        ppu.write_register(0x2000, 0x80);
+        assert_eq!(count_nmis(&mut ppu, 341), 1);
+
+        // Once vblank is cleared by reading $2002 there's nothing to edge
+        ppu.read_register(0x2002);
+        ppu.write_register(0x2000, 0x00);
+        ppu.write_register(0x2000, 0x80);
+        assert_eq!(count_nmis(&mut ppu, 341), 0);
+    }
+
+    #[test]
+    fn test_vblank_without_nmi() {
+        // A game polling $2002 sees vblank start with NMIs off
+        let mut ppu = Ppu::new();
+        ppu.renderer.scanline = 241;
+        ppu.renderer.dot = 0;
+        assert_eq!(count_nmis(&mut ppu, 10), 0);
+        assert!(ppu.registers.status.vblank());
+        assert_eq!(ppu.read_register(0x2002) & 0x80, 0x80);
+        assert_eq!(ppu.read_register(0x2002) & 0x80, 0x00);
+    }
+
+    #[test]
+    fn test_nmi_enabled_as_vblank_starts() {
+        let mut ppu = Ppu::new();
+        ppu.renderer.scanline = 241;
+        ppu.renderer.dot = 1;
+        ppu.write_register(0x2000, 0x80);
+        assert_eq!(count_nmis(&mut ppu, 341), 1);
+    }
+
+    #[test]
+    fn test_status_read_as_vblank_clears() {
+        // The PPU is about to run the given dot, so the read lands just
+        // before it. Vblank, sprite 0 hit and overflow clear on dot 1.
+        for &(dot, expected) in [(0, 0xE0), (1, 0xE0), (2, 0x00)].iter() {
+            let mut ppu = Ppu::new();
+            ppu.renderer.scanline = 261;
+            ppu.renderer.dot = 0;
+            ppu.registers.status.set_vblank(true);
+            ppu.registers.status.set_sprite_zero_hit(true);
+            ppu.registers.status.set_sprite_overflow(true);
+            for _ in 0..dot {
+                ppu.tick();
+            }
+
+            let status = ppu.read_register(0x2002) & 0xE0;
+            assert_eq!(status, expected, "dot: {}", dot);
+            // Whichever happens first, vblank is clear afterwards
+            ppu.tick();
+            assert!(!ppu.registers.status.vblank(), "dot: {}", dot);
+            assert_eq!(ppu.read_register(0x2002) & 0x80, 0, "dot: {}", dot);
+        }
+    }
+
+    #[test]
+    fn test_status_read_as_vblank_starts() {
+        // The read lands just before the PPU runs the given dot on scanline
+        // 241, with vblank set on dot 1
+        let cases = [
+            (0, 0x00, true),  // Two dots early, so it all happens as normal
+            (1, 0x00, false), // One dot early suppresses the flag and NMI
+            (2, 0x80, false), // On the dot, the flag is read but no NMI
+            (3, 0x80, false), // The same one dot late
+            (4, 0x80, true),  // Two dots late is too late to stop the NMI
+        ];
+        for &(dot, expected, nmi) in cases.iter() {
+            let mut ppu = Ppu::new();
+            ppu.write_register(0x2000, 0x80);
+            ppu.renderer.scanline = 241;
+            ppu.renderer.dot = 0;
+
+            let mut nmis = count_nmis(&mut ppu, dot);
+            let status = ppu.read_register(0x2002) & 0x80;
+            let cancelled = ppu.take_nmi_cancelled();
+            nmis += count_nmis(&mut ppu, 10);
+            assert_eq!(status, expected, "dot: {}", dot);
+            assert_eq!(nmis == 1 && !cancelled, nmi, "dot: {}", dot);
+        }
+    }
+}
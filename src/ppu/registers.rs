@@ -4,8 +4,20 @@ use super::mask::Mask;
 use super::status::Status;
 use super::vram::Vram;
 
-use rand::{thread_rng, Rng};
-
+use rand::{thread_rng, Rng, SeedableRng, StdRng};
+
+// OAM is dynamic RAM that only keeps its contents while sprite evaluation
+// keeps reading it. This models the RP2C02G, where OAM left alone with
+// rendering off fades. Decay is only checked once a frame, so a row is lost
+// after OAM_DECAY_FRAMES frames without a refresh. It's refreshed a row of
+// 8 bytes at a time, so a whole row decays together. Real chips decay to
+// unpredictable values; here a decayed row reads back as zeros. The PAL
+// RP2C07 refreshes OAM during vblank itself, so leave this off for it.
+const OAM_ROW_SIZE: usize = 8;
+const OAM_ROWS: usize = 0x100 / OAM_ROW_SIZE;
+const OAM_DECAY_FRAMES: u8 = 2;
+
+#[derive(Clone)]
 pub struct Registers {
     pub vram: Vram,
     pub t_address: Address,
@@ -13,6 +25,8 @@ pub struct Registers {
     pub fine_x: u8,
     pub oam_ram: [u8; 0x100],
     oam_address: u8,
+    pub oam_decay: bool,
+    oam_row_age: [u8; OAM_ROWS],
     pub control: Control,
     pub mask: Mask,
     pub status: Status,
@@ -20,9 +34,13 @@ pub struct Registers {
     open_bus: u8,
     pub force_nmi: bool,
     pub vblank_suppress: bool,
+    // Seeded for reproducible runs, otherwise open bus decays at random
+    decay_rng: Option<StdRng>,
 }
 
 impl Registers {
+    // Everything starts out cleared at power on, except the nametables.
+    // See http://wiki.nesdev.com/w/index.php/PPU_power_up_state
     pub fn new() -> Self {
         let mut p = Registers {
             vram: Vram::new(),
@@ -31,6 +49,8 @@ impl Registers {
             fine_x: 0,
             oam_ram: [0; 0x100],
             oam_address: 0,
+            oam_decay: false,
+            oam_row_age: [0; OAM_ROWS],
             control: Control(0),
             mask: Mask(0),
             status: Status(0),
@@ -38,17 +58,31 @@ impl Registers {
             open_bus: 0,
             force_nmi: false,
             vblank_suppress: false,
+            decay_rng: None,
         };
-        p.reset();
+        p.vram.reset();
         p
     }
 
+    // The reset button clears the control, mask and scroll registers and the
+    // write latch, but v, t, OAM and VRAM keep their contents.
     pub fn reset(&mut self) {
         self.control = Control(0);
-        self.status = Status(0);
         self.mask = Mask(0);
-        self.oam_ram = [0; 0x100];
-        self.vram.reset();
+        self.fine_x = 0;
+        self.latch = false;
+        self.vram.clear_read_buffer();
+    }
+
+    // Power on also clears what the reset button leaves alone, other than
+    // OAM and VRAM
+    pub fn power_on(&mut self) {
+        self.v_address = Address(0);
+        self.t_address = Address(0);
+        self.status = Status(0);
+        self.oam_address = 0;
+        self.open_bus = 0;
+        self.reset();
     }
 
     pub fn write_register(&mut self, address: u16, value: u8) {
@@ -88,11 +122,41 @@ impl Registers {
         result
     }
 
+    pub fn latch(&self) -> bool {
+        self.latch
+    }
+
     pub fn tick_decay(&mut self) {
-        let mut rng = thread_rng();
-        for i in 0..8 {
-            if rng.gen_weighted_bool(4) {
-                self.open_bus &= !(1 << i);
+        let decayed = match self.decay_rng {
+            Some(ref mut rng) => decayed_bits(rng),
+            None => decayed_bits(&mut thread_rng()),
+        };
+        self.open_bus &= !decayed;
+    }
+
+    pub fn set_decay_seed(&mut self, seed: u64) {
+        let seed: &[_] = &[seed as usize];
+        self.decay_rng = Some(SeedableRng::from_seed(seed));
+    }
+
+    pub fn refresh_oam(&mut self) {
+        self.oam_row_age = [0; OAM_ROWS];
+    }
+
+    // Called once per frame. Rows that haven't been refreshed by rendering
+    // or written to for OAM_DECAY_FRAMES frames are cleared.
+    pub fn tick_oam_decay(&mut self) {
+        if !self.oam_decay {
+            return;
+        }
+
+        for (row, age) in self.oam_row_age.iter_mut().enumerate() {
+            *age = age.saturating_add(1);
+            if *age >= OAM_DECAY_FRAMES {
+                let start = row * OAM_ROW_SIZE;
+                for b in self.oam_ram[start..start + OAM_ROW_SIZE].iter_mut() {
+                    *b = 0;
+                }
             }
         }
     }
@@ -116,6 +180,7 @@ impl Registers {
 
     pub fn write_oam_data(&mut self, value: u8) {
         self.oam_ram[self.oam_address as usize] = value;
+        self.oam_row_age[self.oam_address as usize / OAM_ROW_SIZE] = 0;
         self.oam_address = self.oam_address.wrapping_add(1);
     }
 
@@ -143,7 +208,10 @@ impl Registers {
             self.t_address.set_low_byte(value);
             self.v_address = self.t_address.clone();
         } else {
+            // The first write also clears bit 14, the top bit of fine y
             self.t_address.set_high_byte(value);
+            let fine_y = self.t_address.fine_y();
+            self.t_address.set_fine_y(fine_y & 0b011);
         }
         self.latch = !self.latch
     }
@@ -168,11 +236,59 @@ impl Registers {
     }
 }
 
+// Each bit of the open bus decays with a one in four chance
+fn decayed_bits<R: Rng>(rng: &mut R) -> u8 {
+    let mut bits = 0;
+    for i in 0..8 {
+        if rng.gen_weighted_bool(4) {
+            bits |= 1 << i;
+        }
+    }
+    bits
+}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
 
+    #[test]
+    fn test_power_on() {
+        let reg = Registers::new();
+        assert_eq!(reg.t_address.get(), 0);
+        assert_eq!(reg.v_address.get(), 0);
+        assert_eq!(reg.fine_x, 0);
+        assert_eq!(reg.oam_address, 0);
+        assert!(!reg.latch);
+        assert_eq!(reg.control.0, 0);
+        assert_eq!(reg.mask.0, 0);
+        assert_eq!(reg.status.get(), 0);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut reg = Registers::new();
+        reg.write_register(0x2000, 0x80);
+        reg.write_register(0x2005, 0x7D);
+        reg.write_register(0x2005, 0x5E);
+        reg.write_register(0x2006, 0x21);
+        reg.write_register(0x2006, 0x08);
+        reg.write_register(0x2003, 0x10);
+        reg.write_register(0x2004, 0x55);
+        reg.write_register(0x2006, 0x3F);
+        assert!(reg.latch);
+        let (t, v) = (reg.t_address, reg.v_address);
+
+        reg.reset();
+        assert!(!reg.latch);
+        assert_eq!(reg.control.0, 0);
+        assert_eq!(reg.fine_x, 0);
+        assert_eq!(reg.t_address, t);
+        assert_eq!(reg.v_address, v);
+        assert_eq!(reg.oam_address, 0x11);
+        assert_eq!(reg.oam_ram[0x10], 0x55);
+    }
+
     #[test]
     fn test_write_control() {
         let mut reg = Registers::new();
@@ -180,6 +296,30 @@ mod test {
         assert_eq!(reg.control.0, 0b1010_1010);
     }
 
+    #[test]
+    fn test_write_control_nametable() {
+        let mut reg = Registers::new();
+        reg.write_register(0x2000, 0b0000_0011);
+        assert_eq!(reg.t_address.nametable(), 0b11);
+        assert_eq!(reg.t_address.get(), 0x0C00);
+
+        // Scroll writes leave the nametable bits alone
+        reg.write_register(0x2005, 0xFF);
+        reg.write_register(0x2005, 0xFF);
+        assert_eq!(reg.t_address.nametable(), 0b11);
+
+        // The first $2006 write replaces them
+        reg.write_register(0x2006, 0x24);
+        assert_eq!(reg.t_address.nametable(), 0b01);
+        reg.write_register(0x2006, 0x00);
+        assert_eq!(reg.v_address.nametable(), 0b01);
+
+        // And a later $2000 write only touches the nametable bits
+        reg.write_register(0x2000, 0b0000_0010);
+        assert_eq!(reg.t_address.get(), 0x2800);
+        assert_eq!(reg.v_address.get(), 0x2400);
+    }
+
     #[test]
     fn test_write_mask() {
         let mut reg = Registers::new();
@@ -275,6 +415,77 @@ mod test {
         reg.oam_address = 5;
         assert_eq!(reg.read_register(0x2004), 0x0F);
         assert_eq!(reg.oam_address, 5);
+
+        // Writes increment the address and wrap around, reads between them
+        // see the byte the next write will replace and don't move it on
+        reg.write_register(0x2003, 0xFE);
+        reg.write_register(0x2004, 0x11);
+        assert_eq!(reg.oam_address, 0xFF);
+        reg.oam_ram[0xFF] = 0x44;
+        assert_eq!(reg.read_register(0x2004), 0x44);
+        assert_eq!(reg.read_register(0x2004), 0x44);
+        reg.write_register(0x2004, 0x22);
+        reg.write_register(0x2004, 0x33);
+        assert_eq!(reg.oam_address, 0x01);
+        assert_eq!(reg.oam_ram[0xFE], 0x11);
+        assert_eq!(reg.oam_ram[0xFF], 0x22);
+        assert_eq!(reg.oam_ram[0x00], 0x33);
+
+        reg.write_register(0x2003, 0x00);
+        assert_eq!(reg.read_register(0x2004), 0x33);
+        assert_eq!(reg.oam_address, 0x00);
+    }
+
+    fn fill_oam(reg: &mut Registers, value: u8) {
+        reg.write_register(0x2003, 0);
+        for _ in 0..0x100 {
+            reg.write_register(0x2004, value);
+        }
+    }
+
+    #[test]
+    fn test_oam_decay() {
+        let mut reg = Registers::new();
+        reg.oam_decay = true;
+        fill_oam(&mut reg, 0xAA);
+
+        for _ in 0..10 {
+            // Keep the second row alive by rewriting one of its bytes
+            reg.write_register(0x2003, 0x0A);
+            reg.write_register(0x2004, 0x55);
+            reg.tick_oam_decay();
+        }
+
+        for (i, &b) in reg.oam_ram.iter().enumerate() {
+            let expected = match i {
+                0x0A => 0x55,
+                _ if i / 8 == 1 => 0xAA,
+                _ => 0,
+            };
+            assert_eq!(b, expected, "OAM byte {:02X}", i);
+        }
+    }
+
+    #[test]
+    fn test_oam_decay_refreshed_by_rendering() {
+        let mut reg = Registers::new();
+        reg.oam_decay = true;
+        fill_oam(&mut reg, 0xAA);
+        for _ in 0..10 {
+            reg.refresh_oam();
+            reg.tick_oam_decay();
+        }
+        assert!(reg.oam_ram.iter().all(|&b| b == 0xAA));
+    }
+
+    #[test]
+    fn test_oam_decay_disabled() {
+        let mut reg = Registers::new();
+        fill_oam(&mut reg, 0xAA);
+        for _ in 0..10 {
+            reg.tick_oam_decay();
+        }
+        assert!(reg.oam_ram.iter().all(|&b| b == 0xAA));
     }
 
     #[test]
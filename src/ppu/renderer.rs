@@ -1,15 +1,19 @@
 use super::PpuResult;
 use super::Registers;
-use super::colors::RGB;
+use super::colors::{build_emphasis_table, EmphasisTable, RGB};
 use super::nth_bit;
 use super::sprite::Sprite;
 
+use consts::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::mem;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct BitPlane<T> {
     pub low: T,
     pub high: T,
 }
 
+#[derive(Clone)]
 pub struct Renderer {
     pub background_latch: BitPlane<u8>,
     pub background_shift: BitPlane<u16>,
@@ -24,6 +28,14 @@ pub struct Renderer {
     pub primary_oam: Vec<Sprite>,
     pub secondary_oam: Vec<Sprite>,
     pub pixels: Vec<u32>,
+    layer_capture: bool,
+    pub accurate_sprite_overflow: bool,
+    pub signal_ppu_addresses: bool,
+    pub sprite_zero_hit_at: Option<(usize, usize)>,
+    pub background_layer: Vec<u32>,
+    pub sprite_layer: Vec<u32>,
+    colors: [u32; 64],
+    palette: EmphasisTable,
 }
 
 impl Renderer {
@@ -41,14 +53,55 @@ impl Renderer {
             scratch_address: 0,
             nametable_entry: 0,
             attribute_entry: 0,
-            pixels: Vec::with_capacity(256 * 240),
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            layer_capture: false,
+            accurate_sprite_overflow: false,
+            signal_ppu_addresses: true,
+            sprite_zero_hit_at: None,
+            background_layer: Vec::new(),
+            sprite_layer: Vec::new(),
+            colors: RGB,
+            palette: build_emphasis_table(&RGB),
         };
         r.reset();
         r
     }
 
+    pub fn set_palette(&mut self, colors: [u32; 64]) {
+        self.colors = colors;
+        self.palette = build_emphasis_table(&colors);
+    }
+
+    pub fn colors(&self) -> &[u32; 64] {
+        &self.colors
+    }
+
+    // Swaps the frame buffer for one owned by the caller, which the
+    // renderer then draws straight into. The caller gets the old one back.
+    pub fn swap_pixels(&mut self, pixels: &mut Vec<u32>) {
+        assert_eq!(pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        mem::swap(&mut self.pixels, pixels);
+    }
+
+    // Zeroes the buffer in place, as it may be one a frontend swapped in
     pub fn clear_pixels(&mut self) {
-        self.pixels = vec![0; self.pixels.capacity()]
+        for p in self.pixels.iter_mut() {
+            *p = 0
+        }
+    }
+
+    // When enabled, the background and sprites are also drawn to their own
+    // framebuffers. Layer pixels are ARGB like the composite, but opaque
+    // pixels have their alpha set to 0xFF and transparent ones are all zero.
+    pub fn set_layer_capture(&mut self, enabled: bool) {
+        self.layer_capture = enabled;
+        if enabled {
+            self.background_layer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+            self.sprite_layer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        } else {
+            self.background_layer = Vec::new();
+            self.sprite_layer = Vec::new();
+        }
     }
 
     pub fn reset(&mut self) {
@@ -74,7 +127,10 @@ impl Renderer {
                 self.tick_background(true, registers);
                 self.tick_result(registers)
             }
-            (240, 0) => PpuResult::Draw,
+            (240, 0) => {
+                registers.tick_oam_decay();
+                PpuResult::Draw
+            }
             (241, 1) => {
                 if !registers.vblank_suppress {
                     registers.status.set_vblank(true);
@@ -91,10 +147,10 @@ impl Renderer {
         };
 
         if registers.status.vblank() && registers.force_nmi && !registers.vblank_suppress {
-            if let PpuResult::None = r {
-                r = PpuResult::Nmi;
-            } else {
-                panic!();
+            // Enabling NMI on the dot vblank starts is still the one edge
+            match r {
+                PpuResult::None | PpuResult::Nmi => r = PpuResult::Nmi,
+                _ => panic!(),
             }
         }
         registers.force_nmi = false;
@@ -110,6 +166,7 @@ impl Renderer {
                 if pre {
                     registers.status.set_sprite_overflow(false);
                     registers.status.set_sprite_zero_hit(false);
+                    self.sprite_zero_hit_at = None;
                 }
             }
             257 => self.eval_sprites(registers), // TOD - should set oamaddr to 0?
@@ -140,13 +197,13 @@ impl Renderer {
                     self.reload_shift_registers();
                 }
                 2 => {
-                    self.nametable_entry = registers.vram.read_byte(self.scratch_address);
+                    self.nametable_entry = self.fetch(self.scratch_address, registers);
                 }
                 3 => {
                     self.scratch_address = registers.v_address.attribute_address();
                 }
                 4 => {
-                    self.attribute_entry = registers.vram.read_byte(self.scratch_address);
+                    self.attribute_entry = self.fetch(self.scratch_address, registers);
                     if registers.v_address.coarse_y() & 2 != 0 {
                         self.attribute_entry >>= 4;
                     }
@@ -159,13 +216,13 @@ impl Renderer {
                         + registers.v_address.tile_offset(self.nametable_entry);
                 }
                 6 => {
-                    self.background_latch.low = registers.vram.read_byte(self.scratch_address);
+                    self.background_latch.low = self.fetch(self.scratch_address, registers);
                 }
                 7 => {
                     self.scratch_address += 8;
                 }
                 0 => {
-                    self.background_latch.high = registers.vram.read_byte(self.scratch_address);
+                    self.background_latch.high = self.fetch(self.scratch_address, registers);
 
                     if registers.mask.rendering() {
                         registers.v_address.scroll_x();
@@ -174,7 +231,7 @@ impl Renderer {
                 _ => panic!("Impossible math"),
             },
             256 => {
-                self.background_latch.high = registers.vram.read_byte(self.scratch_address);
+                self.background_latch.high = self.fetch(self.scratch_address, registers);
                 if registers.mask.rendering() {
                     registers.v_address.scroll_y();
                 }
@@ -200,10 +257,10 @@ impl Renderer {
                 self.scratch_address = registers.v_address.nametable_address();
             }
             338 => {
-                self.nametable_entry = registers.vram.read_byte(self.scratch_address);
+                self.nametable_entry = self.fetch(self.scratch_address, registers);
             }
             340 => {
-                self.nametable_entry = registers.vram.read_byte(self.scratch_address);
+                self.nametable_entry = self.fetch(self.scratch_address, registers);
                 if pre && registers.mask.rendering() && self.odd_frame {
                     self.dot += 1;
                 }
@@ -213,8 +270,10 @@ impl Renderer {
         }
     }
 
+    // When the mapper isn't sent fetch addresses, it's signalled once per
+    // scanline at roughly the point an MMC3 would see A12 rise instead.
     fn tick_result(&self, registers: &mut Registers) -> PpuResult {
-        if self.dot == 260 && registers.mask.rendering() {
+        if self.dot == 260 && registers.mask.rendering() && !self.signal_ppu_addresses {
             PpuResult::Scanline
         } else {
             PpuResult::None
@@ -222,15 +281,25 @@ impl Renderer {
     }
 
     fn render_pixel(&mut self, x: usize, y: usize, registers: &mut Registers) -> Option<u8> {
-        if y < 240 && x < 256 {
+        if y < SCREEN_HEIGHT && x < SCREEN_WIDTH {
             let background_color = self.render_background_pixel(x, registers);
             let (sprite_color, sprite_behind, possible_zero_hit) =
                 self.render_sprite_pixel(x, registers);
 
             if possible_zero_hit && background_color != 0 {
+                if !registers.status.sprite_zero_hit() {
+                    self.sprite_zero_hit_at = Some((self.scanline, self.dot));
+                }
                 registers.status.set_sprite_zero_hit(true);
             }
 
+            if self.layer_capture {
+                let pixel_index = y * SCREEN_WIDTH + x;
+                self.background_layer[pixel_index] =
+                    layer_pixel(background_color, &self.colors, registers);
+                self.sprite_layer[pixel_index] = layer_pixel(sprite_color, &self.colors, registers);
+            }
+
             let colors = if sprite_behind {
                 [background_color, sprite_color]
             } else {
@@ -250,6 +319,8 @@ impl Renderer {
         let mut r = nth_bit(self.background_shift.high, 15 - registers.fine_x) << 1
             | nth_bit(self.background_shift.low, 15 - registers.fine_x);
 
+        // The attribute shifters are fed from their latches one bit per dot,
+        // so their 8 bits line up with the pattern shifters' high bytes
         if r != 0 {
             r |= (nth_bit(self.attribute_shift.high, 7 - registers.fine_x) << 1
                 | nth_bit(self.attribute_shift.low, 7 - registers.fine_x)) << 2;
@@ -298,22 +369,52 @@ impl Renderer {
     // treated as sprite 0. The first OAM entry to be checked during sprite evaluation is the one starting at OAM[OAMADDR].
     fn eval_sprites(&mut self, registers: &mut Registers) {
         self.secondary_oam.clear();
-        for i in 0..64 {
-            let address = i * 4;
-            let sprite = Sprite::new(i, &registers.oam_ram[address..address + 4]);
-
-            // There's a subtle NES detail at play here. We're loading sprites for the NEXT scanline,
-            // but we're comparing `sprite.y` to the CURRENT scanline. This is because `sprite.y` values
-            // are always offset by 1. So to draw a sprite on scanline 1, you set its Y to 0.
-            if self.scanline >= sprite.y as usize
-                && self.scanline < sprite.y as usize + registers.control.sprite_height() as usize
-            {
-                if self.secondary_oam.len() == 8 {
-                    registers.status.set_sprite_overflow(true);
-                    break;
-                }
+        if registers.mask.rendering() {
+            registers.refresh_oam();
+        }
+
+        let mut n = 0;
+        while n < 64 && self.secondary_oam.len() < 8 {
+            let address = n * 4;
+            let sprite = Sprite::new(n, &registers.oam_ram[address..address + 4]);
+            if self.sprite_in_range(sprite.y, registers) {
                 self.secondary_oam.push(sprite);
             }
+            n += 1;
+        }
+
+        // With 8 sprites found, keep looking for a 9th to set the overflow flag
+        if self.accurate_sprite_overflow {
+            self.eval_sprite_overflow(n, registers);
+        } else if registers.oam_ram[n * 4..]
+            .chunks(4)
+            .any(|s| self.sprite_in_range(s[0], registers))
+        {
+            registers.status.set_sprite_overflow(true);
+        }
+    }
+
+    // There's a subtle NES detail at play here. We're loading sprites for the NEXT scanline,
+    // but we're comparing `sprite.y` to the CURRENT scanline. This is because `sprite.y` values
+    // are always offset by 1. So to draw a sprite on scanline 1, you set its Y to 0.
+    fn sprite_in_range(&self, y: u8, registers: &Registers) -> bool {
+        self.scanline >= y as usize
+            && self.scanline < y as usize + registers.control.sprite_height() as usize
+    }
+
+    // The real PPU gets the overflow search wrong. After a sprite that's out of range it
+    // increments both the sprite index and the byte index within the sprite, so it goes on
+    // to compare tile indexes, attributes and x positions as if they were y positions.
+    // See http://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+    fn eval_sprite_overflow(&mut self, mut n: usize, registers: &mut Registers) {
+        let mut m = 0;
+        while n < 64 {
+            if self.sprite_in_range(registers.oam_ram[n * 4 + m], registers) {
+                registers.status.set_sprite_overflow(true);
+                return;
+            }
+            n += 1;
+            m = (m + 1) % 4;
         }
     }
 
@@ -321,12 +422,31 @@ impl Renderer {
         let mut sprites = self.secondary_oam.clone();
         for sprite in sprites.iter_mut() {
             let tile_address = sprite.tile_address(self.scanline, registers.control);
-            sprite.data_low = registers.vram.read_byte(tile_address);
-            sprite.data_high = registers.vram.read_byte(tile_address + 8);
+            sprite.data_low = self.fetch(tile_address, registers);
+            sprite.data_high = self.fetch(tile_address + 8, registers);
+        }
+
+        // Empty slots still fetch tile 0xFF, which mappers can see
+        let dummy_address = if registers.control.large_sprites() {
+            0x1FF0
+        } else {
+            registers.control.sprite_tile_base() + 0xFF0
+        };
+        for _ in sprites.len()..8 {
+            self.fetch(dummy_address, registers);
+            self.fetch(dummy_address + 8, registers);
         }
         self.primary_oam = sprites;
     }
 
+    // Reads for rendering, which the mapper sees on the address bus
+    fn fetch(&self, address: u16, registers: &Registers) -> u8 {
+        if self.signal_ppu_addresses && registers.mask.rendering() {
+            registers.vram.signal_address(address);
+        }
+        registers.vram.read_byte(address)
+    }
+
     fn reload_shift_registers(&mut self) {
         self.background_shift.low =
             (self.background_shift.low & 0xFF00) | self.background_latch.low as u16;
@@ -344,14 +464,27 @@ impl Renderer {
     }
 
     fn set_pixel(&mut self, x: usize, y: usize, color_index: u8, registers: &mut Registers) {
-        let pixel_index = y * 256 + x;
+        let pixel_index = y * SCREEN_WIDTH + x;
         let palette_offset = if registers.mask.rendering() {
             color_index as u16
         } else {
             0
         };
-        let rgb_index = registers.vram.read_byte(0x3f00 + palette_offset) as usize;
-        self.pixels[pixel_index] = RGB[rgb_index]
+        let mut rgb_index = registers.vram.read_byte(0x3f00 + palette_offset) as usize;
+        if registers.mask.greyscale() {
+            rgb_index &= 0x30;
+        }
+        let (r, g, b) = self.palette[registers.mask.emphasis()][rgb_index];
+        self.pixels[pixel_index] = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+    }
+}
+
+fn layer_pixel(color_index: u8, colors: &[u32; 64], registers: &mut Registers) -> u32 {
+    if color_index == 0 {
+        0
+    } else {
+        let rgb_index = registers.vram.read_byte(0x3f00 + color_index as u16) as usize;
+        0xFF00_0000 | colors[rgb_index]
     }
 }
 
@@ -362,6 +495,26 @@ mod test {
     use std::rc::Rc;
     use ppu::mask::Mask;
     use cartridge::Cartridge;
+    use ppu::colors::Palette;
+
+    #[test]
+    fn test_pixels_size() {
+        let renderer = Renderer::new();
+        assert_eq!(renderer.pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+    }
+
+    #[test]
+    fn test_clear_pixels() {
+        let mut renderer = Renderer::new();
+        let mut pixels = vec![0x123456; SCREEN_WIDTH * SCREEN_HEIGHT];
+        pixels.reserve(100);
+        renderer.swap_pixels(&mut pixels);
+        let buffer = renderer.pixels.as_ptr();
+        renderer.clear_pixels();
+        assert_eq!(renderer.pixels.as_ptr(), buffer);
+        assert_eq!(renderer.pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert!(renderer.pixels.iter().all(|&p| p == 0));
+    }
 
     #[test]
     fn test_evaluate_sprites() {
@@ -394,6 +547,61 @@ mod test {
         assert_eq!(regs.status.sprite_overflow(), true);
     }
 
+    // Puts sprites 0 to 7 on scanline 10 and sprites 8 and 9 elsewhere, as the
+    // ppu_sprite_overflow test ROM does before moving the 9th sprite around.
+    fn build_overflow_scene(accurate: bool) -> (Registers, Renderer) {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        renderer.scanline = 10;
+        renderer.accurate_sprite_overflow = accurate;
+        for i in 0..64 {
+            regs.oam_ram[i * 4] = 0xF0;
+        }
+        for i in 0..8 {
+            regs.oam_ram[i * 4] = 10;
+        }
+        (regs, renderer)
+    }
+
+    #[test]
+    fn test_accurate_sprite_overflow() {
+        // The 9th sprite directly follows the 8th, so it's read correctly
+        let (mut regs, mut renderer) = build_overflow_scene(true);
+        regs.oam_ram[8 * 4] = 10;
+        renderer.eval_sprites(&mut regs);
+        assert!(regs.status.sprite_overflow());
+    }
+
+    #[test]
+    fn test_accurate_sprite_overflow_false_negative() {
+        // Sprite 9 is on the line, but its tile index is compared instead of its y
+        let (mut regs, mut renderer) = build_overflow_scene(true);
+        regs.oam_ram[9 * 4] = 10;
+        regs.oam_ram[9 * 4 + 1] = 0xF0;
+        renderer.eval_sprites(&mut regs);
+        assert!(!regs.status.sprite_overflow());
+
+        let (mut regs, mut renderer) = build_overflow_scene(false);
+        regs.oam_ram[9 * 4] = 10;
+        regs.oam_ram[9 * 4 + 1] = 0xF0;
+        renderer.eval_sprites(&mut regs);
+        assert!(regs.status.sprite_overflow());
+    }
+
+    #[test]
+    fn test_accurate_sprite_overflow_false_positive() {
+        // No 9th sprite is on the line, but sprite 9's tile index looks like it is
+        let (mut regs, mut renderer) = build_overflow_scene(true);
+        regs.oam_ram[9 * 4 + 1] = 10;
+        renderer.eval_sprites(&mut regs);
+        assert!(regs.status.sprite_overflow());
+
+        let (mut regs, mut renderer) = build_overflow_scene(false);
+        regs.oam_ram[9 * 4 + 1] = 10;
+        renderer.eval_sprites(&mut regs);
+        assert!(!regs.status.sprite_overflow());
+    }
+
     fn build_cartridge() -> Rc<RefCell<Cartridge>> {
         let mut data = vec![
             0x4e,
@@ -420,6 +628,151 @@ mod test {
         Rc::new(RefCell::new(Cartridge::new(&data)))
     }
 
+    fn tick_until(renderer: &mut Renderer, regs: &mut Registers, scanline: usize, dot: usize) {
+        while (renderer.scanline, renderer.dot) != (scanline, dot) {
+            renderer.tick(regs);
+            renderer.step();
+        }
+    }
+
+    #[test]
+    fn test_sprite_overflow_stays_set() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        regs.vram.set_cartridge(build_cartridge());
+        regs.mask = Mask(0b0001_1000); // Show sprites and bg
+        for i in 0..64 {
+            regs.oam_ram[i * 4] = 0xF0;
+        }
+        for i in 0..9 {
+            regs.oam_ram[i * 4] = 10;
+        }
+
+        tick_until(&mut renderer, &mut regs, 11, 0);
+        assert!(regs.status.sprite_overflow());
+
+        // Reading $2002 reports it without clearing it
+        assert_eq!(regs.read_register(0x2002) & 0b0010_0000, 0b0010_0000);
+        assert_eq!(regs.read_register(0x2002) & 0b0010_0000, 0b0010_0000);
+
+        // It's only cleared at dot 1 of the pre-render scanline
+        tick_until(&mut renderer, &mut regs, 261, 1);
+        assert!(regs.status.sprite_overflow());
+        renderer.tick(&mut regs);
+        assert!(!regs.status.sprite_overflow());
+    }
+
+    #[test]
+    fn test_pre_render_prefetch() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        regs.vram.set_cartridge(build_cartridge());
+        regs.mask = Mask(0b0000_1010); // Show bg, including the left column
+        regs.vram.palettes[0x00] = 0x0F;
+        regs.vram.palettes[0x01] = 0x16;
+
+        // Tile 1 is opaque on its left half and tile 2 on its right half
+        for row in 0..8 {
+            regs.vram.write_byte(0x0010 + row, 0xF0);
+            regs.vram.write_byte(0x0020 + row, 0x0F);
+        }
+        regs.vram.write_byte(0x2000, 1);
+        regs.vram.write_byte(0x2001, 2);
+
+        renderer.scanline = 261;
+        tick_until(&mut renderer, &mut regs, 0, 258);
+
+        // The first two tiles come from the pre-render line's prefetch
+        let backdrop = renderer.pixels[20];
+        let opaque = renderer.pixels[0];
+        assert_ne!(opaque, backdrop);
+        assert!(renderer.pixels[..4].iter().all(|&p| p == opaque));
+        assert!(renderer.pixels[4..12].iter().all(|&p| p == backdrop));
+        assert!(renderer.pixels[12..16].iter().all(|&p| p == opaque));
+        assert!(renderer.pixels[16..SCREEN_WIDTH].iter().all(|&p| p == backdrop));
+    }
+
+    #[test]
+    fn test_first_pixel() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        regs.vram.set_cartridge(build_cartridge());
+        regs.mask = Mask(0b0000_1010); // Show bg, including the left column
+        regs.vram.palettes[0x00] = 0x21;
+        regs.vram.palettes[0x01] = 0x16;
+
+        // Tile 1 is opaque only in its leftmost column, and fills the row
+        // with the first background palette
+        for row in 0..8 {
+            regs.vram.write_byte(0x0010 + row, 0x80);
+        }
+        for i in 0..32 {
+            regs.vram.write_byte(0x2000 + i, 1);
+        }
+        for i in 0..8 {
+            regs.vram.write_byte(0x23C0 + i, 0);
+        }
+
+        for &fine_x in [0, 3].iter() {
+            regs.fine_x = fine_x;
+            renderer.scanline = 261;
+            renderer.dot = 0;
+            renderer.clear_pixels();
+
+            // Pixel 0 comes out on dot 2, once the shifters hold the prefetch
+            tick_until(&mut renderer, &mut regs, 0, 3);
+            let first = if fine_x == 0 { RGB[0x16] } else { RGB[0x21] };
+            assert_eq!(renderer.pixels[..2], [first, 0]);
+
+            // Fine x scrolls within the tiles without shifting the screen
+            tick_until(&mut renderer, &mut regs, 1, 0);
+            for x in 0..SCREEN_WIDTH {
+                let opaque = (x + fine_x as usize) & 7 == 0;
+                let color = if opaque { RGB[0x16] } else { RGB[0x21] };
+                assert_eq!(renderer.pixels[x], color, "fine x: {} x: {}", fine_x, x);
+            }
+        }
+    }
+
+    #[test]
+    fn test_attribute_fine_x() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        regs.vram.set_cartridge(build_cartridge());
+        regs.mask = Mask(0b0000_1010); // Show bg, including the left column
+        regs.vram.palettes[0x01] = 0x30;
+        regs.vram.palettes[0x05] = 0x16;
+        regs.vram.palettes[0x09] = 0x2A;
+
+        // Tile 1 is opaque everywhere. Tiles 0 and 1 of the row use the
+        // second palette, tiles 2 and 3 the third and the rest the first.
+        for row in 0..8 {
+            regs.vram.write_byte(0x0010 + row, 0xFF);
+        }
+        for i in 0..32 {
+            regs.vram.write_byte(0x2000 + i, 1);
+        }
+        for i in 0..8 {
+            regs.vram.write_byte(0x23C0 + i, 0);
+        }
+        regs.vram.write_byte(0x23C0, 0b0000_1001);
+
+        regs.fine_x = 3;
+        renderer.scanline = 261;
+        renderer.dot = 0;
+        tick_until(&mut renderer, &mut regs, 1, 0);
+
+        // The attribute changes with the pattern, at x + fine x == 16 and 32
+        for x in 0..48 {
+            let color = match x + 3 {
+                0...15 => RGB[0x16],
+                16...31 => RGB[0x2A],
+                _ => RGB[0x30],
+            };
+            assert_eq!(renderer.pixels[x], color, "x: {}", x);
+        }
+    }
+
     #[test]
     fn test_load_sprites() {
         let mut regs = Registers::new();
@@ -615,6 +968,103 @@ mod test {
         assert_eq!(regs.status.sprite_zero_hit(), true);
     }
 
+    #[test]
+    fn test_set_pixel_greyscale_and_emphasis() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        regs.vram.palettes[0x01] = 0x16;
+
+        regs.mask = Mask(0b0000_1000);
+        renderer.set_pixel(0, 0, 1, &mut regs);
+        assert_eq!(renderer.pixels[0], RGB[0x16]);
+
+        // Greyscale keeps only the brightness column
+        regs.mask = Mask(0b0000_1001);
+        renderer.set_pixel(0, 0, 1, &mut regs);
+        assert_eq!(renderer.pixels[0], RGB[0x10]);
+
+        // Green emphasis on 0x30 darkens red and blue
+        regs.vram.palettes[0x01] = 0x30;
+        regs.mask = Mask(0b0100_1000);
+        renderer.set_pixel(0, 0, 1, &mut regs);
+        assert_eq!(renderer.pixels[0], 0xBEFEBE);
+    }
+
+    #[test]
+    fn test_set_pixel_custom_palette() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        let pal: Vec<u8> = (0..64).flat_map(|i| vec![i as u8, 0x80, 0xFF - i as u8]).collect();
+        renderer.set_palette(Palette::from_pal_bytes(&pal).unwrap());
+        regs.vram.palettes[0x01] = 0x16;
+
+        regs.mask = Mask(0b0000_1000);
+        renderer.set_pixel(0, 0, 1, &mut regs);
+        assert_eq!(renderer.pixels[0], 0x1680E9);
+
+        // Emphasis darkens the loaded colors too
+        regs.mask = Mask(0b0010_1000);
+        renderer.set_pixel(0, 0, 1, &mut regs);
+        assert_eq!(renderer.pixels[0], 0x165FAE);
+    }
+
+    #[test]
+    fn test_layer_capture() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        renderer.set_layer_capture(true);
+        regs.mask = Mask(0b0001_1110); // Show all sprites and bg
+        regs.vram.palettes[0x03] = 0x01;
+        regs.vram.palettes[0x11] = 0x02;
+        renderer.background_shift.high = 0b1111_0000_0000_0000;
+        renderer.background_shift.low = 0b1111_0000_0000_0000;
+
+        // Sprite is opaque only at x = 0
+        let mut s = Sprite::new(1, &[0, 0, 0, 0]);
+        s.data_low = 0b1000_0000;
+        renderer.primary_oam.push(s);
+
+        assert_eq!(renderer.render_pixel(0, 0, &mut regs), Some(0b1_00_01));
+        assert_eq!(renderer.render_pixel(1, 0, &mut regs), Some(0b11));
+        renderer.background_shift.high = 0;
+        renderer.background_shift.low = 0;
+        assert_eq!(renderer.render_pixel(2, 0, &mut regs), Some(0));
+
+        let bg = 0xFF00_0000 | RGB[0x01];
+        let sprite = 0xFF00_0000 | RGB[0x02];
+        assert_eq!(renderer.background_layer[..3], [bg, bg, 0]);
+        assert_eq!(renderer.sprite_layer[..3], [sprite, 0, 0]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_position() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        regs.mask = Mask(0b0001_1110); // Show all sprites and bg
+        renderer.background_shift.high = 0b1111_0000_0000_0000;
+        renderer.background_shift.low = 0b1111_0000_0000_0000;
+
+        let mut s = Sprite::new(0, &[29, 0, 0, 10]);
+        s.data_low = 0b1100_0000;
+        renderer.primary_oam.push(s);
+
+        renderer.scanline = 30;
+        renderer.dot = 12;
+        renderer.render_pixel(10, 30, &mut regs);
+        assert_eq!(renderer.sprite_zero_hit_at, Some((30, 12)));
+
+        // Only the first hit of the frame is reported
+        renderer.dot = 13;
+        renderer.render_pixel(11, 30, &mut regs);
+        assert_eq!(renderer.sprite_zero_hit_at, Some((30, 12)));
+
+        // Cleared along with the flag on the pre-render line
+        renderer.scanline = 261;
+        renderer.dot = 1;
+        renderer.tick_sprites(true, &mut regs);
+        assert_eq!(renderer.sprite_zero_hit_at, None);
+    }
+
     #[test]
     fn test_render_pixel_opaque_sprite_behind() {
         let mut regs = Registers::new();
@@ -636,4 +1086,85 @@ mod test {
         assert_eq!(regs.status.sprite_zero_hit(), true);
     }
 
+    #[test]
+    fn test_sprite_zero_hit_needs_both_layers() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        renderer.background_shift.high = 0b1111_0000_0000_0000;
+        renderer.background_shift.low = 0b1111_0000_0000_0000;
+
+        let mut s = Sprite::new(0, &[0, 0, 0, 0]);
+        s.data_low = 0b1000_0000;
+        renderer.primary_oam.push(s);
+
+        // Sprites only, so the background reads as transparent
+        regs.mask = Mask(0b0001_0100);
+        assert_eq!(renderer.render_pixel(0, 0, &mut regs), Some(0b1_00_01));
+        assert!(!regs.status.sprite_zero_hit());
+
+        // Background only, so sprite 0 isn't drawn at all
+        regs.mask = Mask(0b0000_1010);
+        assert_eq!(renderer.render_pixel(0, 0, &mut regs), Some(0b11));
+        assert!(!regs.status.sprite_zero_hit());
+
+        regs.mask = Mask(0b0001_1110);
+        renderer.render_pixel(0, 0, &mut regs);
+        assert!(regs.status.sprite_zero_hit());
+    }
+
+    fn build_sprite_zero_renderer(sprite_x: u8) -> Renderer {
+        // Opaque background everywhere and sprite 0 opaque across its width
+        let mut renderer = Renderer::new();
+        renderer.background_shift.high = 0xFFFF;
+        renderer.background_shift.low = 0xFFFF;
+        let mut s = Sprite::new(0, &[0, 0, 0, sprite_x]);
+        s.data_low = 0xFF;
+        renderer.primary_oam.push(s);
+        renderer
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_not_at_x_255() {
+        let mut regs = Registers::new();
+        regs.mask = Mask(0b0001_1110); // Show all sprites and bg
+        let mut renderer = build_sprite_zero_renderer(248);
+
+        renderer.render_pixel(255, 0, &mut regs);
+        assert!(!regs.status.sprite_zero_hit());
+        renderer.render_pixel(254, 0, &mut regs);
+        assert!(regs.status.sprite_zero_hit());
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_left_column() {
+        // Hiding either layer in the left 8 pixels means no hit there
+        for &mask in [0b0001_1100, 0b0001_1010].iter() {
+            let mut regs = Registers::new();
+            regs.mask = Mask(mask);
+            let mut renderer = build_sprite_zero_renderer(1);
+
+            for x in 1..8 {
+                renderer.render_pixel(x, 0, &mut regs);
+            }
+            assert!(!regs.status.sprite_zero_hit(), "mask: {:08b}", mask);
+            renderer.render_pixel(8, 0, &mut regs);
+            assert!(regs.status.sprite_zero_hit(), "mask: {:08b}", mask);
+        }
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_cleared_on_pre_render_line() {
+        let mut regs = Registers::new();
+        let mut renderer = Renderer::new();
+        regs.status.set_sprite_zero_hit(true);
+
+        // It's still set at the end of vblank, and clears on dot 1
+        renderer.scanline = 261;
+        renderer.dot = 0;
+        renderer.tick_sprites(true, &mut regs);
+        assert!(regs.status.sprite_zero_hit());
+        renderer.dot = 1;
+        renderer.tick_sprites(true, &mut regs);
+        assert!(!regs.status.sprite_zero_hit());
+    }
 }
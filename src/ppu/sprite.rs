@@ -1,8 +1,11 @@
 use super::control::Control;
 use super::nth_bit;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 bitfield!{
     #[derive(Copy, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
     pub struct SpriteStatus(u8);
     impl Debug;
     pub palette,            _: 1, 0;
@@ -136,6 +139,24 @@ mod test {
         assert_eq!(sprite.tile_address(20, c), 0x1000 + (6 * 16) + 0 + (5 - 5));
     }
 
+    #[test]
+    fn test_tile_address_pattern_table() {
+        // 8x16 sprites take the table from bit 0 of the tile index and ignore
+        // PPUCTRL's sprite table bit, which 8x8 sprites use instead
+        let odd = Sprite::new(0, &[5, 7, 0, 0]);
+        let even = Sprite::new(0, &[5, 6, 0, 0]);
+        let large = Control(0b0010_0000);
+        let large_table_1 = Control(0b0010_1000);
+        assert_eq!(odd.tile_address(5, large), 0x1000 + (6 * 16));
+        assert_eq!(odd.tile_address(5, large_table_1), 0x1000 + (6 * 16));
+        assert_eq!(even.tile_address(5, large), 6 * 16);
+        assert_eq!(even.tile_address(5, large_table_1), 6 * 16);
+
+        // Switching to 8x8 sprites mid-frame goes back to PPUCTRL's table
+        assert_eq!(odd.tile_address(5, Control(0)), 7 * 16);
+        assert_eq!(even.tile_address(5, Control(0b0000_1000)), 0x1000 + (6 * 16));
+    }
+
     #[test]
     fn test_color_index() {
         let mut sprite = Sprite::new(0, &[0, 0, 0, 4]);
@@ -1,5 +1,9 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 bitfield!{
     #[derive(Copy, Clone)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
     pub struct Status(u8);
     impl Debug;
     pub sprite_overflow, set_sprite_overflow:        5;
@@ -0,0 +1,226 @@
+// Renders nametables and pattern tables, and decodes OAM and palettes, for
+// debugging tools. Everything is read straight from VRAM and OAM, so the
+// scroll registers and render state of the running PPU are left untouched.
+
+use super::registers::Registers;
+use super::sprite::Sprite;
+use super::vram::Vram;
+
+use consts::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const PATTERN_TABLE_SIZE: usize = 128;
+
+const TILES_WIDE: usize = SCREEN_WIDTH / 8;
+const TILES_HIGH: usize = SCREEN_HEIGHT / 8;
+
+// Renders one of the four logical nametables, 0x2000, 0x2400, 0x2800 or
+// 0x2C00, as a SCREEN_WIDTH x SCREEN_HEIGHT image using the current
+// background pattern table and palettes.
+pub fn render_nametable(registers: &Registers, colors: &[u32; 64], index: u8) -> Vec<u32> {
+    let vram = &registers.vram;
+    let base = 0x2000 + (index as u16 & 0b11) * 0x400;
+    let tile_base = registers.control.background_tile_base();
+    let mut pixels = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+    for row in 0..TILES_HIGH {
+        for col in 0..TILES_WIDE {
+            let tile = vram.read_byte(base + (row * TILES_WIDE + col) as u16);
+            let attribute = vram.read_byte(base + 0x3C0 + ((row / 4) * 8 + col / 4) as u16);
+            let shift = (row & 2) << 1 | (col & 2);
+            let palette = (attribute >> shift) & 0b11;
+            let tile_address = tile_base + tile as u16 * 16;
+            let target = (&mut pixels[..], SCREEN_WIDTH, col * 8, row * 8);
+            draw_tile(vram, colors, tile_address, palette, target);
+        }
+    }
+    pixels
+}
+
+// Renders all four nametables as a 2x2 grid, twice the size of the screen
+// in each direction.
+pub fn render_nametables(registers: &Registers, colors: &[u32; 64]) -> Vec<u32> {
+    let width = 2 * SCREEN_WIDTH;
+    let mut pixels = vec![0; width * 2 * SCREEN_HEIGHT];
+    for index in 0..4u8 {
+        let nametable = render_nametable(registers, colors, index);
+        let x = (index as usize % 2) * SCREEN_WIDTH;
+        let y = (index as usize / 2) * SCREEN_HEIGHT;
+        for (row, line) in nametable.chunks(SCREEN_WIDTH).enumerate() {
+            let start = (y + row) * width + x;
+            pixels[start..start + SCREEN_WIDTH].copy_from_slice(line);
+        }
+    }
+    pixels
+}
+
+// Renders the 256 tiles of pattern table 0 or 1 as a 16x16 grid of tiles.
+// Palettes 0 to 3 are the background palettes and 4 to 7 are for sprites.
+pub fn render_pattern_table(
+    registers: &Registers,
+    colors: &[u32; 64],
+    table: u8,
+    palette: u8,
+) -> Vec<u32> {
+    let vram = &registers.vram;
+    let base = (table as u16 & 1) * 0x1000;
+    let mut pixels = vec![0; PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE];
+
+    for tile in 0..256 {
+        let x = (tile % 16) * 8;
+        let y = (tile / 16) * 8;
+        let tile_address = base + tile as u16 * 16;
+        let target = (&mut pixels[..], PATTERN_TABLE_SIZE, x, y);
+        draw_tile(vram, colors, tile_address, palette & 0b111, target);
+    }
+    pixels
+}
+
+pub fn oam_sprites(registers: &Registers) -> Vec<Sprite> {
+    registers
+        .oam_ram
+        .chunks(4)
+        .enumerate()
+        .map(|(i, bytes)| Sprite::new(i, bytes))
+        .collect()
+}
+
+pub fn palette_rgb(registers: &Registers, colors: &[u32; 64]) -> [u32; 32] {
+    let mut rgb = [0; 32];
+    for (i, color) in rgb.iter_mut().enumerate() {
+        let rgb_index = registers.vram.read_byte(0x3F00 + i as u16) as usize & 0x3F;
+        *color = colors[rgb_index];
+    }
+    rgb
+}
+
+// Draws a tile into pixels, which is width wide, with its corner at x, y
+fn draw_tile(
+    vram: &Vram,
+    colors: &[u32; 64],
+    tile_address: u16,
+    palette: u8,
+    (pixels, width, x, y): (&mut [u32], usize, usize, usize),
+) {
+    for fine_y in 0..8 {
+        let low = vram.read_byte(tile_address + fine_y as u16);
+        let high = vram.read_byte(tile_address + fine_y as u16 + 8);
+        for fine_x in 0..8 {
+            let bit = 7 - fine_x;
+            let color = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+            let palette_address = if color == 0 {
+                0x3F00
+            } else {
+                0x3F00 + (palette as u16) * 4 + color as u16
+            };
+            let rgb_index = vram.read_byte(palette_address) as usize & 0x3F;
+            pixels[(y + fine_y) * width + x + fine_x] = colors[rgb_index];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::colors::RGB;
+    use cartridge::Cartridge;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn build_registers() -> Registers {
+        let mut data = vec![
+            0x4e,
+            0x45,
+            0x53,
+            0x1a,
+            0x02, // Two pages of PRG-ROM
+            0x00, // Zero pages CHR-ROM means use CHR-RAM
+            0x00,
+            0x00,
+            0x01, // One page of PRG-RAM
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        data.extend_from_slice(&[0u8; 2 * 0x4000]);
+
+        let mut regs = Registers::new();
+        regs.vram.set_cartridge(Rc::new(RefCell::new(Cartridge::new(&data))));
+
+        // Tile 1 is a left column of color 1 and a right column of color 3
+        for fine_y in 0..8 {
+            regs.vram.write_byte(16 + fine_y, 0b1000_0001);
+            regs.vram.write_byte(16 + fine_y + 8, 0b0000_0001);
+        }
+
+        regs.vram.write_byte(0x3F00, 0x0F);
+        regs.vram.write_byte(0x3F01, 0x01);
+        regs.vram.write_byte(0x3F03, 0x03);
+        regs.vram.write_byte(0x3F05, 0x05);
+        regs.vram.write_byte(0x3F17, 0x17);
+        regs
+    }
+
+    #[test]
+    fn test_render_pattern_table() {
+        let regs = build_registers();
+        let pixels = render_pattern_table(&regs, &RGB, 0, 0);
+        assert_eq!(pixels.len(), PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE);
+        assert_eq!(pixels[0], RGB[0x0F]);
+        assert_eq!(pixels[8], RGB[0x01]);
+        assert_eq!(pixels[9], RGB[0x0F]);
+        assert_eq!(pixels[7 * PATTERN_TABLE_SIZE + 15], RGB[0x03]);
+
+        let pixels = render_pattern_table(&regs, &RGB, 0, 5);
+        assert_eq!(pixels[15], RGB[0x17]);
+    }
+
+    #[test]
+    fn test_render_nametable() {
+        let mut regs = build_registers();
+        // Tile 1 at row 1, column 2 of nametable 1, with palette 1
+        regs.vram.write_byte(0x2400 + 32 + 2, 1);
+        regs.vram.write_byte(0x2400 + 0x3C0, 0b0100);
+        let v = regs.v_address;
+        let t = regs.t_address;
+
+        let pixels = render_nametable(&regs, &RGB, 1);
+        assert_eq!(pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(pixels[8 * SCREEN_WIDTH + 16], RGB[0x05]);
+        assert_eq!(pixels[8 * SCREEN_WIDTH + 17], RGB[0x0F]);
+        assert_eq!(regs.v_address, v);
+        assert_eq!(regs.t_address, t);
+
+        let all = render_nametables(&regs, &RGB);
+        assert_eq!(all.len(), 4 * SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(all[8 * 2 * SCREEN_WIDTH + SCREEN_WIDTH + 16], RGB[0x05]);
+    }
+
+    #[test]
+    fn test_oam_sprites() {
+        let mut regs = build_registers();
+        regs.oam_ram[4..8].copy_from_slice(&[0x10, 0x22, 0b0100_0001, 0x30]);
+        let sprites = oam_sprites(&regs);
+        assert_eq!(sprites.len(), 64);
+        assert_eq!(sprites[1].oam_index, 1);
+        assert_eq!(sprites[1].y, 0x10);
+        assert_eq!(sprites[1].x, 0x30);
+        assert_eq!(sprites[1].status.palette(), 1);
+        assert!(sprites[1].status.flip_x());
+        assert_eq!(sprites[1].tile_index.small_offset(), 0x220);
+    }
+
+    #[test]
+    fn test_palette_rgb() {
+        let regs = build_registers();
+        let colors = palette_rgb(&regs, &RGB);
+        assert_eq!(colors[0x00], RGB[0x0F]);
+        assert_eq!(colors[0x03], RGB[0x03]);
+        assert_eq!(colors[0x17], RGB[0x17]);
+        // 0x3F10 mirrors the backdrop at 0x3F00
+        assert_eq!(colors[0x10], RGB[0x0F]);
+    }
+}
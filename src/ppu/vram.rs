@@ -5,8 +5,11 @@ use std::rc::Rc;
 const NAMETABLE_SIZE: usize = 0x400;
 const PALETTE_SIZE: usize = 0x20;
 
+#[derive(Clone)]
 pub struct Vram {
-    pub nametables: [u8; 2 * NAMETABLE_SIZE],
+    // The console only has RAM for two nametables. Four-screen games carry
+    // RAM for the other two on the cartridge, which lives here for simplicity.
+    pub nametables: [u8; 4 * NAMETABLE_SIZE],
     pub palettes: [u8; PALETTE_SIZE],
     read_buffer: u8,
     cartridge: Option<Rc<RefCell<Cartridge>>>,
@@ -15,7 +18,7 @@ pub struct Vram {
 impl Vram {
     pub fn new() -> Self {
         Vram {
-            nametables: [0; 2 * NAMETABLE_SIZE],
+            nametables: [0; 4 * NAMETABLE_SIZE],
             palettes: [0; PALETTE_SIZE],
             read_buffer: 0,
             cartridge: None,
@@ -23,11 +26,15 @@ impl Vram {
     }
 
     pub fn reset(&mut self) {
-        self.nametables = [0xFF; 0x800];
-        self.palettes = [0; 0x20];
+        self.nametables = [0xFF; 4 * NAMETABLE_SIZE];
+        self.palettes = [0; PALETTE_SIZE];
         self.cartridge = None;
     }
 
+    pub fn clear_read_buffer(&mut self) {
+        self.read_buffer = 0;
+    }
+
     pub fn set_cartridge(&mut self, cartridge: Rc<RefCell<Cartridge>>) {
         self.cartridge = Some(cartridge);
     }
@@ -44,20 +51,40 @@ impl Vram {
         let mirroring = self.mirroring();
         match address {
             0x0000...0x1FFF => match self.cartridge {
-                Some(ref c) => c.borrow_mut().write_chr_byte(address, value),
+                Some(ref c) => {
+                    let page = c.borrow().chr_console_ram_page(address);
+                    match page {
+                        Some(page) => self.nametables[chr_console_ram(page, address)] = value,
+                        None => c.borrow_mut().write_chr_byte(address, value),
+                    }
+                }
                 None => panic!("tried to write to non-existant cartridge memory"),
             },
             0x2000...0x3EFF => self.nametables[mirror_nametable(mirroring, address)] = value,
-            0x3F00...0x3FFF => self.palettes[mirror_palette(address)] = value,
+            // Palette RAM is only 6 bits wide
+            0x3F00...0x3FFF => self.palettes[mirror_palette(address)] = value & 0x3F,
             _ => (),
         };
     }
 
-    pub fn read_byte(&mut self, address: u16) -> u8 {
+    // Puts an address on the PPU's address bus for the cartridge to see
+    pub fn signal_address(&self, address: u16) {
+        if let Some(ref c) = self.cartridge {
+            c.borrow_mut().signal_ppu_address(address);
+        }
+    }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
         let mirroring = self.mirroring();
         match address {
             0x0000...0x1FFF => match self.cartridge {
-                Some(ref c) => c.borrow().read_chr_byte(address),
+                Some(ref c) => {
+                    let c = c.borrow();
+                    match c.chr_console_ram_page(address) {
+                        Some(page) => self.nametables[chr_console_ram(page, address)],
+                        None => c.read_chr_byte(address),
+                    }
+                }
                 None => panic!("tried to read non-existant cartridge memory"),
             },
             0x2000...0x3EFF => self.nametables[mirror_nametable(mirroring, address)],
@@ -79,12 +106,27 @@ impl Vram {
     }
 }
 
+// Where a pattern table address lands in a page of console nametable RAM
+fn chr_console_ram(page: u8, address: u16) -> usize {
+    page as usize * NAMETABLE_SIZE + address as usize % NAMETABLE_SIZE
+}
+
 fn mirror_nametable(mirroring: Mirroring, address: u16) -> usize {
     let address = address as usize;
     let result = match mirroring {
         Mirroring::None => address - 0x2000,
+        // Bit 11 of the address selects the top (0x2000, 0x2400) or bottom
+        // (0x2800, 0x2C00) pair. Halving the address moves it to bit 10,
+        // where masking with NAMETABLE_SIZE keeps it as the physical table.
         Mirroring::Horizontal => ((address / 2) & NAMETABLE_SIZE) + (address % NAMETABLE_SIZE),
         Mirroring::Vertical => address % (2 * NAMETABLE_SIZE),
+        Mirroring::SingleScreenLower => address % NAMETABLE_SIZE,
+        Mirroring::SingleScreenUpper => NAMETABLE_SIZE + address % NAMETABLE_SIZE,
+        Mirroring::FourScreen => address % (4 * NAMETABLE_SIZE),
+        Mirroring::Custom(pages) => {
+            let page = pages[(address - 0x2000) / NAMETABLE_SIZE % 4] as usize;
+            page * NAMETABLE_SIZE + address % NAMETABLE_SIZE
+        }
     };
     result
 }
@@ -102,6 +144,16 @@ fn mirror_palette(address: u16) -> usize {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_reset() {
+        let mut v = Vram::new();
+        v.nametables[0x123] = 0;
+        v.palettes[0x05] = 0x11;
+        v.reset();
+        assert!(v.nametables.iter().all(|&b| b == 0xFF));
+        assert!(v.palettes.iter().all(|&b| b == 0));
+    }
+
     #[test]
     fn test_read_byte_nametable() {
         let mut v = Vram::new();
@@ -133,9 +185,15 @@ mod test {
         let mut v = Vram::new();
         v.write_byte(0x3F09, 0x11);
         assert_eq!(v.palettes[0x09], 0x11);
+        v.write_byte(0x3F09, 0xFF);
+        assert_eq!(v.palettes[0x09], 0x3F);
+    }
+
+    fn build_cartridge(flags_6: u8) -> Rc<RefCell<Cartridge>> {
+        build_cartridge_with_flags(flags_6, 0)
     }
 
-    fn build_cartridge() -> Rc<RefCell<Cartridge>> {
+    fn build_cartridge_with_flags(flags_6: u8, flags_7: u8) -> Rc<RefCell<Cartridge>> {
         let mut data = vec![
             0x4e,
             0x45,
@@ -143,8 +201,8 @@ mod test {
             0x1a,
             0x02, // Two pages of PRG-ROM
             0x01, // One page of CHR-ROM
-            0x00,
-            0x00,
+            flags_6,
+            flags_7,
             0x01, // One page of PRG-RAM
             0x00,
             0x00,
@@ -169,12 +227,42 @@ mod test {
     #[test]
     fn test_read_byte_cartridge() {
         let mut v = Vram::new();
-        v.set_cartridge(build_cartridge());
+        v.set_cartridge(build_cartridge(0));
         assert_eq!(v.read_byte(0), 0);
         assert_eq!(v.read_byte(10), 10);
         assert_eq!(v.read_byte(20), 20);
     }
 
+    #[test]
+    fn test_chr_console_ram() {
+        // Namco 163, with console RAM page 1 at 0x0400-0x07FF
+        let mut v = Vram::new();
+        let c = build_cartridge_with_flags(0x30, 0x10);
+        c.borrow_mut().write_prg_byte(0x8800, 0xE1);
+        v.set_cartridge(c.clone());
+        v.write_byte(0x0401, 0x11);
+        assert_eq!(v.nametables[0x401], 0x11);
+        assert_eq!(v.read_byte(0x2401), 0x11);
+        assert_eq!(v.read_byte(0x0401), 0x11);
+
+        // Until it's disabled for the lower pattern table
+        c.borrow_mut().write_prg_byte(0xE800, 0b0100_0000);
+        assert_eq!(v.read_byte(0x0401), 1);
+    }
+
+    #[test]
+    fn test_write_byte_four_screen() {
+        let mut v = Vram::new();
+        v.set_cartridge(build_cartridge(0b1000));
+        assert_eq!(v.mirroring(), Mirroring::FourScreen);
+        v.write_byte(0x2401, 0x11);
+        v.write_byte(0x2801, 0x22);
+        assert_eq!(v.nametables[0x401], 0x11);
+        assert_eq!(v.nametables[0x801], 0x22);
+        assert_eq!(v.read_byte(0x2001), 0);
+        assert_eq!(v.read_byte(0x2C01), 0);
+    }
+
     #[test]
     fn test_buffered_read_byte() {
         let mut v = Vram::new();
@@ -213,6 +301,21 @@ mod test {
         assert_eq!(mirror_nametable(Mirroring::Horizontal, 0x3E01), 0x601);
     }
 
+    #[test]
+    fn test_mirror_nametable_horizontally_all_addresses() {
+        for address in 0x2000..0x3F00u16 {
+            let logical = ((address as usize - 0x2000) / NAMETABLE_SIZE) % 4;
+            let physical = logical / 2;
+            let expected = physical * NAMETABLE_SIZE + address as usize % NAMETABLE_SIZE;
+            assert_eq!(
+                mirror_nametable(Mirroring::Horizontal, address),
+                expected,
+                "address: {:04X}",
+                address
+            );
+        }
+    }
+
     #[test]
     fn test_mirror_nametable_vertically() {
         // Nametable 1 - starting at 0x2000
@@ -240,6 +343,34 @@ mod test {
         assert_eq!(mirror_nametable(Mirroring::Vertical, 0x3E01), 0x601);
     }
 
+    #[test]
+    fn test_mirror_nametable_single_screen() {
+        for &address in &[0x2001, 0x2401, 0x2801, 0x2C01, 0x3401] {
+            assert_eq!(mirror_nametable(Mirroring::SingleScreenLower, address), 1);
+            assert_eq!(mirror_nametable(Mirroring::SingleScreenUpper, address), 0x401);
+        }
+    }
+
+    #[test]
+    fn test_mirror_nametable_four_screen() {
+        assert_eq!(mirror_nametable(Mirroring::FourScreen, 0x2001), 1);
+        assert_eq!(mirror_nametable(Mirroring::FourScreen, 0x2401), 0x401);
+        assert_eq!(mirror_nametable(Mirroring::FourScreen, 0x2801), 0x801);
+        assert_eq!(mirror_nametable(Mirroring::FourScreen, 0x2C01), 0xC01);
+        assert_eq!(mirror_nametable(Mirroring::FourScreen, 0x3401), 0x401);
+        assert_eq!(mirror_nametable(Mirroring::FourScreen, 0x3C01), 0xC01);
+    }
+
+    #[test]
+    fn test_mirror_nametable_custom() {
+        let mirroring = Mirroring::Custom([1, 0, 0, 1]);
+        assert_eq!(mirror_nametable(mirroring, 0x2001), 0x401);
+        assert_eq!(mirror_nametable(mirroring, 0x2401), 1);
+        assert_eq!(mirror_nametable(mirroring, 0x2801), 1);
+        assert_eq!(mirror_nametable(mirroring, 0x2C01), 0x401);
+        assert_eq!(mirror_nametable(mirroring, 0x3001), 0x401);
+    }
+
     #[test]
     fn test_mirror_palette() {
         assert_eq!(mirror_palette(0x3F01), 1);
@@ -0,0 +1,53 @@
+use rand::{Rng, SeedableRng, StdRng};
+
+// What the CPU's RAM holds at power on. Real hardware powers up with an
+// indeterminate pattern, and a few games depend on what it happens to be.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum RamInit {
+    #[default]
+    Zeros,
+    Ones,
+    // Four bytes of 0x00 then four of 0xFF, as FCEUX uses
+    Pattern,
+    Random(u64),
+}
+
+impl RamInit {
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInit::Zeros => ram.iter_mut().for_each(|b| *b = 0x00),
+            RamInit::Ones => ram.iter_mut().for_each(|b| *b = 0xFF),
+            RamInit::Pattern => for (i, b) in ram.iter_mut().enumerate() {
+                *b = if i & 4 == 0 { 0x00 } else { 0xFF };
+            },
+            RamInit::Random(seed) => {
+                let seed: &[_] = &[seed as usize];
+                let mut rng: StdRng = SeedableRng::from_seed(seed);
+                rng.fill_bytes(ram);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let mut ram = [0x42u8; 16];
+        RamInit::Ones.fill(&mut ram);
+        assert!(ram.iter().all(|&b| b == 0xFF));
+
+        RamInit::Pattern.fill(&mut ram);
+        assert_eq!(ram[..8], [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        // The same seed always gives the same RAM
+        let mut other = [0u8; 16];
+        RamInit::Random(7).fill(&mut ram);
+        RamInit::Random(7).fill(&mut other);
+        assert_eq!(ram, other);
+        RamInit::Random(8).fill(&mut other);
+        assert_ne!(ram, other);
+    }
+}
@@ -0,0 +1,89 @@
+use consts::NTSC_FPS;
+use cpu::Cpu;
+use std::collections::VecDeque;
+
+// Snapshots of the console taken every few frames. Once it holds the
+// configured number of seconds, the oldest snapshot is dropped for each new
+// one. Snapshots share the ROM and leave out the frame buffer and audio, so
+// they're mostly RAM, around 20KB each for most cartridges.
+pub struct Rewind {
+    snapshots: VecDeque<Cpu>,
+    capacity: usize,
+    interval: usize,
+    frames: usize,
+}
+
+impl Rewind {
+    pub fn new(seconds: f64, interval: usize) -> Self {
+        let interval = interval.max(1);
+        Rewind {
+            snapshots: VecDeque::new(),
+            capacity: (seconds * NTSC_FPS / interval as f64).ceil() as usize,
+            interval,
+            frames: 0,
+        }
+    }
+
+    // Called once a frame, taking a snapshot every interval frames
+    pub fn push(&mut self, cpu: &mut Cpu) {
+        if self.frames == 0 && self.capacity > 0 {
+            if self.snapshots.len() == self.capacity {
+                self.snapshots.pop_front();
+            }
+            self.snapshots.push_back(cpu.snapshot());
+        }
+        self.frames = (self.frames + 1) % self.interval;
+    }
+
+    pub fn pop(&mut self) -> Option<Cpu> {
+        self.frames = 0;
+        self.snapshots.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bus::Bus;
+
+    #[test]
+    fn test_capacity() {
+        // Half a second of snapshots every 10 frames
+        let mut rewind = Rewind::new(0.5, 10);
+        let mut cpu = Cpu::new(Bus::new());
+        for _ in 0..200 {
+            rewind.push(&mut cpu);
+        }
+        assert_eq!(rewind.snapshots.len(), 4);
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut rewind = Rewind::new(10.0, 3);
+        let mut cpu = Cpu::new(Bus::new());
+        for _ in 0..7 {
+            rewind.push(&mut cpu);
+        }
+        // Frames 0, 3 and 6
+        assert_eq!(rewind.snapshots.len(), 3);
+    }
+
+    #[test]
+    fn test_snapshot_output() {
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x00, 0x00];
+        rom.extend_from_slice(&[0; 8]);
+        rom.extend_from_slice(&[0; 2 * 0x4000 + 0x2000]);
+        let mut bus = Bus::new();
+        bus.load_rom_from_memory(&rom).unwrap();
+        let mut cpu = Cpu::new(bus);
+        cpu.bus.apu.buffer.push(1);
+
+        let mut rewind = Rewind::new(1.0, 1);
+        rewind.push(&mut cpu);
+        let snapshot = rewind.pop().unwrap();
+        assert!(snapshot.bus.apu.buffer.is_empty());
+        assert!(snapshot.bus.ppu.renderer.pixels.is_empty());
+        assert_eq!(cpu.bus.apu.buffer, vec![1]);
+        assert_eq!(cpu.bus.ppu.renderer.pixels.len(), 256 * 240);
+    }
+}
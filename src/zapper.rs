@@ -0,0 +1,66 @@
+// Zapper implements the NES light gun
+// http://wiki.nesdev.com/w/index.php/Zapper
+//
+// The frontend decides whether the gun can see light, usually by sampling
+// the rendered frame around the cursor.
+#[derive(Clone)]
+pub struct Zapper {
+    trigger: bool,
+    light: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper {
+            trigger: false,
+            light: false,
+        }
+    }
+
+    // Bit 3 is clear while light is sensed, and bit 4 is set while the
+    // trigger is held
+    pub fn read_register(&self) -> u8 {
+        let mut v = 0;
+        if !self.light {
+            v |= 0b0000_1000;
+        }
+        if self.trigger {
+            v |= 0b0001_0000;
+        }
+        v
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    pub fn set_light(&mut self, sensed: bool) {
+        self.light = sensed;
+    }
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_register() {
+        let mut zapper = Zapper::new();
+        assert_eq!(zapper.read_register(), 0b0000_1000);
+
+        zapper.set_trigger(true);
+        assert_eq!(zapper.read_register(), 0b0001_1000);
+
+        zapper.set_light(true);
+        assert_eq!(zapper.read_register(), 0b0001_0000);
+
+        zapper.set_trigger(false);
+        assert_eq!(zapper.read_register(), 0);
+    }
+}
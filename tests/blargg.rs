@@ -0,0 +1,118 @@
+// Runs blargg's test ROMs, which report their results through PRG-RAM:
+//
+// $6000     status: $80 while running, $81 when the reset button should be
+//           pressed, and otherwise the result code, 0 for a pass
+// $6001-3   $DE $B0 $61, marking the status as valid
+// $6004     the result as text, ending with a 0 byte
+//
+// The ROMs aren't in the repository, so the tests are ignored. Put them in
+// tests/roms/blargg/ and run:
+//
+//     cargo test --test blargg -- --ignored
+//
+// Add a ROM by giving its test a name and a path in blargg_tests! below.
+
+extern crate nes;
+
+use std::fs;
+
+use nes::Nes;
+
+const ROM_DIR: &str = "tests/roms/blargg";
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+// The ROMs ask for the reset button to be held at least 100ms
+const RESET_DELAY_FRAMES: usize = 10;
+const MAX_FRAMES: usize = 60 * 60;
+
+fn read_status(nes: &mut Nes) -> Option<u8> {
+    let bus = &mut nes.cpu.bus;
+    let signature = [
+        bus.unclocked_read_byte(0x6001),
+        bus.unclocked_read_byte(0x6002),
+        bus.unclocked_read_byte(0x6003),
+    ];
+    if signature == SIGNATURE {
+        Some(bus.unclocked_read_byte(0x6000))
+    } else {
+        None
+    }
+}
+
+fn read_text(nes: &mut Nes) -> String {
+    let mut text = String::new();
+    for address in 0x6004..0x8000u16 {
+        match nes.cpu.bus.unclocked_read_byte(address) {
+            0 => break,
+            c => text.push(c as char),
+        }
+    }
+    text
+}
+
+fn run_blargg(path: &str) {
+    let path = format!("{}/{}", ROM_DIR, path);
+    let rom = fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    let mut nes = Nes::new();
+    nes.load_rom(&rom);
+
+    let mut reset_at = None;
+    for frame in 0..MAX_FRAMES {
+        nes.run_frame();
+        match read_status(&mut nes) {
+            None | Some(RUNNING) => (),
+            Some(NEEDS_RESET) => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(f) if f == frame => {
+                    nes.reset();
+                    reset_at = None;
+                }
+                _ => (),
+            },
+            Some(code) => {
+                let text = read_text(&mut nes);
+                assert!(
+                    code == 0 && text.contains("Passed"),
+                    "{} failed with code {}:\n{}",
+                    path,
+                    code,
+                    text
+                );
+                return;
+            }
+        }
+    }
+    panic!("{} didn't finish in {} frames", path, MAX_FRAMES);
+}
+
+macro_rules! blargg_tests {
+    ($($name:ident: $path:expr,)*) => {
+        $(
+            #[test]
+            #[ignore]
+            fn $name() {
+                run_blargg($path);
+            }
+        )*
+    }
+}
+
+blargg_tests! {
+    apu_len_ctr: "apu_test/rom_singles/1-len_ctr.nes",
+    apu_len_table: "apu_test/rom_singles/2-len_table.nes",
+    apu_irq_flag: "apu_test/rom_singles/3-irq_flag.nes",
+    apu_jitter: "apu_test/rom_singles/4-jitter.nes",
+    apu_len_timing: "apu_test/rom_singles/5-len_timing.nes",
+    apu_irq_flag_timing: "apu_test/rom_singles/6-irq_flag_timing.nes",
+    apu_dmc_basics: "apu_test/rom_singles/7-dmc_basics.nes",
+    apu_dmc_rates: "apu_test/rom_singles/8-dmc_rates.nes",
+    apu_reset_4015_cleared: "apu_reset/4015_cleared.nes",
+    apu_reset_4017_timing: "apu_reset/4017_timing.nes",
+    ppu_vbl_basics: "ppu_vbl_nmi/rom_singles/01-vbl_basics.nes",
+    ppu_vbl_set_time: "ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes",
+    ppu_vbl_clear_time: "ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes",
+    ppu_nmi_control: "ppu_vbl_nmi/rom_singles/04-nmi_control.nes",
+    ppu_open_bus: "ppu_open_bus/ppu_open_bus.nes",
+    cpu_instr_official: "instr_test-v5/official_only.nes",
+}
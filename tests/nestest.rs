@@ -0,0 +1,93 @@
+// Runs nestest in its automated mode and compares every instruction with
+// the log of a known good run. Neither file is in the repository, so put
+// them in tests/roms/ and run:
+//
+//     cargo test --features debug-tools -- --ignored
+//
+// Both are linked from the emulator tests page of the nesdev wiki.
+#![cfg(feature = "debug-tools")]
+
+extern crate nes;
+
+use std::fs;
+
+use nes::Nes;
+
+const ROM_PATH: &str = "tests/roms/nestest.nes";
+const LOG_PATH: &str = "tests/roms/nestest.log";
+
+// The value of a column like "A:00" or "CYC:7" in a trace line
+fn field(line: &str, name: &str) -> u64 {
+    let prefix = format!("{}:", name);
+    let value = line
+        .split_whitespace()
+        .find(|s| s.starts_with(&prefix))
+        .unwrap_or_else(|| panic!("no {} in {:?}", name, line));
+    let radix = if name == "CYC" { 10 } else { 16 };
+    u64::from_str_radix(&value[prefix.len()..], radix).unwrap()
+}
+
+fn pc(line: &str) -> u16 {
+    u16::from_str_radix(&line[..4], 16).unwrap()
+}
+
+#[test]
+#[ignore]
+fn test_nestest() {
+    let mut rom = fs::read(ROM_PATH).expect("nestest.nes in tests/roms");
+    let log = fs::read_to_string(LOG_PATH).expect("nestest.log in tests/roms");
+
+    // Point the reset vector at $C000, the entry point for automated mode
+    let reset_vector = 16 + 0x3FFC;
+    rom[reset_vector] = 0x00;
+    rom[reset_vector + 1] = 0xC0;
+
+    let mut nes = Nes::new();
+    nes.load_rom(&rom);
+
+    // Reset leaves SP and the cycle count at different values than the log
+    // starts with, so those are compared relative to the first line.
+    let first = nes.cpu.trace_line();
+    let expected_first = log.lines().next().unwrap();
+    let sp_offset = field(expected_first, "SP") as i64 - field(&first, "SP") as i64;
+    let cyc_offset = field(expected_first, "CYC") as i64 - field(&first, "CYC") as i64;
+
+    for (i, expected) in log.lines().enumerate() {
+        let actual = nes.cpu.trace_line();
+        let mismatch = |what: &str| {
+            panic!(
+                "{} differs at PC {:04X} (line {})\nexpected: {}\nactual:   {}",
+                what,
+                pc(expected),
+                i + 1,
+                expected,
+                actual
+            )
+        };
+
+        if pc(&actual) != pc(expected) {
+            mismatch("PC");
+        }
+        for name in &["A", "X", "Y"] {
+            if field(&actual, name) != field(expected, name) {
+                mismatch(name);
+            }
+        }
+        // The Break and Push bits aren't real flags
+        if field(&actual, "P") & 0xCF != field(expected, "P") & 0xCF {
+            mismatch("P");
+        }
+        if field(&actual, "SP") as i64 + sp_offset != field(expected, "SP") as i64 {
+            mismatch("SP");
+        }
+        if field(&actual, "CYC") as i64 + cyc_offset != field(expected, "CYC") as i64 {
+            mismatch("CYC");
+        }
+
+        nes.cpu.execute_next_instruction();
+    }
+
+    // The official and unofficial results are left at $02 and $03
+    assert_eq!(nes.cpu.bus.unclocked_read_byte(0x02), 0);
+    assert_eq!(nes.cpu.bus.unclocked_read_byte(0x03), 0);
+}